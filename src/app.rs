@@ -1,29 +1,105 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread::JoinHandle;
+
 use eframe::epaint::{CircleShape, RectShape};
 use egui::{
-    Color32, Context, PointerButton, Pos2, Rect, Rounding, Shape, SidePanel, Stroke, TextEdit,
-    TextStyle, Vec2, Window,
+    Button, Color32, ComboBox, Context, PointerButton, Pos2, Rect, Rounding, ScrollArea, Shape,
+    SidePanel, Slider, Stroke, TextEdit, TextStyle, Ui, Vec2, Window,
 };
 
-use crate::game::{Grid, Player, Turn, Variant};
+use crate::bitboard::Position;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::error::UT3Error;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::eval::{Heuristic, Weights};
+use crate::game::{GameStatus, Grid, Player, RuleSet};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::mate::Progress;
+use crate::mate::{MateFinder, MateResult};
+use crate::mcts::{Budget, Mcts};
+use crate::search::{plies_to_end, Limits, Searcher};
+use crate::solver::{Solution, Solver};
+use crate::tablebase::Tablebase;
+use crate::tree::{GameTree, NodeId};
+use crate::{notation, pgn};
 
 const BOX_SIZE: f32 = 60.0;
 const GRID_SIZE: f32 = 3.0 * BOX_SIZE;
 
+#[derive(Copy, Clone, PartialEq, Eq, Default)]
+enum EngineKind {
+    #[default]
+    AlphaBeta,
+    Mcts,
+}
+
 #[derive(Default)]
 struct UiState {
     notation_textbox_content: String,
+    position_textbox_content: String,
+    error: Option<String>,
+    engine: EngineKind,
+    search_depth: u32,
+    mcts_iterations: u64,
+    // What the last search found, to show under the button
+    search_summary: Option<String>,
+    mate_moves: u32,
+    mate_summary: Option<String>,
+    solve_summary: Option<String>,
+    tablebase_path: String,
+    weights_path: String,
+}
+
+// A forced win search running on another thread, so the UI keeps going meanwhile
+#[cfg(not(target_arch = "wasm32"))]
+struct MateJob {
+    stop: Arc<AtomicBool>,
+    progress: Arc<Progress>,
+    side: Player,
+    handle: JoinHandle<MateResult>,
+}
+
+// An exact solve running on another thread, like `MateJob`
+#[cfg(not(target_arch = "wasm32"))]
+struct SolveJob {
+    stop: Arc<AtomicBool>,
+    side: Player,
+    handle: JoinHandle<Option<Solution>>,
 }
 
 pub struct App {
-    board: Grid,
+    tree: GameTree,
+    searcher: Searcher,
+    mcts: Mcts,
+    #[cfg(not(target_arch = "wasm32"))]
+    mate_job: Option<MateJob>,
+    #[cfg(not(target_arch = "wasm32"))]
+    solve_job: Option<SolveJob>,
+    tablebase: Option<Arc<Tablebase>>,
     state: UiState,
 }
 
 impl Default for App {
     fn default() -> Self {
         Self {
-            board: Grid::default().with_variant(Variant::Relative),
-            state: UiState::default(),
+            tree: GameTree::new(Position::new(RuleSet::RELATIVE)),
+            // Kept small since it is allocated up front, also on the web
+            searcher: Searcher::new(1 << 16),
+            mcts: Mcts::default(),
+            #[cfg(not(target_arch = "wasm32"))]
+            mate_job: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            solve_job: None,
+            tablebase: None,
+            state: UiState {
+                search_depth: 6,
+                mcts_iterations: 10_000,
+                mate_moves: 3,
+                ..UiState::default()
+            },
         }
     }
 }
@@ -32,20 +108,370 @@ impl App {
     pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
         Default::default()
     }
+
+    fn mate_finder(&mut self, ui: &mut Ui) {
+        ui.add(Slider::new(&mut self.state.mate_moves, 1..=8).text("Moves to win in"));
+
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(job) = &self.mate_job {
+            if job.handle.is_finished() {
+                let job = self.mate_job.take().unwrap();
+                let result = job.handle.join().expect("the forced win search panicked");
+                self.state.mate_summary = Some(describe_mate(&result, job.side));
+            } else {
+                ui.label(format!(
+                    "Trying {} moves, {} positions so far",
+                    job.progress.moves.load(Ordering::Relaxed),
+                    job.progress.nodes.load(Ordering::Relaxed)
+                ));
+                if ui.button("Cancel").clicked() {
+                    job.stop.store(true, Ordering::Relaxed);
+                }
+                // Keep the progress moving even without any input
+                ui.ctx().request_repaint();
+                return;
+            }
+        }
+
+        if ui
+            .add_enabled(
+                !self.tree.grid().status().is_over(),
+                Button::new("Find forced win"),
+            )
+            .clicked()
+        {
+            let mut finder = MateFinder::new();
+            let grid = self.tree.grid().clone();
+            let side = grid.current_player();
+            let moves = self.state.mate_moves;
+            self.state.mate_summary = None;
+
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                self.mate_job = Some(MateJob {
+                    stop: finder.stop_flag(),
+                    progress: finder.progress(),
+                    side,
+                    handle: std::thread::spawn(move || finder.find(&grid, moves)),
+                });
+            }
+            // There are no threads on the web, so search there and then
+            #[cfg(target_arch = "wasm32")]
+            {
+                self.state.mate_summary = Some(describe_mate(&finder.find(&grid, moves), side));
+            }
+        }
+        if let Some(summary) = &self.state.mate_summary {
+            ScrollArea::vertical()
+                .id_source("forced win")
+                .max_height(200.0)
+                .show(ui, |ui| ui.monospace(summary));
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn weights_loader(&mut self, ui: &mut Ui) {
+        ui.add(TextEdit::singleline(&mut self.state.weights_path).hint_text("Eval weights file"));
+        if ui.button("Load eval weights").clicked() {
+            match Weights::load(self.state.weights_path.trim()) {
+                Ok(weights) => {
+                    self.searcher.set_evaluator(Heuristic::new(weights));
+                    self.mcts.evaluator = Heuristic::new(weights);
+                    self.state.error = None;
+                }
+                Err(err) => self.state.error = Some(err.to_string()),
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn tablebase_loader(&mut self, ui: &mut Ui) {
+        ui.add(TextEdit::singleline(&mut self.state.tablebase_path).hint_text("Tablebase file"));
+        if ui.button("Load tablebase").clicked() {
+            let tablebase = std::fs::File::open(self.state.tablebase_path.trim())
+                .map_err(UT3Error::from)
+                .and_then(|file| Tablebase::read(&mut std::io::BufReader::new(file)));
+            match tablebase {
+                Ok(tablebase) => {
+                    let tablebase = Arc::new(tablebase);
+                    self.searcher.set_tablebase(Some(tablebase.clone()));
+                    self.tablebase = Some(tablebase);
+                    self.state.error = None;
+                }
+                Err(err) => self.state.error = Some(err.to_string()),
+            }
+        }
+        if let Some(tablebase) = &self.tablebase {
+            ui.label(format!(
+                "{} sampled positions with up to {} empty tiles, {}",
+                tablebase.len(),
+                tablebase.max_empty(),
+                tablebase.rules()
+            ));
+        }
+    }
+
+    fn solver(&mut self, ui: &mut Ui) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(job) = &self.solve_job {
+            if job.handle.is_finished() {
+                let job = self.solve_job.take().unwrap();
+                let solution = job.handle.join().expect("the solver panicked");
+                self.state.solve_summary = Some(describe_solution(solution.as_ref(), job.side));
+            } else {
+                ui.label("Solving...");
+                if ui.button("Cancel").clicked() {
+                    job.stop.store(true, Ordering::Relaxed);
+                }
+                ui.ctx().request_repaint();
+                return;
+            }
+        }
+
+        let position = self.tree.grid().position();
+        let solvable = !position.status().is_over()
+            && position.empty_tiles().count_ones() <= MAX_SOLVE_EMPTY_TILES;
+        if ui
+            .add_enabled(solvable, Button::new("Solve exactly"))
+            .on_disabled_hover_text(format!(
+                "Only for positions with at most {MAX_SOLVE_EMPTY_TILES} empty tiles"
+            ))
+            .clicked()
+        {
+            let mut solver = Solver::new(1 << 16);
+            let grid = self.tree.grid().clone();
+            let side = grid.current_player();
+            self.state.solve_summary = None;
+
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                self.solve_job = Some(SolveJob {
+                    stop: solver.stop_flag(),
+                    side,
+                    handle: std::thread::spawn(move || solver.solve(&grid)),
+                });
+            }
+            #[cfg(target_arch = "wasm32")]
+            {
+                self.state.solve_summary =
+                    Some(describe_solution(solver.solve(&grid).as_ref(), side));
+            }
+        }
+        if let Some(summary) = &self.state.solve_summary {
+            ui.label(summary);
+        }
+    }
+}
+
+// Solving takes too long to be useful past this, and on the web it runs on the UI thread
+const MAX_SOLVE_EMPTY_TILES: u32 = 20;
+
+// Big trees aren't much use to read through, so past this size only the first move is shown
+const MAX_SHOWN_TREE: usize = 200;
+
+// `None` when the solver was cancelled
+fn describe_solution(solution: Option<&Solution>, side: Player) -> String {
+    match solution {
+        Some(solution) => {
+            let pv = solution
+                .pv
+                .iter()
+                .map(|mv| mv.to_string())
+                .collect::<Vec<_>>();
+            format!("{side}: {}\n{}", solution.outcome, pv.join(" "))
+        }
+        None => "Cancelled".to_string(),
+    }
+}
+
+fn describe_mate(result: &MateResult, side: Player) -> String {
+    match result {
+        MateResult::Win(tree) if tree.size() <= MAX_SHOWN_TREE => {
+            format!("{side} wins in {} moves:\n{tree}", tree.moves_to_win())
+        }
+        MateResult::Win(tree) => format!(
+            "{side} wins in {} moves, starting with {}",
+            tree.moves_to_win(),
+            tree.mv
+        ),
+        MateResult::NoWin => format!("{side} can't force a win"),
+        MateResult::Cancelled => "Cancelled".to_string(),
+    }
+}
+
+// Lays out the moves from `first` like PGN, with a button for each one that sets `jump` to it
+fn show_line(
+    ui: &mut Ui,
+    tree: &GameTree,
+    first: NodeId,
+    siblings: bool,
+    jump: &mut Option<NodeId>,
+) {
+    let mut id = first;
+    let mut siblings = siblings;
+    loop {
+        let node = tree.node(id).unwrap();
+        let (outer, inner) = node.coords().unwrap();
+        let text = format!("{}. {outer}/{inner}", node.turn_number());
+        if ui.selectable_label(tree.cursor() == id, text).clicked() {
+            *jump = Some(id);
+        }
+        if siblings {
+            let parent = tree.node(node.parent().unwrap()).unwrap();
+            for &sibling in &parent.children()[1..] {
+                ui.label("(");
+                show_line(ui, tree, sibling, false, jump);
+                ui.label(")");
+            }
+        }
+        match node.children().first() {
+            Some(&child) => {
+                id = child;
+                siblings = true;
+            }
+            None => return,
+        }
+    }
 }
 
 impl eframe::App for App {
     fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
         SidePanel::left("options").show(ctx, |ui| {
             ui.heading("Options");
+            let mut rules = self.tree.grid().rules();
+            ComboBox::from_label("Rules")
+                .selected_text(rules.preset_name().unwrap_or("Custom"))
+                .show_ui(ui, |ui| {
+                    for (name, preset) in RuleSet::PRESETS {
+                        ui.selectable_value(&mut rules, preset, name);
+                    }
+                });
+            if rules != self.tree.grid().rules() {
+                // Changing the rules halfway through wouldn't make sense, so start over
+                self.tree = GameTree::new(Position::new(rules));
+                self.state.notation_textbox_content.clear();
+            }
+            ui.separator();
+            ComboBox::from_label("Engine")
+                .selected_text(match self.state.engine {
+                    EngineKind::AlphaBeta => "Alpha-beta",
+                    EngineKind::Mcts => "MCTS",
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(
+                        &mut self.state.engine,
+                        EngineKind::AlphaBeta,
+                        "Alpha-beta",
+                    );
+                    ui.selectable_value(&mut self.state.engine, EngineKind::Mcts, "MCTS");
+                });
+            match self.state.engine {
+                EngineKind::AlphaBeta => {
+                    ui.add(Slider::new(&mut self.state.search_depth, 1..=10).text("Search depth"))
+                }
+                EngineKind::Mcts => ui.add(
+                    Slider::new(&mut self.state.mcts_iterations, 1000..=100_000)
+                        .logarithmic(true)
+                        .text("Iterations"),
+                ),
+            };
+            #[cfg(not(target_arch = "wasm32"))]
+            self.weights_loader(ui);
+            if ui
+                .add_enabled(
+                    !self.tree.grid().status().is_over(),
+                    Button::new("Play engine move"),
+                )
+                .clicked()
+            {
+                let grid = self.tree.grid();
+                let side = grid.current_player();
+                let (best_move, summary) = match self.state.engine {
+                    EngineKind::AlphaBeta => {
+                        let result = self
+                            .searcher
+                            .search_grid(grid, Limits::depth(self.state.search_depth));
+                        let score = match plies_to_end(result.score) {
+                            Some(plies) if result.score > 0 => {
+                                format!("{side} wins in {plies} plies")
+                            }
+                            Some(plies) => format!("{} wins in {plies} plies", side.opponent()),
+                            None => format!("{:+} for {side}", result.score),
+                        };
+                        let pv = result
+                            .pv
+                            .iter()
+                            .map(|mv| mv.to_string())
+                            .collect::<Vec<_>>();
+                        (
+                            result.best_move,
+                            format!("{score}, depth {}\n{}", result.depth, pv.join(" ")),
+                        )
+                    }
+                    EngineKind::Mcts => {
+                        let result = self
+                            .mcts
+                            .search_grid(grid, Budget::Iterations(self.state.mcts_iterations));
+                        let moves = result
+                            .moves
+                            .iter()
+                            .take(3)
+                            .map(|stats| {
+                                format!(
+                                    "{}: {} visits, {:.0}% for {side}",
+                                    stats.mv,
+                                    stats.visits,
+                                    stats.win_rate * 100.0
+                                )
+                            })
+                            .collect::<Vec<_>>();
+                        (result.best_move, moves.join("\n"))
+                    }
+                };
+                self.state.search_summary = Some(summary);
+                if let Some(mv) = best_move {
+                    self.tree
+                        .play(mv.coords())
+                        .expect("the engines only play legal moves");
+                    self.state.notation_textbox_content = self.tree.grid().to_string();
+                }
+            }
+            if let Some(summary) = &self.state.search_summary {
+                ui.label(summary);
+            }
             // [] Play full random game (ignores following options, possibly has some config)
             // [] Start game with random moves played (# moves)
             // [] Show what squares opponent will be able to use
-            // Mate in N finder (if I can make it)
+            ui.separator();
+            self.mate_finder(ui);
+            ui.separator();
+            self.solver(ui);
+            // Reading files isn't possible on the web
+            #[cfg(not(target_arch = "wasm32"))]
+            {
+                ui.separator();
+                self.tablebase_loader(ui);
+            }
         });
 
         SidePanel::right("notation").show(ctx, |ui| {
             ui.heading("Game");
+            ui.label(match self.tree.grid().status() {
+                GameStatus::Ongoing => format!("{} to move", self.tree.grid().current_player()),
+                GameStatus::Won(player) => format!("{player} wins!"),
+                GameStatus::Drawn => "Draw".to_string(),
+            });
+            if let Some(outcome) = self
+                .tablebase
+                .as_ref()
+                .and_then(|tablebase| tablebase.probe(self.tree.grid().position()))
+            {
+                ui.label(format!(
+                    "Tablebase: {} for {}",
+                    outcome,
+                    self.tree.grid().current_player()
+                ));
+            }
             ui.add(
                 TextEdit::multiline(&mut self.state.notation_textbox_content)
                     .font(TextStyle::Monospace),
@@ -53,16 +479,98 @@ impl eframe::App for App {
             if ui.button("Replay game from notation").clicked()
                 && !self.state.notation_textbox_content.is_empty()
             {
-                self.board = Grid::default();
-                for turn in self.state.notation_textbox_content.split('\n') {
-                    let turn: Turn = turn.try_into().unwrap();
-                    self.board.apply_turn(turn.coords).unwrap(); // XXX: REMOVE UNWRAPS
+                let text = &self.state.notation_textbox_content;
+                // PGN starts with its headers, which carry the rules with the game
+                let tree = if text.trim_start().starts_with('[') {
+                    pgn::parse(text).and_then(|game| GameTree::from_pgn(&game))
+                } else {
+                    let mut board = Grid::from_position(*self.tree.grid().start());
+                    notation::replay(&mut board, text).map(|()| GameTree::from_grid(&board))
+                };
+                match tree {
+                    Ok(tree) => {
+                        self.tree = tree;
+                        self.state.error = None;
+                    }
+                    Err(err) => self.state.error = Some(err.to_string()),
                 }
             }
+            if ui.button("Export as PGN").clicked() {
+                self.state.notation_textbox_content = self.tree.to_pgn().to_string();
+            }
+            ui.horizontal(|ui| {
+                let cursor = self.tree.node(self.tree.cursor()).unwrap();
+                let (has_parent, has_children) =
+                    (cursor.parent().is_some(), !cursor.children().is_empty());
+                if ui.add_enabled(has_parent, Button::new("Back")).clicked() {
+                    self.tree.back();
+                    self.state.notation_textbox_content = self.tree.grid().to_string();
+                }
+                if ui
+                    .add_enabled(has_children, Button::new("Forward"))
+                    .clicked()
+                {
+                    self.tree.forward();
+                    self.state.notation_textbox_content = self.tree.grid().to_string();
+                }
+            });
+            ui.separator();
+            let mut jump = None;
+            ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+                ui.horizontal_wrapped(|ui| {
+                    let root = self.tree.node(GameTree::ROOT).unwrap();
+                    if let Some(&first) = root.children().first() {
+                        show_line(ui, &self.tree, first, true, &mut jump);
+                    }
+                });
+            });
+            if let Some(id) = jump {
+                self.tree.jump(id);
+                self.state.notation_textbox_content = self.tree.grid().to_string();
+            }
+            ui.horizontal(|ui| {
+                let cursor = self.tree.cursor();
+                let is_root = cursor == GameTree::ROOT;
+                if ui
+                    .add_enabled(!is_root, Button::new("Promote variation"))
+                    .clicked()
+                {
+                    self.tree.promote(cursor);
+                }
+                if ui
+                    .add_enabled(!is_root, Button::new("Delete from here"))
+                    .clicked()
+                {
+                    self.tree.delete(cursor);
+                    self.state.notation_textbox_content = self.tree.grid().to_string();
+                }
+            });
+            ui.separator();
+            ui.add(
+                TextEdit::singleline(&mut self.state.position_textbox_content)
+                    .font(TextStyle::Monospace),
+            );
+            ui.horizontal(|ui| {
+                if ui.button("Show current position").clicked() {
+                    self.state.position_textbox_content = self.tree.grid().position().to_string();
+                }
+                if ui.button("Load position").clicked() {
+                    match Position::try_from(self.state.position_textbox_content.trim()) {
+                        Ok(position) => {
+                            self.tree = GameTree::new(position);
+                            self.state.notation_textbox_content.clear();
+                            self.state.error = None;
+                        }
+                        Err(err) => self.state.error = Some(err.to_string()),
+                    }
+                }
+            });
+            if let Some(error) = &self.state.error {
+                ui.colored_label(Color32::RED, error);
+            }
             // New Game
             // <Online stuff?>
             // Current game notation
-            // Way to traverse moves -> (with fancy tree?)
         });
 
         Window::new("Board")
@@ -87,7 +595,10 @@ impl eframe::App for App {
                     )
                 };
 
-                let valid_boxes = self.board.get_valid_boxes(self.board.get_track());
+                let valid_boxes = self
+                    .tree
+                    .grid()
+                    .get_valid_boxes(self.tree.grid().get_track());
 
                 for ix in 0..3 {
                     for iy in 0..3 {
@@ -111,13 +622,11 @@ impl eframe::App for App {
                                 };
 
                                 if let Some(pos) = interact_pos {
-                                    if rect.contains(pos) {
-                                        if let Ok(_) =
-                                            self.board.apply_turn((outer_coords, inner_coords))
-                                        {
-                                            self.state.notation_textbox_content =
-                                                self.board.to_string();
-                                        }
+                                    if rect.contains(pos)
+                                        && self.tree.play((outer_coords, inner_coords)).is_ok()
+                                    {
+                                        self.state.notation_textbox_content =
+                                            self.tree.grid().to_string();
                                     }
                                 }
 
@@ -144,8 +653,11 @@ impl eframe::App for App {
                                     }
                                 }
 
-                                if let Some(player) =
-                                    self.board.get_box(outer_coords).get_tile(inner_coords)
+                                if let Some(player) = self
+                                    .tree
+                                    .grid()
+                                    .get_box(outer_coords)
+                                    .get_tile(inner_coords)
                                 {
                                     match *player {
                                         Player::X => {
@@ -183,7 +695,8 @@ impl eframe::App for App {
                                     Vec2::splat(GRID_SIZE),
                                 ),
                                 rounding: Rounding::none(),
-                                fill: if let Some(player) = self.board.get_box(outer_coords).winner
+                                fill: if let Some(player) =
+                                    self.tree.grid().get_box(outer_coords).winner
                                 {
                                     match player {
                                         Player::X => Color32::from_rgba_unmultiplied(255, 0, 0, 20),
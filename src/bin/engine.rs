@@ -0,0 +1,212 @@
+//! A headless engine that speaks the text protocol in `ut3e::protocol` on stdin and stdout, for
+//! GUIs and scripts to drive.
+//!
+//! ```sh
+//! $ cargo run --release --bin ut3e-engine
+//! ut3e
+//! position startpos moves C/C C/NW
+//! go depth 8
+//! ```
+
+use std::io::{self, BufRead};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use ut3e::bitboard::{Move, Position};
+use ut3e::error::UT3Error;
+use ut3e::eval::{Evaluator, Heuristic, Weights};
+use ut3e::game::{Grid, RuleSet};
+use ut3e::nn::Network;
+use ut3e::protocol::{Command, Info, Response};
+use ut3e::search::{table_entries, Limits, Searcher};
+use ut3e::tablebase::Tablebase;
+
+const DEFAULT_HASH: usize = 16;
+const MAX_HASH: usize = 4096;
+
+type EngineSearcher = Searcher<Box<dyn Evaluator + Send>>;
+
+struct Search {
+    handle: JoinHandle<EngineSearcher>,
+    stop: Arc<AtomicBool>,
+}
+
+struct Engine {
+    // `None` while a search has it
+    searcher: Option<EngineSearcher>,
+    search: Option<Search>,
+    grid: Grid,
+    rules: RuleSet,
+    hash: usize,
+    weights: Weights,
+    // Used instead of `weights` when set
+    network: Option<Network>,
+    tablebase: Option<Arc<Tablebase>>,
+}
+
+fn send(response: Response) {
+    println!("{response}");
+}
+
+impl Engine {
+    fn new() -> Self {
+        let mut engine = Self {
+            searcher: None,
+            search: None,
+            grid: Grid::default(),
+            rules: RuleSet::default(),
+            hash: DEFAULT_HASH,
+            weights: Weights::default(),
+            network: None,
+            tablebase: None,
+        };
+        engine.rebuild_searcher();
+        engine
+    }
+
+    fn rebuild_searcher(&mut self) {
+        let evaluator: Box<dyn Evaluator + Send> = match &self.network {
+            Some(network) => Box::new(network.clone()),
+            None => Box::new(Heuristic::new(self.weights)),
+        };
+        let mut searcher = Searcher::with_evaluator(table_entries(self.hash << 20), evaluator);
+        searcher.set_tablebase(self.tablebase.clone());
+        self.searcher = Some(searcher);
+    }
+
+    // Waits for the search to finish, telling it to hurry up if `stop` is set
+    fn finish_search(&mut self, stop: bool) {
+        if let Some(search) = self.search.take() {
+            if stop {
+                search.stop.store(true, Ordering::Relaxed);
+            }
+            self.searcher = Some(search.handle.join().expect("the search panicked"));
+        }
+    }
+
+    // Returns `false` to quit
+    fn handle(&mut self, command: Command) -> Result<bool, UT3Error> {
+        match command {
+            Command::Hello => {
+                send(Response::Id {
+                    field: "name".to_string(),
+                    value: format!("ut3e {}", env!("CARGO_PKG_VERSION")),
+                });
+                for option in [
+                    format!("name Hash type spin default {DEFAULT_HASH} min 1 max {MAX_HASH}"),
+                    format!("name Rules type string default {}", RuleSet::default()),
+                    "name Weights type string default <empty>".to_string(),
+                    "name Network type string default <empty>".to_string(),
+                    "name Tablebase type string default <empty>".to_string(),
+                ] {
+                    send(Response::Option(option));
+                }
+                send(Response::HelloOk);
+            }
+            // Answered straight away like in UCI, even while searching
+            Command::IsReady => send(Response::ReadyOk),
+            Command::SetOption { name, value } => {
+                self.finish_search(true);
+                self.set_option(&name, &value)?;
+            }
+            Command::NewGame => {
+                self.finish_search(true);
+                self.searcher.as_mut().unwrap().clear();
+            }
+            Command::Position { start, moves } => {
+                self.finish_search(true);
+                self.grid = self.play(start, &moves)?;
+            }
+            Command::Go(limits) => {
+                self.finish_search(true);
+                self.go(limits);
+            }
+            Command::Stop => self.finish_search(true),
+            Command::Quit => {
+                self.finish_search(true);
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn set_option(&mut self, name: &str, value: &str) -> Result<(), UT3Error> {
+        let path = (value != "<empty>" && !value.is_empty()).then_some(value);
+        match name {
+            "Hash" => {
+                let hash: usize = value
+                    .parse()
+                    .map_err(|_| UT3Error::UnexpectedToken(value.to_string()))?;
+                self.hash = hash.clamp(1, MAX_HASH);
+            }
+            "Rules" => self.rules = RuleSet::try_from(value)?,
+            "Weights" => self.weights = path.map_or(Ok(Weights::default()), Weights::load)?,
+            "Network" => self.network = path.map(Network::load).transpose()?,
+            "Tablebase" => {
+                self.tablebase = match path {
+                    Some(path) => Some(Arc::new(Tablebase::read(&mut io::BufReader::new(
+                        std::fs::File::open(path)?,
+                    ))?)),
+                    None => None,
+                }
+            }
+            _ => return Err(UT3Error::UnexpectedToken(name.to_string())),
+        }
+        self.rebuild_searcher();
+        Ok(())
+    }
+
+    // Checks the moves are legal by playing them on a `Grid`
+    fn play(&self, start: Option<Position>, moves: &[Move]) -> Result<Grid, UT3Error> {
+        let mut grid = Grid::from_position(start.unwrap_or_else(|| Position::new(self.rules)));
+        for mv in moves {
+            grid.apply_turn(mv.coords())?;
+        }
+        Ok(grid)
+    }
+
+    fn go(&mut self, limits: Limits) {
+        let mut searcher = self.searcher.take().unwrap();
+        let stop = searcher.stop_flag();
+        // A `stop` that came in after the last search had already finished
+        stop.store(false, Ordering::Relaxed);
+        let position = *self.grid.position();
+        let handle = thread::spawn(move || {
+            let start = Instant::now();
+            let result = searcher.search_with_info(&position, limits, |result| {
+                send(Response::Info(Info {
+                    depth: result.depth,
+                    score: result.score,
+                    nodes: result.nodes,
+                    time: start.elapsed(),
+                    pv: result.pv.clone(),
+                }))
+            });
+            send(Response::BestMove(result.best_move));
+            searcher
+        });
+        self.search = Some(Search { handle, stop });
+    }
+}
+
+fn main() {
+    let mut engine = Engine::new();
+    for line in io::stdin().lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let result = Command::try_from(line.as_str()).and_then(|command| engine.handle(command));
+        match result {
+            Ok(true) => (),
+            Ok(false) => return,
+            Err(err) => send(Response::Message(format!("error: {err}"))),
+        }
+    }
+    engine.finish_search(false);
+}
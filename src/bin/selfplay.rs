@@ -0,0 +1,177 @@
+//! Plays games for training data, see `ut3e::selfplay` for the file format. Running it again on
+//! the same file carries on where it left off, playing only the games that aren't in it yet.
+//!
+//! ```sh
+//! $ cargo run --release --bin ut3e-selfplay -- games.ut3s --games 10000 --threads 8
+//! $ cargo run --release --bin ut3e-selfplay -- games.ut3s --engine alphabeta:depth=6 \
+//!     --engine mcts:iterations=20000
+//! ```
+
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::process::ExitCode;
+use std::thread;
+
+use ut3e::engine;
+use ut3e::error::UT3Error;
+use ut3e::eval::Heuristic;
+use ut3e::game::RuleSet;
+use ut3e::mcts::{Budget, MctsConfig, Playout};
+use ut3e::nn::Network;
+use ut3e::selfplay::{finished_games, play_games, write_header, SelfPlayConfig, SelfPlayer};
+
+const USAGE: &str = "usage: ut3e-selfplay <output file> [--games <count>] [--threads <count>] \
+                     [--rules <rules>] [--seed <seed>] [--opening-plies <plies>] \
+                     [--iterations <count>] [--sample-plies <plies>] \
+                     [--playout random|heuristic|evaluation] [--network <file>] \
+                     [--engine <spec> --engine <spec>]";
+
+struct Options {
+    output: String,
+    games: u64,
+    threads: usize,
+    config: SelfPlayConfig,
+    iterations: u64,
+    sample_plies: u32,
+    playout: Playout,
+    // Evaluates positions, and guides the search with its policy
+    network: Option<String>,
+    engines: Vec<String>,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut options = Options {
+        output: String::new(),
+        games: 1000,
+        threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+        config: SelfPlayConfig {
+            rules: RuleSet::default(),
+            opening_plies: 2,
+            seed: 0,
+        },
+        iterations: 2000,
+        sample_plies: 12,
+        playout: Playout::Heuristic,
+        network: None,
+        engines: Vec::new(),
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for `{arg}`"));
+        match arg.as_str() {
+            "--games" => options.games = value()?.parse().map_err(|_| "invalid --games")?,
+            "--threads" => options.threads = value()?.parse().map_err(|_| "invalid --threads")?,
+            "--rules" => {
+                options.config.rules =
+                    RuleSet::try_from(value()?.as_str()).map_err(|e| e.to_string())?
+            }
+            "--seed" => options.config.seed = value()?.parse().map_err(|_| "invalid --seed")?,
+            "--opening-plies" => {
+                options.config.opening_plies =
+                    value()?.parse().map_err(|_| "invalid --opening-plies")?
+            }
+            "--iterations" => {
+                options.iterations = value()?.parse().map_err(|_| "invalid --iterations")?
+            }
+            "--sample-plies" => {
+                options.sample_plies = value()?.parse().map_err(|_| "invalid --sample-plies")?
+            }
+            "--playout" => {
+                options.playout = match value()?.as_str() {
+                    "random" => Playout::Random,
+                    "heuristic" => Playout::Heuristic,
+                    "evaluation" => Playout::Evaluation,
+                    _ => return Err("invalid --playout".to_string()),
+                }
+            }
+            "--network" => options.network = Some(value()?),
+            "--engine" => options.engines.push(value()?),
+            _ if options.output.is_empty() && !arg.starts_with("--") => options.output = arg,
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+    if options.output.is_empty() {
+        return Err("missing output file".to_string());
+    }
+    if !options.engines.is_empty() && options.engines.len() != 2 {
+        return Err("either no engines or two are needed".to_string());
+    }
+    options.threads = options.threads.max(1);
+    Ok(options)
+}
+
+fn self_player(options: &Options) -> Result<SelfPlayer, UT3Error> {
+    Ok(match options.engines.as_slice() {
+        [x, o] => SelfPlayer::Engines([engine::from_spec(x)?, engine::from_spec(o)?]),
+        _ => SelfPlayer::Mcts {
+            config: MctsConfig {
+                playout: options.playout,
+                priors: options.network.is_some(),
+                ..MctsConfig::default()
+            },
+            evaluator: match &options.network {
+                Some(path) => Box::new(Network::load(path)?),
+                None => Box::new(Heuristic::default()),
+            },
+            budget: Budget::Iterations(options.iterations),
+            sample_plies: options.sample_plies,
+        },
+    })
+}
+
+fn run(options: &Options) -> Result<(), UT3Error> {
+    let rules = options.config.rules;
+    let finished = if std::path::Path::new(&options.output).exists() {
+        finished_games(&options.output, rules)?
+    } else {
+        write_header(&mut File::create(&options.output)?, rules)?;
+        HashSet::new()
+    };
+    let games = (0..options.games)
+        .filter(|game| !finished.contains(game))
+        .collect::<Vec<_>>();
+    if !finished.is_empty() {
+        println!("{} games already played", finished.len());
+    }
+
+    let mut output = BufWriter::new(OpenOptions::new().append(true).open(&options.output)?);
+    let mut played = 0;
+    let mut positions = 0;
+    play_games(
+        &games,
+        options.threads,
+        &options.config,
+        || self_player(options),
+        |records| {
+            for record in &records {
+                record.write(&mut output)?;
+            }
+            output.flush()?;
+            played += 1;
+            positions += records.len();
+            if played % 100 == 0 || played == games.len() {
+                println!("{played}/{} games, {positions} positions", games.len());
+            }
+            Ok(())
+        },
+    )
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Generates an endgame tablebase from the late positions of random games.
+//!
+//! ```sh
+//! $ cargo run --release --bin ut3e-tablebase -- endgames.ut3t --rules relative --empty 14 --games 200
+//! ```
+//!
+//! The table is sampled: it only has the positions that can be reached from the `--games` random
+//! positions with `--empty` empty tiles, not every such position. More games cover more of them.
+
+use std::fs::File;
+use std::io::BufWriter;
+use std::process::ExitCode;
+
+use ut3e::bitboard::Position;
+use ut3e::error::UT3Error;
+use ut3e::game::RuleSet;
+use ut3e::rng::Rng;
+use ut3e::tablebase::Tablebase;
+
+const USAGE: &str = "usage: ut3e-tablebase <output file> [--rules <rules>] [--empty <tiles>] \
+                     [--games <count>] [--seed <seed>]";
+
+struct Options {
+    output: String,
+    rules: RuleSet,
+    max_empty: u32,
+    games: u32,
+    seed: u64,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut options = Options {
+        output: String::new(),
+        rules: RuleSet::default(),
+        max_empty: 12,
+        games: 100,
+        seed: 0,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for `{arg}`"));
+        match arg.as_str() {
+            "--rules" => {
+                options.rules = RuleSet::try_from(value()?.as_str()).map_err(|e| e.to_string())?
+            }
+            "--empty" => options.max_empty = value()?.parse().map_err(|_| "invalid --empty")?,
+            "--games" => options.games = value()?.parse().map_err(|_| "invalid --games")?,
+            "--seed" => options.seed = value()?.parse().map_err(|_| "invalid --seed")?,
+            _ if options.output.is_empty() && !arg.starts_with("--") => options.output = arg,
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+    if options.output.is_empty() {
+        return Err("missing output file".to_string());
+    }
+    Ok(options)
+}
+
+// Plays random moves until there are only `max_empty` empty tiles left, starting over if the
+// game ends first
+fn random_root(rules: RuleSet, max_empty: u32, rng: &mut Rng) -> Position {
+    loop {
+        let mut position = Position::new(rules);
+        while !position.status().is_over() && position.empty_tiles().count_ones() > max_empty {
+            let mv = rng.choose(position.legal_moves()).unwrap();
+            position.play(mv);
+        }
+        if !position.status().is_over() {
+            return position;
+        }
+    }
+}
+
+fn run(options: &Options) -> Result<(), UT3Error> {
+    let mut rng = Rng::new(options.seed);
+    let roots = (0..options.games)
+        .map(|_| random_root(options.rules, options.max_empty, &mut rng))
+        .collect::<Vec<_>>();
+
+    let tablebase = Tablebase::generate(&roots, options.max_empty)?;
+    tablebase.write(&mut BufWriter::new(File::create(&options.output)?))?;
+    println!("wrote {} positions to {}", tablebase.len(), options.output);
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Plays engines against each other and reports how they did. Every pair of engines plays
+//! `--games` games, with each random opening played twice so both get to be X.
+//!
+//! ```sh
+//! $ cargo run --release --bin ut3e-tournament -- --engine alphabeta:depth=6 \
+//!     --engine mcts:iterations=20000 --games 200 --pgn games.pgn --sprt 0,10
+//! ```
+//!
+//! See `ut3e::engine::from_spec` for how engines are described.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::process::ExitCode;
+
+use ut3e::engine::{self, Engine};
+use ut3e::error::UT3Error;
+use ut3e::game::{Grid, Player, RuleSet};
+use ut3e::rng::Rng;
+use ut3e::tournament::{play_game, random_opening, Score};
+
+const USAGE: &str = "usage: ut3e-tournament --engine <spec> --engine <spec> [--engine <spec>...] \
+                     [--games <count>] [--opening-plies <plies>] [--rules <rules>] \
+                     [--seed <seed>] [--pgn <file>] [--sprt <elo0>,<elo1>] \
+                     [--alpha <probability>] [--beta <probability>]";
+
+struct Options {
+    engines: Vec<String>,
+    games: u32,
+    opening_plies: u32,
+    rules: RuleSet,
+    seed: u64,
+    pgn: Option<String>,
+    sprt: Option<(f64, f64)>,
+    alpha: f64,
+    beta: f64,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut options = Options {
+        engines: Vec::new(),
+        games: 100,
+        opening_plies: 4,
+        rules: RuleSet::default(),
+        seed: 0,
+        pgn: None,
+        sprt: None,
+        alpha: 0.05,
+        beta: 0.05,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for `{arg}`"));
+        match arg.as_str() {
+            "--engine" => options.engines.push(value()?),
+            "--games" => options.games = value()?.parse().map_err(|_| "invalid --games")?,
+            "--opening-plies" => {
+                options.opening_plies = value()?.parse().map_err(|_| "invalid --opening-plies")?
+            }
+            "--rules" => {
+                options.rules = RuleSet::try_from(value()?.as_str()).map_err(|e| e.to_string())?
+            }
+            "--seed" => options.seed = value()?.parse().map_err(|_| "invalid --seed")?,
+            "--pgn" => options.pgn = Some(value()?),
+            "--sprt" => {
+                let value = value()?;
+                let (elo0, elo1) = value
+                    .split_once(',')
+                    .and_then(|(elo0, elo1)| Some((elo0.parse().ok()?, elo1.parse().ok()?)))
+                    .ok_or("invalid --sprt")?;
+                options.sprt = Some((elo0, elo1));
+            }
+            "--alpha" => options.alpha = value()?.parse().map_err(|_| "invalid --alpha")?,
+            "--beta" => options.beta = value()?.parse().map_err(|_| "invalid --beta")?,
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+    if options.engines.len() < 2 {
+        return Err("at least two engines are needed".to_string());
+    }
+    Ok(options)
+}
+
+fn report(first: &str, second: &str, score: Score, options: &Options) {
+    let (elo, margin) = score.elo();
+    println!(
+        "{first} vs {second}: +{} ={} -{} ({:.1}%), Elo {elo:+.1} ± {margin:.1}",
+        score.wins,
+        score.draws,
+        score.losses,
+        100.0 * score.mean(),
+    );
+    if let Some((elo0, elo1)) = options.sprt {
+        let sprt = score.sprt(elo0, elo1, options.alpha, options.beta);
+        let result = match sprt.result {
+            Some(true) => "H1 accepted",
+            Some(false) => "H0 accepted",
+            None => "inconclusive",
+        };
+        println!(
+            "SPRT ({elo0}, {elo1}): LLR {:.2} [{:.2}, {:.2}], {result}",
+            sprt.llr, sprt.lower, sprt.upper
+        );
+    }
+}
+
+fn run(options: &Options) -> Result<(), UT3Error> {
+    let mut engines = options
+        .engines
+        .iter()
+        .map(|spec| engine::from_spec(spec))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut pgn = match &options.pgn {
+        Some(path) => Some(BufWriter::new(File::create(path)?)),
+        None => None,
+    };
+    let mut rng = Rng::new(options.seed);
+    let mut round = 0;
+
+    for first in 0..engines.len() {
+        for second in first + 1..engines.len() {
+            let (head, tail) = engines.split_at_mut(second);
+            let (a, b): (&mut dyn Engine, &mut dyn Engine) =
+                (head[first].as_mut(), tail[0].as_mut());
+            let mut score = Score::default();
+            let mut opening = Grid::default();
+
+            for game_idx in 0..options.games {
+                // Each opening is played twice, with the engines swapping sides
+                let a_side = if game_idx % 2 == 0 {
+                    Player::X
+                } else {
+                    Player::O
+                };
+                if a_side == Player::X {
+                    opening = random_opening(options.rules, options.opening_plies, &mut rng);
+                }
+                let mut game = match a_side {
+                    Player::X => play_game([a, b], &opening)?,
+                    Player::O => play_game([b, a], &opening)?,
+                };
+                round += 1;
+                score.add(game.result, a_side);
+
+                game.headers.splice(
+                    0..0,
+                    [
+                        ("Event".to_string(), "ut3e tournament".to_string()),
+                        ("Round".to_string(), round.to_string()),
+                    ],
+                );
+                println!(
+                    "Game {round}: {} vs {}, {}",
+                    game.header("X").unwrap(),
+                    game.header("O").unwrap(),
+                    game.result
+                );
+                if let Some(pgn) = &mut pgn {
+                    writeln!(pgn, "{game}")?;
+                    pgn.flush()?;
+                }
+
+                // Only stop early between two engines, since otherwise the pairs would play
+                // different numbers of games, and only once both games of an opening are in
+                let decided = options.sprt.is_some_and(|(elo0, elo1)| {
+                    options.engines.len() == 2
+                        && a_side == Player::O
+                        && score
+                            .sprt(elo0, elo1, options.alpha, options.beta)
+                            .result
+                            .is_some()
+                });
+                if decided {
+                    break;
+                }
+            }
+
+            println!();
+            report(a.name(), b.name(), score, options);
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,419 @@
+//! Grows a network by reinforcement learning. Each generation plays self-play games with the best
+//! network so far, trains the latest network on the most recent games, and plays it against the
+//! best to decide whether it takes over.
+//!
+//! ```sh
+//! $ cargo run --release --bin ut3e-train -- runs/relative --generations 20 --threads 8
+//! $ cargo run --release --bin ut3e-train -- runs/absolute --rules absolute
+//! ```
+//!
+//! Everything goes in the run's directory, and running it again carries on from the last finished
+//! generation:
+//!
+//! - `gen-NNNN.ut3s`: the self-play games of generation `NNNN`, see `ut3e::selfplay`
+//! - `gen-NNNN.ut3n`: the network trained in generation `NNNN`, see `ut3e::nn`
+//! - `best.ut3n`: the network that won its way in most recently. Until there is one, self-play
+//!   uses MCTS with heuristic playouts.
+//! - `metrics.csv`: a line for each generation
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use ut3e::engine::{Engine, MonteCarlo};
+use ut3e::error::UT3Error;
+use ut3e::eval::{Evaluator, Heuristic};
+use ut3e::game::{Player, RuleSet};
+use ut3e::mcts::{Budget, Mcts, MctsConfig, Playout};
+use ut3e::nn::{Network, MAX_LAYER_SIZE};
+use ut3e::rng::Rng;
+use ut3e::selfplay::{
+    finished_games, game_seed, play_games, read_all, read_header, write_header, SelfPlayConfig,
+    SelfPlayer,
+};
+use ut3e::tournament::{play_game, random_opening, Score};
+use ut3e::train::{Sample, TrainConfig, Trainer};
+
+const USAGE: &str = "usage: ut3e-train <directory> [--rules <rules>] [--generations <count>] \
+                     [--games <count>] [--threads <count>] [--iterations <count>] \
+                     [--opening-plies <plies>] [--sample-plies <plies>] [--hidden <sizes>] \
+                     [--window <generations>] [--epochs <count>] [--batch-size <count>] \
+                     [--learning-rate <rate>] [--gate-games <count>] [--gate-score <score>] \
+                     [--seed <seed>]";
+
+const METRICS_HEADER: &str = "generation,positions,samples,value_loss,policy_loss,wins,draws,\
+                              losses,score,elo,accepted";
+
+struct Options {
+    dir: PathBuf,
+    rules: RuleSet,
+    generations: u32,
+    games: u64,
+    threads: usize,
+    iterations: u64,
+    opening_plies: u32,
+    sample_plies: u32,
+    hidden: Vec<usize>,
+    window: u32,
+    epochs: u32,
+    train: TrainConfig,
+    gate_games: u32,
+    // The share of the gating games the new network needs to take over
+    gate_score: f64,
+    seed: u64,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut options = Options {
+        dir: PathBuf::new(),
+        rules: RuleSet::default(),
+        generations: 10,
+        games: 500,
+        threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+        iterations: 800,
+        opening_plies: 2,
+        sample_plies: 12,
+        hidden: vec![128, 64],
+        window: 4,
+        epochs: 4,
+        train: TrainConfig::default(),
+        gate_games: 40,
+        gate_score: 0.55,
+        seed: 0,
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for `{arg}`"));
+        match arg.as_str() {
+            "--rules" => {
+                options.rules = RuleSet::try_from(value()?.as_str()).map_err(|e| e.to_string())?
+            }
+            "--generations" => {
+                options.generations = value()?.parse().map_err(|_| "invalid --generations")?
+            }
+            "--games" => options.games = value()?.parse().map_err(|_| "invalid --games")?,
+            "--threads" => options.threads = value()?.parse().map_err(|_| "invalid --threads")?,
+            "--iterations" => {
+                options.iterations = value()?.parse().map_err(|_| "invalid --iterations")?
+            }
+            "--opening-plies" => {
+                options.opening_plies = value()?.parse().map_err(|_| "invalid --opening-plies")?
+            }
+            "--sample-plies" => {
+                options.sample_plies = value()?.parse().map_err(|_| "invalid --sample-plies")?
+            }
+            "--hidden" => {
+                options.hidden = value()?
+                    .split(',')
+                    .map(|size| {
+                        size.parse()
+                            .ok()
+                            .filter(|&size| size > 0 && size <= MAX_LAYER_SIZE)
+                    })
+                    .collect::<Option<_>>()
+                    .ok_or("invalid --hidden")?
+            }
+            "--window" => options.window = value()?.parse().map_err(|_| "invalid --window")?,
+            "--epochs" => options.epochs = value()?.parse().map_err(|_| "invalid --epochs")?,
+            "--batch-size" => {
+                options.train.batch_size = value()?.parse().map_err(|_| "invalid --batch-size")?
+            }
+            "--learning-rate" => {
+                options.train.learning_rate =
+                    value()?.parse().map_err(|_| "invalid --learning-rate")?
+            }
+            "--gate-games" => {
+                options.gate_games = value()?.parse().map_err(|_| "invalid --gate-games")?
+            }
+            "--gate-score" => {
+                options.gate_score = value()?.parse().map_err(|_| "invalid --gate-score")?
+            }
+            "--seed" => options.seed = value()?.parse().map_err(|_| "invalid --seed")?,
+            _ if options.dir.as_os_str().is_empty() && !arg.starts_with("--") => {
+                options.dir = arg.into()
+            }
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+    if options.dir.as_os_str().is_empty() {
+        return Err("missing directory".to_string());
+    }
+    options.threads = options.threads.max(1);
+    options.window = options.window.max(1);
+    Ok(options)
+}
+
+fn generation_path(dir: &Path, generation: u32, extension: &str) -> PathBuf {
+    dir.join(format!("gen-{generation:04}.{extension}"))
+}
+
+// Writes to a temporary file first, so a checkpoint is never left half written
+fn save(network: &Network, path: &Path) -> Result<(), UT3Error> {
+    let temp = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temp)?);
+    network.write(&mut writer)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(temp, path)?;
+    Ok(())
+}
+
+fn load_if_exists(path: &Path) -> Result<Option<Network>, UT3Error> {
+    match path.exists() {
+        true => Network::load(path).map(Some),
+        false => Ok(None),
+    }
+}
+
+// The number of the last generation in the metrics file, or 0 if there isn't one
+fn finished_generations(path: &Path) -> Result<u32, UT3Error> {
+    if !path.exists() {
+        return Ok(0);
+    }
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .skip(1)
+        .filter_map(|line| line.split(',').next()?.parse().ok())
+        .max()
+        .unwrap_or(0))
+}
+
+// MCTS guided by `network`, or with heuristic playouts without one
+fn mcts(network: Option<&Network>, seed: u64) -> (MctsConfig, Box<dyn Evaluator>) {
+    match network {
+        Some(network) => (
+            MctsConfig {
+                playout: Playout::Evaluation,
+                priors: network.has_policy(),
+                seed,
+                ..MctsConfig::default()
+            },
+            Box::new(network.clone()),
+        ),
+        None => (
+            MctsConfig {
+                seed,
+                ..MctsConfig::default()
+            },
+            Box::new(Heuristic::default()),
+        ),
+    }
+}
+
+fn self_play(
+    options: &Options,
+    generation: u32,
+    best: Option<&Network>,
+) -> Result<usize, UT3Error> {
+    let path = generation_path(&options.dir, generation, "ut3s");
+    let finished = if path.exists() {
+        finished_games(&path, options.rules)?
+    } else {
+        write_header(&mut File::create(&path)?, options.rules)?;
+        Default::default()
+    };
+    let games = (0..options.games)
+        .filter(|game| !finished.contains(game))
+        .collect::<Vec<_>>();
+    let config = SelfPlayConfig {
+        rules: options.rules,
+        opening_plies: options.opening_plies,
+        seed: game_seed(options.seed, generation as u64),
+    };
+
+    let mut output = BufWriter::new(OpenOptions::new().append(true).open(&path)?);
+    let mut played = 0;
+    play_games(
+        &games,
+        options.threads,
+        &config,
+        || {
+            let (config, evaluator) = mcts(best, 0);
+            Ok(SelfPlayer::Mcts {
+                config,
+                evaluator,
+                budget: Budget::Iterations(options.iterations),
+                sample_plies: options.sample_plies,
+            })
+        },
+        |records| {
+            for record in &records {
+                record.write(&mut output)?;
+            }
+            output.flush()?;
+            played += 1;
+            if played % 100 == 0 || played == games.len() {
+                println!("  self-play: {played}/{} games", games.len());
+            }
+            Ok(())
+        },
+    )?;
+
+    let (_, records) = read_all(&mut BufReader::new(File::open(&path)?))?;
+    Ok(records.len())
+}
+
+// The positions from the last `window` generations up to `generation`
+fn samples(options: &Options, generation: u32) -> Result<Vec<Sample>, UT3Error> {
+    let mut samples = Vec::new();
+    for generation in generation.saturating_sub(options.window - 1).max(1)..=generation {
+        let path = generation_path(&options.dir, generation, "ut3s");
+        let (rules, records) = read_all(&mut BufReader::new(File::open(&path)?))?;
+        if rules != options.rules {
+            return Err(UT3Error::InvalidFile(format!(
+                "`{}` has games with other rules",
+                path.display()
+            )));
+        }
+        samples.extend(records.iter().map(Sample::from));
+    }
+    Ok(samples)
+}
+
+// Plays `candidate` against `best`, each opening twice with the sides swapped, returning the
+// candidate's score
+fn gate(
+    options: &Options,
+    generation: u32,
+    candidate: &Network,
+    best: Option<&Network>,
+) -> Result<Score, UT3Error> {
+    let seed = game_seed(options.seed ^ 0x6a7e, generation as u64);
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..options.threads {
+            let sender = sender.clone();
+            let next = &next;
+            scope.spawn(move || {
+                let engine = |name: &str, network: Option<&Network>, seed| {
+                    let (config, evaluator) = mcts(network, seed);
+                    let mcts = Mcts::with_evaluator(config, evaluator);
+                    MonteCarlo::new(name, mcts, Budget::Iterations(options.iterations))
+                };
+                let mut new = engine("candidate", Some(candidate), seed);
+                let mut old = engine("best", best, seed.wrapping_add(1));
+                loop {
+                    let game = next.fetch_add(1, Ordering::Relaxed) as u32;
+                    if game >= options.gate_games {
+                        return;
+                    }
+                    let mut rng = Rng::new(game_seed(seed, (game / 2) as u64));
+                    let opening = random_opening(options.rules, options.opening_plies, &mut rng);
+                    let (side, engines): (_, [&mut dyn Engine; 2]) = match game % 2 {
+                        0 => (Player::X, [&mut new, &mut old]),
+                        _ => (Player::O, [&mut old, &mut new]),
+                    };
+                    let result = play_game(engines, &opening).map(|game| (game.result, side));
+                    let failed = result.is_err();
+                    if sender.send(result).is_err() || failed {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let mut score = Score::default();
+        for result in receiver {
+            let (result, side) = result?;
+            score.add(result, side);
+        }
+        Ok(score)
+    })
+}
+
+fn run(options: &Options) -> Result<(), UT3Error> {
+    fs::create_dir_all(&options.dir)?;
+    let metrics_path = options.dir.join("metrics.csv");
+    let best_path = options.dir.join("best.ut3n");
+    let first = finished_generations(&metrics_path)? + 1;
+    if first == 1 {
+        File::create(&metrics_path)?.write_all(format!("{METRICS_HEADER}\n").as_bytes())?;
+    }
+
+    if first > 1 {
+        let path = generation_path(&options.dir, first - 1, "ut3s");
+        if read_header(&mut File::open(&path)?)? != options.rules {
+            return Err(UT3Error::InvalidFile(format!(
+                "`{}` has games with other rules",
+                path.display()
+            )));
+        }
+    }
+
+    let mut best = load_if_exists(&best_path)?;
+    let mut latest = match first {
+        1 => Network::new(&options.hidden, true, &mut Rng::new(options.seed)),
+        _ => Network::load(generation_path(&options.dir, first - 1, "ut3n"))?,
+    };
+    let mut rng = Rng::new(game_seed(options.seed, first as u64));
+
+    for generation in first..=options.generations {
+        println!("generation {generation}");
+        let positions = self_play(options, generation, best.as_ref())?;
+
+        let samples = samples(options, generation)?;
+        let mut trainer = Trainer::new(&latest, options.train);
+        let mut losses = Default::default();
+        for epoch in 1..=options.epochs {
+            losses = trainer.epoch(&mut latest, &samples, &mut rng);
+            println!(
+                "  epoch {epoch}: value loss {:.4}, policy loss {:.4}",
+                losses.value, losses.policy
+            );
+        }
+        save(&latest, &generation_path(&options.dir, generation, "ut3n"))?;
+
+        let score = gate(options, generation, &latest, best.as_ref())?;
+        let accepted = score.mean() >= options.gate_score;
+        let (elo, margin) = score.elo();
+        println!(
+            "  gating: +{} ={} -{}, Elo {elo:+.1} ± {margin:.1}, {}",
+            score.wins,
+            score.draws,
+            score.losses,
+            if accepted { "accepted" } else { "rejected" }
+        );
+        if accepted {
+            save(&latest, &best_path)?;
+            best = Some(latest.clone());
+        }
+
+        let mut metrics = OpenOptions::new().append(true).open(&metrics_path)?;
+        writeln!(
+            metrics,
+            "{generation},{positions},{},{:.6},{:.6},{},{},{},{:.4},{elo:.1},{accepted}",
+            samples.len(),
+            losses.value,
+            losses.policy,
+            score.wins,
+            score.draws,
+            score.losses,
+            score.mean(),
+        )?;
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
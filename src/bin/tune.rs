@@ -0,0 +1,159 @@
+//! Tunes the heuristic's weights on finished games, see `ut3e::tune`, and writes them as a weights
+//! file for `ut3e-engine`'s `Weights` option or an engine spec's `weights=`.
+//!
+//! ```sh
+//! $ cargo run --release --bin ut3e-tune -- games.pgn --rules relative --output relative.weights
+//! $ cargo run --release --bin ut3e-tune -- games.ut3s --weights relative.weights --step 4
+//! ```
+//!
+//! Games can come from PGN files or self-play files (`.ut3s`). Only games played under `--rules`
+//! are used, and only their positions after the first `--skip-plies` (4 by default).
+
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::process::ExitCode;
+use std::thread;
+
+use ut3e::error::UT3Error;
+use ut3e::eval::Weights;
+use ut3e::game::RuleSet;
+use ut3e::pgn;
+use ut3e::selfplay::read_all;
+use ut3e::tune::{error, game_positions, tune, with_rules, TuneConfig, TuningPosition};
+
+const USAGE: &str = "usage: ut3e-tune <games file>... [--rules <rules>] [--weights <file>] \
+                     [--output <file>] [--skip-plies <plies>] [--step <step>] \
+                     [--passes <count>] [--threads <count>]";
+
+struct Options {
+    files: Vec<String>,
+    rules: RuleSet,
+    weights: Option<String>,
+    output: Option<String>,
+    skip_plies: usize,
+    config: TuneConfig,
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut args = std::env::args().skip(1);
+    let mut options = Options {
+        files: Vec::new(),
+        rules: RuleSet::default(),
+        weights: None,
+        output: None,
+        skip_plies: 4,
+        config: TuneConfig {
+            threads: thread::available_parallelism().map_or(1, |threads| threads.get()),
+            ..TuneConfig::default()
+        },
+    };
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("missing value for `{arg}`"));
+        match arg.as_str() {
+            "--rules" => {
+                options.rules = RuleSet::try_from(value()?.as_str()).map_err(|e| e.to_string())?
+            }
+            "--weights" => options.weights = Some(value()?),
+            "--output" => options.output = Some(value()?),
+            "--skip-plies" => {
+                options.skip_plies = value()?.parse().map_err(|_| "invalid --skip-plies")?
+            }
+            "--step" => options.config.step = value()?.parse().map_err(|_| "invalid --step")?,
+            "--passes" => {
+                options.config.passes = value()?.parse().map_err(|_| "invalid --passes")?
+            }
+            "--threads" => {
+                options.config.threads = value()?.parse().map_err(|_| "invalid --threads")?
+            }
+            _ if !arg.starts_with("--") => options.files.push(arg),
+            _ => return Err(format!("unexpected argument `{arg}`")),
+        }
+    }
+    if options.files.is_empty() {
+        return Err("missing games file".to_string());
+    }
+    Ok(options)
+}
+
+// Every position in `path` with a finished game, whatever its rules
+fn read_positions(path: &str, skip_plies: usize) -> Result<Vec<TuningPosition>, UT3Error> {
+    if path.ends_with(".ut3s") {
+        // Self-play games start on an empty grid, so a record's ply is how many tiles are taken
+        let (_, records) = read_all(&mut BufReader::new(File::open(path)?))?;
+        return Ok(records
+            .iter()
+            .filter(|record| 81 - record.position.empty_tiles().count_ones() as usize >= skip_plies)
+            .map(TuningPosition::from)
+            .collect());
+    }
+    let mut positions = Vec::new();
+    for game in pgn::parse_all(&fs::read_to_string(path)?)? {
+        positions.extend(game_positions(&game, skip_plies)?);
+    }
+    Ok(positions)
+}
+
+fn run(options: &Options) -> Result<(), UT3Error> {
+    let mut positions = Vec::new();
+    for path in &options.files {
+        positions.extend(read_positions(path, options.skip_plies)?);
+    }
+    let total = positions.len();
+    let positions = with_rules(positions, options.rules);
+    println!(
+        "{} positions with rules {}, {} skipped",
+        positions.len(),
+        options.rules,
+        total - positions.len()
+    );
+    if positions.is_empty() {
+        return Err(UT3Error::InvalidFile(
+            "no finished games to tune on".to_string(),
+        ));
+    }
+
+    let weights = match &options.weights {
+        Some(path) => Weights::load(path)?,
+        None => Weights::default(),
+    };
+    println!(
+        "starting error {:.6}",
+        error(weights, &positions, options.config.threads)
+    );
+    let tuned = tune(weights, &positions, &options.config, |progress| {
+        println!(
+            "pass {}: step {}, error {:.6}",
+            progress.pass, progress.step, progress.error
+        );
+    });
+    let final_error = error(tuned, &positions, options.config.threads);
+
+    let file = format!(
+        "# Tuned for {} on {} positions, error {final_error:.6}\n{tuned}",
+        options.rules,
+        positions.len()
+    );
+    match &options.output {
+        Some(path) => fs::write(path, file)?,
+        None => print!("{file}"),
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let options = match parse_args() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(&options) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,672 @@
+use std::fmt;
+
+use crate::error::UT3Error;
+use crate::game::{Direction, GameStatus, Player, RuleSet, Variant};
+use crate::zobrist::{self, KEYS};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+// Tiles are numbered `9 * box + tile`, using the `Direction` indices, so each box is 9 consecutive
+// bits of an 81 bit mask and its tiles line up with the boxes of the grid in a `u16`.
+const BOX_MASK: u128 = 0x1ff;
+const ALL_TILES: u128 = (1 << 81) - 1;
+const ALL_BOXES: u16 = 0x1ff;
+
+pub(crate) const LINES: [u16; 8] = [
+    0b000_000_111,
+    0b000_111_000,
+    0b111_000_000,
+    0b001_001_001,
+    0b010_010_010,
+    0b100_100_100,
+    0b100_010_001,
+    0b001_010_100,
+];
+
+const fn make_win_table() -> [bool; 512] {
+    let mut table = [false; 512];
+    let mut bits = 0;
+    while bits < 512 {
+        let mut i = 0;
+        while i < LINES.len() {
+            if bits & LINES[i] as usize == LINES[i] as usize {
+                table[bits] = true;
+            }
+            i += 1;
+        }
+        bits += 1;
+    }
+    table
+}
+
+// Whether a set of 9 tiles (or boxes) contains a line, for every possible set
+const WINS: [bool; 512] = make_win_table();
+
+pub(crate) fn box_tiles(mask: u128, idx: usize) -> u16 {
+    ((mask >> (9 * idx)) & BOX_MASK) as u16
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct Move(u8);
+
+impl Move {
+    pub fn new(coords: (Direction, Direction)) -> Self {
+        Self((9 * coords.0.index() + coords.1.index()) as u8)
+    }
+
+    pub fn from_index(idx: usize) -> Self {
+        assert!(idx < 81, "move index out of range: {idx}");
+        Self(idx as u8)
+    }
+
+    pub fn index(&self) -> usize {
+        self.0 as usize
+    }
+
+    pub fn coords(&self) -> (Direction, Direction) {
+        (
+            Direction::ALL[self.index() / 9],
+            Direction::ALL[self.index() % 9],
+        )
+    }
+}
+
+impl From<(Direction, Direction)> for Move {
+    fn from(coords: (Direction, Direction)) -> Self {
+        Self::new(coords)
+    }
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (outer, inner) = self.coords();
+        write!(f, "{outer}/{inner}")
+    }
+}
+
+impl TryFrom<&str> for Move {
+    type Error = UT3Error;
+    fn try_from(string: &str) -> Result<Self, Self::Error> {
+        let (outer, inner) = string
+            .split_once('/')
+            .ok_or(UT3Error::MissingField("`/` between the box and the tile"))?;
+        Ok(Self::new((outer.try_into()?, inner.try_into()?)))
+    }
+}
+
+/// An iterator over the moves in a set of tiles, which doesn't need to allocate
+#[derive(Copy, Clone, Debug)]
+pub struct Moves(u128);
+
+impl Moves {
+    pub fn from_mask(mask: u128) -> Self {
+        Self(mask)
+    }
+
+    pub fn mask(&self) -> u128 {
+        self.0
+    }
+
+    pub fn contains(&self, mv: Move) -> bool {
+        self.0 & (1 << mv.index()) != 0
+    }
+}
+
+impl Iterator for Moves {
+    type Item = Move;
+
+    fn next(&mut self) -> Option<Move> {
+        if self.0 == 0 {
+            None
+        } else {
+            let idx = self.0.trailing_zeros();
+            self.0 &= self.0 - 1;
+            Some(Move(idx as u8))
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.0.count_ones() as usize;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Moves {}
+
+/// Everything `Position::play` overwrites that can't be worked out again from the move itself
+#[derive(Copy, Clone, Debug)]
+pub struct Undo {
+    box_winners: [u16; 2],
+    winner: Option<Player>,
+    track: Option<Direction>,
+    hash: u64,
+}
+
+/// A compact position, with one 81 bit mask of tiles per player and one 9 bit mask of won boxes
+/// per player. Wins are updated incrementally by `play`, only looking at the box that was played
+/// in, and `undo` takes a move back without having to recompute anything.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Position {
+    tiles: [u128; 2],
+    box_winners: [u16; 2],
+    full_boxes: u16,
+    winner: Option<Player>,
+    side_to_move: Player,
+    track: Option<Direction>,
+    rules: RuleSet,
+    hash: u64,
+}
+
+impl Position {
+    pub fn new(rules: RuleSet) -> Self {
+        let mut position = Self {
+            tiles: [0; 2],
+            box_winners: [0; 2],
+            full_boxes: 0,
+            winner: None,
+            side_to_move: rules.first_player,
+            track: None,
+            rules,
+            hash: 0,
+        };
+        position.hash = zobrist::hash(&position);
+        position
+    }
+
+    /// A position from each player's tiles (bit `9 * box + tile`) and boxes won, checked the same
+    /// way as a position string
+    pub fn from_bitboards(
+        tiles: [u128; 2],
+        box_winners: [u16; 2],
+        side_to_move: Player,
+        track: Option<Direction>,
+        rules: RuleSet,
+    ) -> Result<Self, UT3Error> {
+        if (tiles[0] | tiles[1]) >> 81 != 0
+            || tiles[0] & tiles[1] != 0
+            || (box_winners[0] | box_winners[1]) >> 9 != 0
+        {
+            return Err(UT3Error::InvalidPosition(
+                "tiles out of range or taken twice".to_string(),
+            ));
+        }
+        let mut position =
+            Position::from_parts(tiles, box_winners, None, side_to_move, track, rules);
+        position.validate()?;
+        position.update_winner(side_to_move.opponent());
+
+        if let Some(track) = position.track {
+            if position.winner.is_some() || position.box_is_finished(track) {
+                return Err(UT3Error::InvalidPosition(format!(
+                    "can't be sent to box `{track:?}`, since it is finished"
+                )));
+            }
+        }
+
+        Ok(position)
+    }
+
+    // For positions that weren't reached by playing moves from the start, e.g. transformed or
+    // parsed ones. Which boxes are full and the hash are worked out from the rest.
+    pub(crate) fn from_parts(
+        tiles: [u128; 2],
+        box_winners: [u16; 2],
+        winner: Option<Player>,
+        side_to_move: Player,
+        track: Option<Direction>,
+        rules: RuleSet,
+    ) -> Self {
+        let full_boxes = (0..9)
+            .filter(|&idx| box_tiles(tiles[0] | tiles[1], idx) == BOX_MASK as u16)
+            .fold(0, |mask, idx| mask | 1 << idx);
+        let mut position = Self {
+            tiles,
+            box_winners,
+            full_boxes,
+            winner,
+            side_to_move,
+            track,
+            rules,
+            hash: 0,
+        };
+        position.hash = zobrist::hash(&position);
+        position
+    }
+
+    /// A Zobrist hash of the tiles, box winners, side to move, track and rules
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    pub fn rules(&self) -> RuleSet {
+        self.rules
+    }
+
+    pub fn side_to_move(&self) -> Player {
+        self.side_to_move
+    }
+
+    /// The box the side to move has to play in, or `None` if they can play in any unfinished box
+    pub fn track(&self) -> Option<Direction> {
+        self.track
+    }
+
+    pub fn winner(&self) -> Option<Player> {
+        self.winner
+    }
+
+    pub fn tiles(&self, player: Player) -> u128 {
+        self.tiles[player.index()]
+    }
+
+    pub fn empty_tiles(&self) -> u128 {
+        !(self.tiles[0] | self.tiles[1]) & ALL_TILES
+    }
+
+    pub fn tile(&self, coords: (Direction, Direction)) -> Option<Player> {
+        let bit = 1 << Move::new(coords).index();
+        if self.tiles[0] & bit != 0 {
+            Some(Player::X)
+        } else if self.tiles[1] & bit != 0 {
+            Some(Player::O)
+        } else {
+            None
+        }
+    }
+
+    pub fn box_winners(&self, player: Player) -> u16 {
+        self.box_winners[player.index()]
+    }
+
+    pub fn box_winner(&self, idx: Direction) -> Option<Player> {
+        if self.box_winners[0] & (1 << idx.index()) != 0 {
+            Some(Player::X)
+        } else if self.box_winners[1] & (1 << idx.index()) != 0 {
+            Some(Player::O)
+        } else {
+            None
+        }
+    }
+
+    pub fn full_boxes(&self) -> u16 {
+        self.full_boxes
+    }
+
+    /// Boxes that can't be played in any more
+    pub fn finished_boxes(&self) -> u16 {
+        if self.rules.play_in_won_boxes {
+            self.full_boxes
+        } else {
+            self.full_boxes | self.box_winners[0] | self.box_winners[1]
+        }
+    }
+
+    pub fn box_is_finished(&self, idx: Direction) -> bool {
+        self.finished_boxes() & (1 << idx.index()) != 0
+    }
+
+    /// Boxes that filled up without anyone winning them
+    pub fn drawn_boxes(&self) -> u16 {
+        self.full_boxes & !(self.box_winners[0] | self.box_winners[1])
+    }
+
+    pub fn status(&self) -> GameStatus {
+        if let Some(player) = self.winner {
+            GameStatus::Won(player)
+        } else if self.finished_boxes() == ALL_BOXES {
+            GameStatus::Drawn
+        } else {
+            GameStatus::Ongoing
+        }
+    }
+
+    pub fn legal_moves(&self) -> Moves {
+        self.moves_in(self.track)
+    }
+
+    /// The moves that would be legal if the side to move had been sent to `track`
+    pub fn moves_in(&self, track: Option<Direction>) -> Moves {
+        if self.winner.is_some() {
+            return Moves(0);
+        }
+
+        let boxes = match track {
+            Some(track) => 1 << track.index(),
+            None => !self.finished_boxes() & ALL_BOXES,
+        };
+        let mut mask = 0;
+        for idx in 0..9 {
+            if boxes & (1 << idx) != 0 {
+                mask |= BOX_MASK << (9 * idx);
+            }
+        }
+
+        Moves(mask & self.empty_tiles())
+    }
+
+    /// Whether `mv` would win its box for the side to move
+    pub fn wins_box(&self, mv: Move) -> bool {
+        let idx = mv.index() / 9;
+        let tiles = self.tiles[self.side_to_move.index()] | 1 << mv.index();
+        (self.box_winners[0] | self.box_winners[1]) & (1 << idx) == 0
+            && WINS[box_tiles(tiles, idx) as usize]
+    }
+
+    /// Plays `mv` for the side to move without checking that it is legal
+    pub fn play(&mut self, mv: Move) -> Undo {
+        let undo = Undo {
+            box_winners: self.box_winners,
+            winner: self.winner,
+            track: self.track,
+            hash: self.hash,
+        };
+
+        let player = self.side_to_move;
+        let (outer, inner) = mv.coords();
+        let idx = outer.index();
+        self.tiles[player.index()] |= 1 << mv.index();
+
+        let mut box_changed = false;
+        if (self.box_winners[0] | self.box_winners[1]) & (1 << idx) == 0
+            && WINS[box_tiles(self.tiles[player.index()], idx) as usize]
+        {
+            self.box_winners[player.index()] |= 1 << idx;
+            self.hash ^= KEYS.box_winner(player, outer);
+            box_changed = true;
+        }
+        if box_tiles(self.tiles[0] | self.tiles[1], idx) == BOX_MASK as u16 {
+            self.full_boxes |= 1 << idx;
+            box_changed = true;
+        }
+
+        if box_changed && self.winner.is_none() {
+            self.update_winner(player);
+        }
+
+        let next = match self.rules.variant {
+            Variant::Relative => outer + inner,
+            Variant::Absolute => inner,
+        };
+        self.track = if self.winner.is_some() || self.box_is_finished(next) {
+            None
+        } else {
+            Some(next)
+        };
+        self.side_to_move = player.opponent();
+
+        self.hash ^= KEYS.tile(player, mv.index())
+            ^ KEYS.track(undo.track)
+            ^ KEYS.track(self.track)
+            ^ KEYS.side_to_move(player)
+            ^ KEYS.side_to_move(self.side_to_move);
+
+        undo
+    }
+
+    /// Takes back `mv`, which must have been the last move played, using what `play` returned
+    pub fn undo(&mut self, mv: Move, undo: Undo) {
+        self.side_to_move = self.side_to_move.opponent();
+        self.tiles[self.side_to_move.index()] &= !(1 << mv.index());
+        self.full_boxes &= !(1 << mv.coords().0.index());
+        self.box_winners = undo.box_winners;
+        self.winner = undo.winner;
+        self.track = undo.track;
+        self.hash = undo.hash;
+    }
+
+    // `player` has just moved, so only they can have newly made a line on the grid, unless the box
+    // they played in was drawn and drawn boxes are wild. Then it can be either or both players, and
+    // `player` goes first.
+    fn update_winner(&mut self, player: Player) {
+        if self.has_line(player) {
+            self.winner = Some(player);
+        } else if self.has_line(player.opponent()) {
+            self.winner = Some(player.opponent());
+        } else if self.rules.decide_draws_by_box_count && self.finished_boxes() == ALL_BOXES {
+            let (x, o) = (
+                self.box_winners[0].count_ones(),
+                self.box_winners[1].count_ones(),
+            );
+            if x > o {
+                self.winner = Some(Player::X);
+            } else if o > x {
+                self.winner = Some(Player::O);
+            }
+        }
+    }
+}
+
+impl Position {
+    fn has_line(&self, player: Player) -> bool {
+        let won = self.box_winners[player.index()];
+        let wild = if self.rules.drawn_boxes_count_for_both {
+            self.drawn_boxes()
+        } else {
+            0
+        };
+
+        LINES
+            .iter()
+            .any(|&line| line & !(won | wild) == 0 && line & won != 0)
+    }
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self::new(RuleSet::default())
+    }
+}
+
+fn player_char(player: Option<Player>) -> char {
+    match player {
+        Some(Player::X) => 'X',
+        Some(Player::O) => 'O',
+        None => '.',
+    }
+}
+
+fn parse_player_char(c: char) -> Result<Option<Player>, UT3Error> {
+    match c {
+        'X' => Ok(Some(Player::X)),
+        'O' => Ok(Some(Player::O)),
+        '.' => Ok(None),
+        _ => Err(UT3Error::InvalidPlayer(c.to_string())),
+    }
+}
+
+// A single line with the tiles of each box (as `X`, `O` or `.`, separated by `/`), the winner of
+// each box, the side to move, the box they have to play in (or `-` for anywhere) and the rules, e.g.
+// `.....X.../........./........./........./........./........./........./........./......... ......... O E absolute`
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for outer in Direction::ALL {
+            if outer != Direction::NW {
+                write!(f, "/")?;
+            }
+            for inner in Direction::ALL {
+                write!(f, "{}", player_char(self.tile((outer, inner))))?;
+            }
+        }
+
+        write!(f, " ")?;
+        for dir in Direction::ALL {
+            write!(f, "{}", player_char(self.box_winner(dir)))?;
+        }
+
+        write!(f, " {} ", self.side_to_move)?;
+        match self.track {
+            Some(track) => write!(f, "{track}")?,
+            None => write!(f, "-")?,
+        }
+        write!(f, " {}", self.rules)
+    }
+}
+
+impl TryFrom<&str> for Position {
+    type Error = UT3Error;
+    fn try_from(string: &str) -> Result<Self, Self::Error> {
+        let fields = string.split_ascii_whitespace().collect::<Vec<&str>>();
+        if fields.len() != 5 {
+            return Err(UT3Error::InvalidPosition(format!(
+                "expected 5 fields, found {}",
+                fields.len()
+            )));
+        }
+
+        let boxes = fields[0].split('/').collect::<Vec<&str>>();
+        if boxes.len() != 9 || boxes.iter().any(|b| b.chars().count() != 9) {
+            return Err(UT3Error::InvalidPosition(
+                "expected 9 boxes of 9 tiles each".to_string(),
+            ));
+        }
+        let mut tiles = [0; 2];
+        for (outer, b) in boxes.iter().enumerate() {
+            for (inner, c) in b.chars().enumerate() {
+                if let Some(player) = parse_player_char(c)? {
+                    tiles[player.index()] |= 1 << (9 * outer + inner);
+                }
+            }
+        }
+
+        if fields[1].chars().count() != 9 {
+            return Err(UT3Error::InvalidPosition(
+                "expected 9 box winners".to_string(),
+            ));
+        }
+        let mut box_winners = [0; 2];
+        for (idx, c) in fields[1].chars().enumerate() {
+            if let Some(player) = parse_player_char(c)? {
+                box_winners[player.index()] |= 1 << idx;
+            }
+        }
+
+        let side_to_move: Player = fields[2].try_into()?;
+        let track = match fields[3] {
+            "-" => None,
+            dir => Some(dir.try_into()?),
+        };
+        let rules: RuleSet = fields[4].try_into()?;
+
+        Position::from_bitboards(tiles, box_winners, side_to_move, track, rules)
+    }
+}
+
+impl Position {
+    // Checks that the tiles, box winners and side to move could have come from an actual game
+    fn validate(&self) -> Result<(), UT3Error> {
+        for dir in Direction::ALL {
+            let idx = dir.index();
+            let lines =
+                [Player::X, Player::O].map(|p| WINS[box_tiles(self.tiles(p), idx) as usize]);
+            match self.box_winner(dir) {
+                Some(player) if !lines[player.index()] => {
+                    return Err(UT3Error::InvalidPosition(format!(
+                        "box `{dir:?}` is marked as won by {player} without a line"
+                    )))
+                }
+                None if lines[0] || lines[1] => {
+                    return Err(UT3Error::InvalidPosition(format!(
+                        "box `{dir:?}` has a line but no winner"
+                    )))
+                }
+                _ => (),
+            }
+            if lines[0] && lines[1] && !self.rules.play_in_won_boxes {
+                return Err(UT3Error::InvalidPosition(format!(
+                    "box `{dir:?}` has lines for both players"
+                )));
+            }
+        }
+        if self.box_winners[0] & self.box_winners[1] != 0 {
+            return Err(UT3Error::InvalidPosition(
+                "a box can't be won by both players".to_string(),
+            ));
+        }
+
+        let first = self.rules.first_player;
+        let moved = [first, first.opponent()].map(|p| self.tiles(p).count_ones());
+        let first_has_moved_more = if self.side_to_move == first { 0 } else { 1 };
+        if moved[0] != moved[1] + first_has_moved_more {
+            return Err(UT3Error::InvalidPosition(format!(
+                "{} X and {} O tiles don't fit with {} to move",
+                self.tiles(Player::X).count_ones(),
+                self.tiles(Player::O).count_ones(),
+                self.side_to_move
+            )));
+        }
+
+        let side = self.side_to_move;
+        // With wild drawn boxes the last turn can make a line for either player, but otherwise the
+        // side to move can't have one
+        if self.has_line(side) && !self.rules.drawn_boxes_count_for_both {
+            return Err(UT3Error::InvalidPosition(format!(
+                "{side} is to move but has already won"
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+/// Serializes as a position string, so positions stay readable in JSON
+#[cfg(feature = "serde")]
+impl Serialize for Position {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Position {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let string = String::deserialize(deserializer)?;
+        Position::try_from(string.as_str()).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Grid, RuleSet};
+    use Direction::*;
+
+    #[test]
+    fn position_string_round_trip() {
+        let mut grid = Grid::default().with_rules(RuleSet::RELATIVE);
+        for coords in [(C, C), (C, NW), (NW, C)] {
+            grid.apply_turn(coords).unwrap();
+        }
+        let string = grid.position().to_string();
+        assert_eq!(
+            Position::try_from(string.as_str()).unwrap(),
+            *grid.position()
+        );
+        assert_eq!(
+            Position::default().to_string(),
+            "........./........./........./........./........./........./........./........./......... ......... X - absolute"
+        );
+    }
+
+    #[test]
+    fn invalid_position_strings() {
+        let empty =
+            "........./........./........./........./........./........./........./.........";
+        for string in [
+            "",
+            "........./......... ......... X - absolute",
+            &format!("{empty}/......... ......... X - sideways"),
+            &format!("{empty}/......... ......... Z - absolute"),
+            &format!("{empty}/......... ......... X Q absolute"),
+            &format!("{empty}/........? ......... X - absolute"),
+            // O can't have moved more than X
+            &format!("{empty}/O........ ......... X - absolute"),
+        ] {
+            assert!(Position::try_from(string).is_err(), "{string}");
+        }
+        assert!(
+            Position::try_from(format!("{empty}/......... ......... X - absolute").as_str())
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,357 @@
+//! Players that pick moves on their own: the built-in searchers, or another program speaking the
+//! protocol in `protocol`. Engines are described by specs like `alphabeta:depth=6`, see
+//! `from_spec`.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command as Process, Stdio};
+use std::time::Duration;
+
+use crate::bitboard::Move;
+use crate::error::UT3Error;
+use crate::eval::{Evaluator, Heuristic, Weights};
+use crate::game::Grid;
+use crate::mcts::{Budget, Mcts, MctsConfig, Playout};
+use crate::nn::Network;
+use crate::protocol::{Command, Response};
+use crate::search::{Limits, Searcher};
+
+pub trait Engine {
+    fn name(&self) -> &str;
+
+    /// Called before every game, so the engine can forget what it learned in the last one
+    fn new_game(&mut self) -> Result<(), UT3Error>;
+
+    /// The move to play in `grid`, or `None` if the game is over
+    fn best_move(&mut self, grid: &Grid) -> Result<Option<Move>, UT3Error>;
+}
+
+/// `Searcher` with fixed limits for every move
+pub struct AlphaBeta {
+    name: String,
+    searcher: Searcher<Box<dyn Evaluator>>,
+    limits: Limits,
+}
+
+impl AlphaBeta {
+    pub fn new(name: &str, searcher: Searcher<Box<dyn Evaluator>>, limits: Limits) -> Self {
+        Self {
+            name: name.to_string(),
+            searcher,
+            limits,
+        }
+    }
+}
+
+impl Engine for AlphaBeta {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) -> Result<(), UT3Error> {
+        self.searcher.clear();
+        Ok(())
+    }
+
+    fn best_move(&mut self, grid: &Grid) -> Result<Option<Move>, UT3Error> {
+        Ok(self.searcher.search_grid(grid, self.limits).best_move)
+    }
+}
+
+/// `Mcts` with a fixed budget for every move
+pub struct MonteCarlo {
+    name: String,
+    mcts: Mcts<Box<dyn Evaluator>>,
+    budget: Budget,
+}
+
+impl MonteCarlo {
+    pub fn new(name: &str, mcts: Mcts<Box<dyn Evaluator>>, budget: Budget) -> Self {
+        Self {
+            name: name.to_string(),
+            mcts,
+            budget,
+        }
+    }
+}
+
+impl Engine for MonteCarlo {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) -> Result<(), UT3Error> {
+        Ok(())
+    }
+
+    fn best_move(&mut self, grid: &Grid) -> Result<Option<Move>, UT3Error> {
+        Ok(self.mcts.search_grid(grid, self.budget).best_move)
+    }
+}
+
+/// Another program, like `ut3e-engine`, driven over its stdin and stdout
+pub struct External {
+    name: String,
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    limits: Limits,
+}
+
+impl External {
+    /// Starts `program` and sets each of `options` with `setoption`
+    pub fn spawn(
+        program: &str,
+        options: &[(String, String)],
+        limits: Limits,
+    ) -> Result<Self, UT3Error> {
+        let mut child = Process::new(program)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let mut engine = Self {
+            name: program.to_string(),
+            stdin: child.stdin.take().unwrap(),
+            stdout: BufReader::new(child.stdout.take().unwrap()),
+            child,
+            limits,
+        };
+
+        engine.send(&Command::Hello)?;
+        loop {
+            match engine.receive()? {
+                Response::Id { field, value } if field == "name" => engine.name = value,
+                Response::HelloOk => break,
+                _ => (),
+            }
+        }
+        for (name, value) in options {
+            engine.send(&Command::SetOption {
+                name: name.clone(),
+                value: value.clone(),
+            })?;
+        }
+        engine.wait_until_ready()?;
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &Command) -> Result<(), UT3Error> {
+        writeln!(self.stdin, "{command}")?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+
+    // The next line the engine sends that makes sense, since it might print other things too
+    fn receive(&mut self) -> Result<Response, UT3Error> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("`{}` stopped responding", self.name),
+                )
+                .into());
+            }
+            if let Ok(response) = Response::try_from(line.as_str()) {
+                return Ok(response);
+            }
+        }
+    }
+
+    fn wait_until_ready(&mut self) -> Result<(), UT3Error> {
+        self.send(&Command::IsReady)?;
+        while !matches!(self.receive()?, Response::ReadyOk) {}
+        Ok(())
+    }
+}
+
+impl Engine for External {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn new_game(&mut self) -> Result<(), UT3Error> {
+        self.send(&Command::NewGame)?;
+        self.wait_until_ready()
+    }
+
+    fn best_move(&mut self, grid: &Grid) -> Result<Option<Move>, UT3Error> {
+        self.send(&Command::Position {
+            start: Some(*grid.start()),
+            moves: grid
+                .turns
+                .iter()
+                .map(|turn| Move::new(turn.coords))
+                .collect(),
+        })?;
+        self.send(&Command::Go(self.limits))?;
+        loop {
+            if let Response::BestMove(mv) = self.receive()? {
+                return Ok(mv);
+            }
+        }
+    }
+}
+
+impl Drop for External {
+    fn drop(&mut self) {
+        // Give it the chance to quit by itself first
+        let _ = self.send(&Command::Quit);
+        for _ in 0..10 {
+            if self.child.try_wait().is_ok_and(|status| status.is_some()) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+// The `weights` or `network` settings of a spec
+#[derive(Default)]
+struct EvaluatorSpec {
+    weights: Option<String>,
+    network: Option<String>,
+}
+
+impl EvaluatorSpec {
+    // Takes the setting if it's about the evaluator
+    fn set(&mut self, key: &str, value: &str) -> Result<bool, UT3Error> {
+        match key {
+            "weights" => self.weights = Some(value.to_string()),
+            "network" => self.network = Some(value.to_string()),
+            _ => return Ok(false),
+        }
+        if self.weights.is_some() && self.network.is_some() {
+            return Err(UT3Error::InvalidEngine(
+                "`weights` and `network` can't both be set".to_string(),
+            ));
+        }
+        Ok(true)
+    }
+
+    fn build(&self) -> Result<Box<dyn Evaluator>, UT3Error> {
+        Ok(match (&self.weights, &self.network) {
+            (_, Some(network)) => Box::new(Network::load(network)?),
+            (Some(weights), None) => Box::new(Heuristic::new(Weights::load(weights)?)),
+            (None, None) => Box::new(Heuristic::default()),
+        })
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, UT3Error> {
+    value
+        .parse()
+        .map_err(|_| UT3Error::InvalidEngine(format!("invalid value `{value}` for `{key}`")))
+}
+
+/// Makes an engine from a spec, which is the kind of engine, then optionally a `:` and a comma
+/// separated list of `key=value` settings:
+///
+/// - `alphabeta`: `depth`, `movetime` (in milliseconds), `nodes`, `hash` (in table entries), and
+///   either `weights` (a file, see `eval::Weights`) or `network` (a file, see `nn`). Defaults to
+///   `depth=6`.
+/// - `mcts`: `iterations`, `movetime`, `exploration`, `playout` (`random`, `heuristic` or
+///   `evaluation`), `priors` (`true` or `false`), `seed`, and `weights` or `network` as for
+///   `alphabeta`. Defaults to `iterations=10000`.
+/// - `external`: `cmd` (the program to run, required), `depth`, `movetime` and `nodes`. Anything
+///   else is passed on with `setoption`. Defaults to `movetime=1000`, since without a limit the
+///   program would search forever.
+///
+/// Every kind also takes a `name`, e.g. `alphabeta:name=deep,depth=8`.
+pub fn from_spec(spec: &str) -> Result<Box<dyn Engine>, UT3Error> {
+    let (kind, settings) = spec.split_once(':').unwrap_or((spec, ""));
+    let mut name = None;
+    let mut limits = Limits::default();
+    let mut other = Vec::new();
+    for setting in settings.split(',').filter(|setting| !setting.is_empty()) {
+        let (key, value) = setting.split_once('=').ok_or_else(|| {
+            UT3Error::InvalidEngine(format!("expected `key=value`, found `{setting}`"))
+        })?;
+        match key {
+            "name" => name = Some(value.to_string()),
+            "depth" => limits.depth = Some(parse_value(key, value)?),
+            "movetime" => limits.time = Some(Duration::from_millis(parse_value(key, value)?)),
+            "nodes" => limits.nodes = Some(parse_value(key, value)?),
+            _ => other.push((key.to_string(), value.to_string())),
+        }
+    }
+    let unlimited = limits.depth.is_none() && limits.time.is_none() && limits.nodes.is_none();
+
+    match kind {
+        "alphabeta" => {
+            let mut table_size = 1 << 20;
+            let mut evaluator = EvaluatorSpec::default();
+            for (key, value) in &other {
+                match key.as_str() {
+                    "hash" => table_size = parse_value(key, value)?,
+                    _ if evaluator.set(key, value)? => (),
+                    _ => return Err(UT3Error::InvalidEngine(format!("unknown setting `{key}`"))),
+                }
+            }
+            if unlimited {
+                limits.depth = Some(6);
+            }
+            let searcher = Searcher::with_evaluator(table_size, evaluator.build()?);
+            let name = name.unwrap_or_else(|| spec.to_string());
+            Ok(Box::new(AlphaBeta::new(&name, searcher, limits)))
+        }
+        "mcts" => {
+            let mut config = MctsConfig::default();
+            let mut iterations = 10_000;
+            let mut evaluator = EvaluatorSpec::default();
+            for (key, value) in &other {
+                match key.as_str() {
+                    "iterations" => iterations = parse_value(key, value)?,
+                    "exploration" => config.exploration = parse_value(key, value)?,
+                    "priors" => config.priors = parse_value(key, value)?,
+                    "seed" => config.seed = parse_value(key, value)?,
+                    "playout" => {
+                        config.playout = match value.as_str() {
+                            "random" => Playout::Random,
+                            "heuristic" => Playout::Heuristic,
+                            "evaluation" => Playout::Evaluation,
+                            _ => {
+                                return Err(UT3Error::InvalidEngine(format!(
+                                    "unknown playout `{value}`"
+                                )))
+                            }
+                        }
+                    }
+                    _ if evaluator.set(key, value)? => (),
+                    _ => return Err(UT3Error::InvalidEngine(format!("unknown setting `{key}`"))),
+                }
+            }
+            if limits.depth.is_some() || limits.nodes.is_some() {
+                return Err(UT3Error::InvalidEngine(
+                    "MCTS takes `iterations` or `movetime`".to_string(),
+                ));
+            }
+            let budget = match limits.time {
+                Some(time) => Budget::Time(time),
+                None => Budget::Iterations(iterations),
+            };
+            let name = name.unwrap_or_else(|| spec.to_string());
+            let mcts = Mcts::with_evaluator(config, evaluator.build()?);
+            Ok(Box::new(MonteCarlo::new(&name, mcts, budget)))
+        }
+        "external" => {
+            let idx = other
+                .iter()
+                .position(|(key, _)| key == "cmd")
+                .ok_or(UT3Error::MissingField("`cmd` for the external engine"))?;
+            let (_, program) = other.remove(idx);
+            if unlimited {
+                limits.time = Some(Duration::from_secs(1));
+            }
+            let mut engine = External::spawn(&program, &other, limits)?;
+            // Otherwise it goes by the name the program gives
+            if let Some(name) = name {
+                engine.name = name;
+            }
+            Ok(Box::new(engine))
+        }
+        _ => Err(UT3Error::InvalidEngine(format!("unknown engine `{kind}`"))),
+    }
+}
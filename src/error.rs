@@ -1,6 +1,6 @@
 use thiserror::Error;
 
-use crate::game::{Direction, Player};
+use crate::game::{Direction, GameStatus, Player};
 
 #[derive(Error, Debug)]
 pub enum UT3Error {
@@ -19,4 +19,41 @@ pub enum UT3Error {
     },
     #[error("box `{0:?}` is finished")]
     BoxHasWinner(Direction),
+    #[error("the game is already over: {0}")]
+    GameOver(GameStatus),
+    #[error("invalid rules: `{0}`")]
+    InvalidRules(String),
+    #[error("invalid position: {0}")]
+    InvalidPosition(String),
+    #[error("missing {0}")]
+    MissingField(&'static str),
+    #[error("unexpected `{0}` after the turn")]
+    TrailingInput(String),
+    #[error("wrong turn number: should have been {expected} but was {got}")]
+    WrongTurnNumber { expected: u32, got: u32 },
+    #[error("wrong player: it is {expected}'s turn, not {got}'s")]
+    WrongPlayer { expected: Player, got: Player },
+    #[error("invalid header: {0}")]
+    InvalidHeader(String),
+    #[error("unexpected `{0}`")]
+    UnexpectedToken(String),
+    #[error("unterminated {0}")]
+    Unterminated(&'static str),
+    #[error("invalid file: {0}")]
+    InvalidFile(String),
+    #[error("invalid weight: `{0}`")]
+    InvalidWeight(String),
+    #[error("invalid engine: {0}")]
+    InvalidEngine(String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[cfg(feature = "serde")]
+    #[error("invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("line {line}, column {column}: {source}")]
+    At {
+        line: usize,
+        column: usize,
+        source: Box<UT3Error>,
+    },
 }
@@ -0,0 +1,357 @@
+use std::fmt;
+use std::path::Path;
+
+use crate::bitboard::{box_tiles, Position, LINES};
+use crate::error::UT3Error;
+use crate::game::{Direction, Grid, Player};
+use crate::search::plies_to_end;
+
+/// A static evaluation of positions, for the searchers to use where they stop looking further
+pub trait Evaluator {
+    /// How good `position` is for the side to move: positive is better for them, and `0` is
+    /// even. Scores must stay well within `search::WIN`, which is kept for forced wins.
+    fn evaluate(&self, position: &Position) -> i32;
+
+    fn evaluate_grid(&self, grid: &Grid) -> i32 {
+        self.evaluate(grid.position())
+    }
+
+    /// How likely each move (by `Move::index`) is to be the best, for evaluators that have an
+    /// idea. MCTS uses it to decide which moves to look at first.
+    fn policy(&self, _position: &Position) -> Option<[f32; 81]> {
+        None
+    }
+}
+
+impl<E: Evaluator + ?Sized> Evaluator for &E {
+    fn evaluate(&self, position: &Position) -> i32 {
+        (**self).evaluate(position)
+    }
+
+    fn policy(&self, position: &Position) -> Option<[f32; 81]> {
+        (**self).policy(position)
+    }
+}
+
+impl<E: Evaluator + ?Sized> Evaluator for Box<E> {
+    fn evaluate(&self, position: &Position) -> i32 {
+        (**self).evaluate(position)
+    }
+
+    fn policy(&self, position: &Position) -> Option<[f32; 81]> {
+        (**self).policy(position)
+    }
+}
+
+/// The most an evaluation gets either way, so that it stays well away from forced wins
+pub const MAX_SCORE: i32 = 10_000;
+
+/// How much a score is worth as a chance of winning: a score of `SCALE` is about a 73% chance
+pub const SCALE: f64 = 400.0;
+
+/// The expected result for the side to move, counting a draw as half a win, for a score from an
+/// evaluator or a searcher
+pub fn win_probability(score: i32) -> f64 {
+    match plies_to_end(score) {
+        Some(_) if score > 0 => 1.0,
+        Some(_) => 0.0,
+        None => 1.0 / (1.0 + (-score as f64 / SCALE).exp()),
+    }
+}
+
+/// The weights of `Heuristic`. They can be read from and written as a config file, with one
+/// `name = value` per line and `#` starting a comment:
+///
+/// ```text
+/// # Boxes are worth more on the center and corners
+/// center_box = 150
+/// grid_two = 300
+/// ```
+///
+/// Weights a file leaves out keep their default values. Each has to be within `MAX_SCORE` either
+/// way.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Weights {
+    /// Winning the center box
+    pub center_box: i32,
+    /// Winning a corner box
+    pub corner_box: i32,
+    /// Winning an edge box
+    pub edge_box: i32,
+    /// Each line on the grid with two boxes won and the third still open
+    pub grid_two: i32,
+    /// Each line in an unfinished box with two tiles taken and the third still open, scaled by
+    /// what the box is worth (out of 100)
+    pub box_two: i32,
+    /// Each center tile in an unfinished box
+    pub center_tile: i32,
+    /// Each corner tile in an unfinished box
+    pub corner_tile: i32,
+    /// Each edge tile in an unfinished box
+    pub edge_tile: i32,
+    /// The side to move getting to choose any box, because the opponent sent them to a finished
+    /// one
+    pub free_choice: i32,
+}
+
+impl Weights {
+    /// The names used in config files, in the order of `to_array`
+    pub const NAMES: [&'static str; 9] = [
+        "center_box",
+        "corner_box",
+        "edge_box",
+        "grid_two",
+        "box_two",
+        "center_tile",
+        "corner_tile",
+        "edge_tile",
+        "free_choice",
+    ];
+
+    pub fn to_array(&self) -> [i32; 9] {
+        [
+            self.center_box,
+            self.corner_box,
+            self.edge_box,
+            self.grid_two,
+            self.box_two,
+            self.center_tile,
+            self.corner_tile,
+            self.edge_tile,
+            self.free_choice,
+        ]
+    }
+
+    pub fn from_array(weights: [i32; 9]) -> Self {
+        Self {
+            center_box: weights[0],
+            corner_box: weights[1],
+            edge_box: weights[2],
+            grid_two: weights[3],
+            box_two: weights[4],
+            center_tile: weights[5],
+            corner_tile: weights[6],
+            edge_tile: weights[7],
+            free_choice: weights[8],
+        }
+    }
+
+    /// Reads a config file, see `Weights`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, UT3Error> {
+        Self::try_from(std::fs::read_to_string(path)?.as_str())
+    }
+
+    // What a box or tile is worth, by where it is
+    fn by_place(idx: usize, center: i32, corner: i32, edge: i32) -> i32 {
+        match idx {
+            4 => center,
+            0 | 2 | 6 | 8 => corner,
+            _ => edge,
+        }
+    }
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self {
+            center_box: 150,
+            corner_box: 120,
+            edge_box: 100,
+            grid_two: 300,
+            box_two: 10,
+            center_tile: 4,
+            corner_tile: 3,
+            edge_tile: 2,
+            free_choice: 40,
+        }
+    }
+}
+
+impl TryFrom<&str> for Weights {
+    type Error = UT3Error;
+    fn try_from(string: &str) -> Result<Self, Self::Error> {
+        let mut weights = Weights::default().to_array();
+        for (line_idx, line) in string.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let at = |source| UT3Error::At {
+                line: line_idx + 1,
+                column: 1,
+                source: Box::new(source),
+            };
+
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| at(UT3Error::InvalidWeight(line.to_string())))?;
+            let idx = Weights::NAMES
+                .iter()
+                .position(|&known| known == name.trim())
+                .ok_or_else(|| at(UT3Error::InvalidWeight(name.trim().to_string())))?;
+            weights[idx] = value
+                .trim()
+                .parse()
+                .ok()
+                .filter(|weight: &i32| weight.abs() <= MAX_SCORE)
+                .ok_or_else(|| at(UT3Error::InvalidWeight(value.trim().to_string())))?;
+        }
+        Ok(Weights::from_array(weights))
+    }
+}
+
+/// Writes the weights as a config file, see `Weights`
+impl fmt::Display for Weights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in Weights::NAMES.iter().zip(self.to_array()) {
+            writeln!(f, "{name} = {value}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The built-in evaluation: boxes won, two in a row on the grid and in the boxes still being
+/// played, where in those boxes the tiles are, and having a free choice of box
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Heuristic {
+    pub weights: Weights,
+}
+
+impl Heuristic {
+    pub fn new(weights: Weights) -> Self {
+        Self { weights }
+    }
+}
+
+// Counts the lines `tiles` has two of, given the tiles (or boxes) that block them
+fn open_twos(tiles: u16, blocked: u16) -> i32 {
+    LINES
+        .iter()
+        .filter(|&&line| line & blocked == 0 && (line & tiles).count_ones() == 2)
+        .count() as i32
+}
+
+impl Evaluator for Heuristic {
+    fn evaluate(&self, position: &Position) -> i32 {
+        let weights = &self.weights;
+        let rules = position.rules();
+        let drawn = position.drawn_boxes();
+        let finished = position.finished_boxes();
+
+        let mut scores = [0; 2];
+        for player in [Player::X, Player::O] {
+            let won = position.box_winners(player);
+            let theirs = position.box_winners(player.opponent());
+            let score = &mut scores[player.index()];
+
+            let (own, blocked) = if rules.drawn_boxes_count_for_both {
+                (won | drawn, theirs)
+            } else {
+                (won, theirs | drawn)
+            };
+            *score += open_twos(own, blocked) * weights.grid_two;
+
+            for dir in Direction::ALL {
+                let idx = dir.index();
+                let box_weight = Weights::by_place(
+                    idx,
+                    weights.center_box,
+                    weights.corner_box,
+                    weights.edge_box,
+                );
+                if won & (1 << idx) != 0 {
+                    *score += box_weight;
+                }
+                if finished & (1 << idx) != 0 {
+                    continue;
+                }
+                let mine = box_tiles(position.tiles(player), idx);
+                let other = box_tiles(position.tiles(player.opponent()), idx);
+                *score += open_twos(mine, other) * weights.box_two * box_weight / 100;
+                *score += (0..9)
+                    .filter(|tile| mine & (1 << tile) != 0)
+                    .map(|tile| {
+                        Weights::by_place(
+                            tile,
+                            weights.center_tile,
+                            weights.corner_tile,
+                            weights.edge_tile,
+                        )
+                    })
+                    .sum::<i32>();
+            }
+        }
+
+        let side = position.side_to_move();
+        let mut score = scores[side.index()] - scores[side.opponent().index()];
+        if position.track().is_none() {
+            score += weights.free_choice;
+        }
+        score.clamp(-MAX_SCORE, MAX_SCORE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The line and the weight (or line) named in the error for `config`
+    fn weight_error(config: &str) -> (usize, String) {
+        match Weights::try_from(config) {
+            Err(UT3Error::At { line, source, .. }) => match *source {
+                UT3Error::InvalidWeight(weight) => (line, weight),
+                other => panic!("expected an invalid weight, got {other:?}"),
+            },
+            other => panic!("expected an error with a position, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn weights_round_trip() {
+        let weights =
+            Weights::try_from("# Tuned\n\ngrid_two = 250  # was 300\n edge_tile=-1\n").unwrap();
+        assert_eq!(weights.grid_two, 250);
+        assert_eq!(weights.edge_tile, -1);
+        assert_eq!(weights.center_box, Weights::default().center_box);
+        assert_eq!(
+            Weights::try_from(weights.to_string().as_str()).unwrap(),
+            weights
+        );
+    }
+
+    #[test]
+    fn invalid_weights() {
+        assert_eq!(
+            weight_error("grid_two = 1\ncenter_box 2"),
+            (2, "center_box 2".to_string())
+        );
+        assert_eq!(
+            weight_error("# comment\n\ncenter = 2"),
+            (3, "center".to_string())
+        );
+        assert_eq!(weight_error("grid_two = 1.5"), (1, "1.5".to_string()));
+        assert_eq!(weight_error("grid_two ="), (1, String::new()));
+        assert_eq!(weight_error("grid_two = 10001"), (1, "10001".to_string()));
+        assert_eq!(
+            weight_error("grid_two = -99999999999"),
+            (1, "-99999999999".to_string())
+        );
+        assert!(Weights::try_from("grid_two = -10000").is_ok());
+    }
+
+    #[test]
+    fn scores_stay_within_max_score() {
+        // X has won four boxes to O's three, with O to move
+        let position = Position::try_from(
+            "XXXOO..../OOO....../XXXO...../OOO....../XXX....../XXX....../OOO....../XOXXOOOXX/\
+             XOXXOOOX. XOXOXXO.. O SE absolute",
+        )
+        .unwrap();
+        let weights = Weights::from_array([MAX_SCORE; 9]);
+        assert_eq!(Heuristic::new(weights).evaluate(&position), -MAX_SCORE);
+        assert_eq!(
+            Heuristic::new(weights).evaluate(&position),
+            Heuristic::new(Weights::from_array([MAX_SCORE / 2; 9])).evaluate(&position)
+        );
+    }
+}
@@ -1,18 +1,38 @@
+use std::fmt;
 use std::ops::Add;
 
+use crate::bitboard::{Move, Position, Undo};
 use crate::error::UT3Error;
+use crate::notation;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Player {
     X,
     O,
 }
 
-impl ToString for Player {
-    fn to_string(&self) -> String {
+impl Player {
+    pub fn index(&self) -> usize {
+        *self as usize
+    }
+
+    pub fn opponent(&self) -> Self {
+        match self {
+            Player::X => Player::O,
+            Player::O => Player::X,
+        }
+    }
+}
+
+impl fmt::Display for Player {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Player::X => "X".to_string(),
-            Player::O => "O".to_string(),
+            Player::X => write!(f, "X"),
+            Player::O => write!(f, "O"),
         }
     }
 }
@@ -32,6 +52,7 @@ impl TryFrom<&str> for Player {
 
 // Maybe this isn't great, but the elements are ordered such that they correctly index into a 1D list of 9 elements
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum Direction {
     NW = 0,
     N = 1,
@@ -45,6 +66,18 @@ pub enum Direction {
 }
 
 impl Direction {
+    pub const ALL: [Direction; 9] = [
+        Direction::NW,
+        Direction::N,
+        Direction::NE,
+        Direction::W,
+        Direction::C,
+        Direction::E,
+        Direction::SW,
+        Direction::S,
+        Direction::SE,
+    ];
+
     pub fn index(&self) -> usize {
         *self as usize
     }
@@ -66,18 +99,18 @@ impl Add<Direction> for Direction {
     }
 }
 
-impl ToString for Direction {
-    fn to_string(&self) -> String {
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Direction::NW => String::from("NW"),
-            Direction::N => String::from("N"),
-            Direction::NE => String::from("NE"),
-            Direction::W => String::from("W"),
-            Direction::C => String::from("C"),
-            Direction::E => String::from("E"),
-            Direction::SW => String::from("SW"),
-            Direction::S => String::from("S"),
-            Direction::SE => String::from("SE"),
+            Direction::NW => write!(f, "NW"),
+            Direction::N => write!(f, "N"),
+            Direction::NE => write!(f, "NE"),
+            Direction::W => write!(f, "W"),
+            Direction::C => write!(f, "C"),
+            Direction::E => write!(f, "E"),
+            Direction::SW => write!(f, "SW"),
+            Direction::S => write!(f, "S"),
+            Direction::SE => write!(f, "SE"),
         }
     }
 }
@@ -144,7 +177,8 @@ impl From<Direction> for (u32, u32) {
     }
 }
 
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Turn {
     pub turn_number: u32,
     player: Player,
@@ -152,27 +186,35 @@ pub struct Turn {
 }
 
 impl Turn {
+    /// A turn played by X on odd turn numbers and by O on even ones, as when X goes first. Use
+    /// `with_player` when O goes first.
     pub fn new(turn_number: u32, coords: (Direction, Direction)) -> Self {
+        Self::with_player(
+            turn_number,
+            RuleSet::CLASSIC.player_for_turn(turn_number),
+            coords,
+        )
+    }
+
+    pub fn with_player(turn_number: u32, player: Player, coords: (Direction, Direction)) -> Self {
         Self {
             turn_number,
-            player: if turn_number % 2 == 0 {
-                Player::O
-            } else {
-                Player::X
-            },
+            player,
             coords,
         }
     }
+
+    pub fn player(&self) -> Player {
+        self.player
+    }
 }
 
-impl ToString for Turn {
-    fn to_string(&self) -> String {
-        format!(
+impl fmt::Display for Turn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
             "{}\t{}\t{}/{}",
-            self.turn_number,
-            self.player.to_string(),
-            self.coords.0.to_string(),
-            self.coords.1.to_string()
+            self.turn_number, self.player, self.coords.0, self.coords.1
         )
     }
 }
@@ -180,40 +222,13 @@ impl ToString for Turn {
 impl TryFrom<&str> for Turn {
     type Error = UT3Error;
     fn try_from(string: &str) -> Result<Self, Self::Error> {
-        let parts = string.split_ascii_whitespace().collect::<Vec<&str>>();
-        let turn_number = parts[0].parse()?;
-        let coords = {
-            let coords = parts[2].split("/").collect::<Vec<&str>>();
-            (coords[0].try_into()?, coords[1].try_into()?)
-        };
-
-        Ok(Self::new(turn_number, coords))
-    }
-}
-
-fn get_win(board: &[Option<Player>]) -> Option<Player> {
-    if board[0].is_some() && board[0] == board[1] && board[1] == board[2] {
-        board[0]
-    } else if board[3].is_some() && board[3] == board[4] && board[4] == board[5] {
-        board[3]
-    } else if board[6].is_some() && board[6] == board[7] && board[7] == board[8] {
-        board[6]
-    } else if board[0].is_some() && board[0] == board[3] && board[3] == board[6] {
-        board[0]
-    } else if board[1].is_some() && board[1] == board[4] && board[4] == board[7] {
-        board[1]
-    } else if board[2].is_some() && board[2] == board[5] && board[5] == board[8] {
-        board[2]
-    } else if board[0].is_some() && board[0] == board[4] && board[4] == board[8] {
-        board[0]
-    } else if board[2].is_some() && board[2] == board[4] && board[4] == board[6] {
-        board[2]
-    } else {
-        None
+        notation::parse_turn(string).map_err(|(_, err)| err)
     }
 }
 
-#[derive(Debug)]
+/// A copy of one of the boxes of a `Grid`, kept up to date as turns are played and undone
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Box {
     pub winner: Option<Player>,
     inner: [Option<Player>; 9],
@@ -224,6 +239,7 @@ impl Box {
         &self.inner[idx.index()]
     }
 
+    #[deprecated(note = "the box is only a copy, so changing it doesn't change the grid")]
     pub fn get_tile_mut(&mut self, idx: Direction) -> &mut Option<Player> {
         &mut self.inner[idx.index()]
     }
@@ -233,153 +249,660 @@ impl Box {
     }
 }
 
-impl Default for Box {
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Variant {
+    Relative,
+    Absolute,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RuleSet {
+    /// How the previous turn picks the box that has to be played in next
+    pub variant: Variant,
+    /// Whether a box can still be played in (and sent to) after it is won, until it is full. The
+    /// first player to win a box keeps it.
+    pub play_in_won_boxes: bool,
+    /// Whether a box that fills up without a winner counts for both players when making a line on
+    /// the grid. Drawing a box can then complete a line for either player, and if it completes one
+    /// for both at once, the player who drew it wins.
+    pub drawn_boxes_count_for_both: bool,
+    /// Whether a grid that finishes without a line goes to whoever won the most boxes
+    pub decide_draws_by_box_count: bool,
+    pub first_player: Player,
+}
+
+impl RuleSet {
+    pub const CLASSIC: RuleSet = RuleSet {
+        variant: Variant::Absolute,
+        play_in_won_boxes: false,
+        drawn_boxes_count_for_both: false,
+        decide_draws_by_box_count: false,
+        first_player: Player::X,
+    };
+
+    pub const RELATIVE: RuleSet = RuleSet {
+        variant: Variant::Relative,
+        ..RuleSet::CLASSIC
+    };
+
+    pub const OPEN_BOXES: RuleSet = RuleSet {
+        play_in_won_boxes: true,
+        ..RuleSet::CLASSIC
+    };
+
+    pub const WILD_DRAWN_BOXES: RuleSet = RuleSet {
+        drawn_boxes_count_for_both: true,
+        ..RuleSet::CLASSIC
+    };
+
+    pub const BOX_COUNT: RuleSet = RuleSet {
+        decide_draws_by_box_count: true,
+        ..RuleSet::CLASSIC
+    };
+
+    pub const PRESETS: [(&'static str, RuleSet); 5] = [
+        ("Classic", RuleSet::CLASSIC),
+        ("Relative", RuleSet::RELATIVE),
+        ("Open boxes", RuleSet::OPEN_BOXES),
+        ("Wild drawn boxes", RuleSet::WILD_DRAWN_BOXES),
+        ("Box count", RuleSet::BOX_COUNT),
+    ];
+
+    pub fn preset_name(&self) -> Option<&'static str> {
+        RuleSet::PRESETS
+            .iter()
+            .find(|(_, rules)| rules == self)
+            .map(|(name, _)| *name)
+    }
+
+    pub fn player_for_turn(&self, turn_number: u32) -> Player {
+        if turn_number.is_multiple_of(2) {
+            self.first_player.opponent()
+        } else {
+            self.first_player
+        }
+    }
+}
+
+impl Default for RuleSet {
     fn default() -> Self {
-        Self {
-            winner: None,
-            inner: [None; 9],
+        RuleSet::CLASSIC
+    }
+}
+
+// The variant, followed by any rules that differ from the classic ones, e.g. `relative` or
+// `absolute+open-boxes+o-first`
+impl fmt::Display for RuleSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.variant {
+            Variant::Relative => write!(f, "relative")?,
+            Variant::Absolute => write!(f, "absolute")?,
+        }
+        if self.play_in_won_boxes {
+            write!(f, "+open-boxes")?;
+        }
+        if self.drawn_boxes_count_for_both {
+            write!(f, "+wild-draws")?;
         }
+        if self.decide_draws_by_box_count {
+            write!(f, "+box-count")?;
+        }
+        if self.first_player == Player::O {
+            write!(f, "+o-first")?;
+        }
+        Ok(())
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-pub enum Variant {
-    Relative,
-    Absolute,
+impl TryFrom<&str> for RuleSet {
+    type Error = UT3Error;
+    fn try_from(string: &str) -> Result<Self, Self::Error> {
+        let mut parts = string.split('+');
+        let variant = match parts.next() {
+            Some("relative") => Variant::Relative,
+            Some("absolute") => Variant::Absolute,
+            _ => return Err(UT3Error::InvalidRules(string.to_string())),
+        };
+
+        let mut rules = RuleSet {
+            variant,
+            ..RuleSet::CLASSIC
+        };
+        for part in parts {
+            match part {
+                "open-boxes" => rules.play_in_won_boxes = true,
+                "wild-draws" => rules.drawn_boxes_count_for_both = true,
+                "box-count" => rules.decide_draws_by_box_count = true,
+                "o-first" => rules.first_player = Player::O,
+                _ => return Err(UT3Error::InvalidRules(string.to_string())),
+            }
+        }
+
+        Ok(rules)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum GameStatus {
+    Ongoing,
+    Won(Player),
+    Drawn,
+}
+
+impl GameStatus {
+    pub fn is_over(&self) -> bool {
+        *self != GameStatus::Ongoing
+    }
+}
+
+impl fmt::Display for GameStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GameStatus::Ongoing => write!(f, "ongoing"),
+            GameStatus::Won(player) => write!(f, "{player} won"),
+            GameStatus::Drawn => write!(f, "drawn"),
+        }
+    }
 }
 
-#[derive(Debug)]
+/// Serializes as the starting position and the turns played from it, which are replayed (and so
+/// checked) when deserializing
+#[derive(Clone, Debug)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Serialize, Deserialize),
+    serde(try_from = "GridRecord", into = "GridRecord")
+)]
 pub struct Grid {
+    /// Kept up to date as turns are played and undone, but changing it does nothing
+    #[deprecated(note = "use `Grid::winner()` instead")]
     pub winner: Option<Player>,
+    /// Kept up to date like `winner`. Use `with_variant` to change it.
+    #[deprecated(note = "use `Grid::variant()` instead")]
     pub variant: Variant,
     pub current_turn_number: u32,
     pub turns: Vec<Turn>,
+    undone_turns: Vec<Turn>,
+    history: Vec<Undo>,
+    start: Position,
+    position: Position,
+    // Copies of the boxes of `position`, for `get_box`
     inner: [Box; 9],
 }
 
 impl Grid {
+    /// Plays `coords` for the player whose turn it is. Playing a new turn forgets any turns that
+    /// could have been redone.
     pub fn apply_turn(&mut self, coords: (Direction, Direction)) -> Result<(), UT3Error> {
-        if self.get_track().is_some() && coords.0 != self.get_track().unwrap() {
+        self.make_turn(coords)?;
+        self.undone_turns.clear();
+        Ok(())
+    }
+
+    /// Takes back the last turn, restoring the board to exactly how it was before it was played.
+    /// Returns the removed turn, or `None` if no turns have been played.
+    pub fn undo_turn(&mut self) -> Option<Turn> {
+        let turn = self.turns.pop()?;
+        let undo = self
+            .history
+            .pop()
+            .expect("history is pushed alongside every turn");
+
+        self.position.undo(Move::new(turn.coords), undo);
+        self.update_box(turn.coords.0);
+        self.current_turn_number -= 1;
+
+        self.undone_turns.push(turn);
+        Some(turn)
+    }
+
+    /// Plays the most recently undone turn again. Returns the replayed turn, or `None` if there is
+    /// nothing to redo.
+    pub fn redo_turn(&mut self) -> Option<Turn> {
+        let turn = self.undone_turns.pop()?;
+        self.make_turn(turn.coords)
+            .expect("an undone turn is legal again once everything after it is undone");
+        self.turns.last().copied()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.undone_turns.is_empty()
+    }
+
+    fn make_turn(&mut self, coords: (Direction, Direction)) -> Result<(), UT3Error> {
+        if self.status().is_over() {
+            Err(UT3Error::GameOver(self.status()))
+        } else if self.get_track().is_some() && coords.0 != self.get_track().unwrap() {
             Err(UT3Error::WrongTrack {
                 required: self.get_track().unwrap(),
                 got: coords.0,
             })
-        } else if self.get_box(coords.0).get_tile(coords.1).is_some() {
+        } else if let Some(value) = self.position.tile(coords) {
             Err(UT3Error::PositionTaken {
                 position: coords,
-                value: self.get_box(coords.0).get_tile(coords.1).unwrap(),
+                value,
             })
         } else if self.box_is_finished(coords.0) {
             Err(UT3Error::BoxHasWinner(coords.0))
         } else {
-            let turn = Turn::new(self.current_turn_number, coords);
-
-            *self.get_box_mut(coords.0).get_tile_mut(coords.1) = Some(turn.player);
+            let turn = Turn::with_player(self.current_turn_number, self.current_player(), coords);
 
+            self.history.push(self.position.play(Move::new(coords)));
+            self.update_box(coords.0);
             self.turns.push(turn);
 
-            self.update_wins();
-
             self.current_turn_number += 1;
             Ok(())
         }
     }
 
-    pub fn get_track(&self) -> Option<Direction> {
-        if let Some(prev_turn) = self.turns.last() {
-            let absolute_direction = match self.variant {
-                Variant::Relative => prev_turn.coords.0 + prev_turn.coords.1,
-                Variant::Absolute => prev_turn.coords.1,
-            };
-
-            if self.box_is_finished(absolute_direction) {
-                None
-            } else {
-                Some(absolute_direction)
-            }
-        } else {
-            None
+    /// A grid that starts from `position` instead of an empty board, with no turns played yet
+    #[allow(deprecated)]
+    pub fn from_position(position: Position) -> Self {
+        let filled =
+            position.tiles(Player::X).count_ones() + position.tiles(Player::O).count_ones();
+        let mut grid = Self {
+            winner: None,
+            variant: position.rules().variant,
+            current_turn_number: filled + 1,
+            turns: Vec::new(),
+            undone_turns: Vec::new(),
+            history: Vec::new(),
+            start: position,
+            position,
+            inner: Default::default(),
+        };
+        for dir in Direction::ALL {
+            grid.update_box(dir);
+        }
+        grid
+    }
+
+    // Copies box `idx` and the grid's winner over from `position` after it changed
+    #[allow(deprecated)]
+    fn update_box(&mut self, idx: Direction) {
+        let b = &mut self.inner[idx.index()];
+        b.winner = self.position.box_winner(idx);
+        for (tile, dir) in b.inner.iter_mut().zip(Direction::ALL) {
+            *tile = self.position.tile((idx, dir));
         }
+        self.winner = self.position.winner();
+    }
+
+    pub fn position(&self) -> &Position {
+        &self.position
+    }
+
+    /// The position before any of `turns` were played
+    pub fn start(&self) -> &Position {
+        &self.start
+    }
+
+    pub fn hash(&self) -> u64 {
+        self.position.hash()
+    }
+
+    pub fn rules(&self) -> RuleSet {
+        self.position.rules()
+    }
+
+    pub fn winner(&self) -> Option<Player> {
+        self.position.winner()
+    }
+
+    pub fn variant(&self) -> Variant {
+        self.rules().variant
+    }
+
+    /// The game is drawn once every box is finished without anyone having won the grid, since
+    /// there is nowhere left to play.
+    pub fn status(&self) -> GameStatus {
+        self.position.status()
+    }
+
+    pub fn current_player(&self) -> Player {
+        self.position.side_to_move()
+    }
+
+    pub fn get_track(&self) -> Option<Direction> {
+        self.position.track()
     }
 
     pub fn get_box(&self, idx: Direction) -> &Box {
         &self.inner[idx.index()]
     }
 
+    #[deprecated(note = "the box is only a copy, so changing it doesn't change the grid")]
     pub fn get_box_mut(&mut self, idx: Direction) -> &mut Box {
         &mut self.inner[idx.index()]
     }
 
     pub fn box_is_finished(&self, idx: Direction) -> bool {
-        return self.get_box(idx).winner.is_some() || self.get_box(idx).is_full();
+        self.position.box_is_finished(idx)
     }
 
     pub fn get_valid_boxes(&self, track: Option<Direction>) -> Vec<(Direction, Direction)> {
-        let mut boxes = Vec::new();
-        if let Some(track) = track {
-            let b = self.get_box(track);
-            for i in 0..9 {
-                let dir = i.try_into().unwrap();
-                if b.get_tile(dir).is_none() {
-                    boxes.push((track, dir));
-                }
+        self.position
+            .moves_in(track)
+            .map(|mv| mv.coords())
+            .collect()
+    }
+
+    /// Sets up a new grid with `variant` and otherwise the same rules. Panics if the grid isn't
+    /// an empty board with no turns played, like `with_rules`.
+    pub fn with_variant(self, variant: Variant) -> Self {
+        let rules = self.rules();
+        self.with_rules(RuleSet { variant, ..rules })
+    }
+
+    /// Sets up a new grid with `rules`. Panics if the grid isn't an empty board with no turns
+    /// played, since those would have to be thrown away.
+    pub fn with_rules(self, rules: RuleSet) -> Self {
+        assert!(
+            self.turns.is_empty() && self.start == Position::new(self.rules()),
+            "rules can only be set on a new grid"
+        );
+        Self::from_position(Position::new(rules))
+    }
+}
+
+#[cfg(feature = "serde")]
+#[derive(Serialize, Deserialize)]
+struct GridRecord {
+    start: Position,
+    turns: Vec<Turn>,
+}
+
+#[cfg(feature = "serde")]
+impl From<Grid> for GridRecord {
+    fn from(grid: Grid) -> Self {
+        Self {
+            start: grid.start,
+            turns: grid.turns,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<GridRecord> for Grid {
+    type Error = UT3Error;
+    fn try_from(record: GridRecord) -> Result<Self, Self::Error> {
+        let mut grid = Grid::from_position(record.start);
+        for turn in record.turns {
+            if turn.turn_number != grid.current_turn_number {
+                return Err(UT3Error::WrongTurnNumber {
+                    expected: grid.current_turn_number,
+                    got: turn.turn_number,
+                });
             }
-        } else {
-            for i in 0..9 {
-                let d1 = i.try_into().unwrap();
-                let b = self.get_box(d1);
-                if b.winner.is_none() && !b.is_full() {
-                    for j in 0..9 {
-                        let d2 = j.try_into().unwrap();
-                        if b.get_tile(d2).is_none() {
-                            boxes.push((d1, d2));
-                        }
-                    }
-                }
+            if turn.player != grid.current_player() {
+                return Err(UT3Error::WrongPlayer {
+                    expected: grid.current_player(),
+                    got: turn.player,
+                });
             }
+            grid.apply_turn(turn.coords)?;
+        }
+        Ok(grid)
+    }
+}
+
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            self.turns
+                .iter()
+                .map(|turn| turn.to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
+        )
+    }
+}
+
+impl Default for Grid {
+    fn default() -> Self {
+        Self::from_position(Position::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Direction::*;
+
+    fn grid_from(rules: RuleSet, moves: &[(Direction, Direction)]) -> Grid {
+        let mut grid = Grid::default().with_rules(rules);
+        for &coords in moves {
+            grid.apply_turn(coords).unwrap();
         }
+        grid
+    }
+
+    fn position(string: &str) -> Grid {
+        Grid::from_position(Position::try_from(string).unwrap())
+    }
 
+    fn valid_boxes(grid: &Grid) -> Vec<Direction> {
+        let mut boxes = grid
+            .get_valid_boxes(grid.get_track())
+            .into_iter()
+            .map(|(outer, _)| outer)
+            .collect::<Vec<_>>();
+        boxes.dedup();
         boxes
     }
 
-    fn update_wins(&mut self) {
-        for mut b in &mut self.inner {
-            b.winner = get_win(&b.inner);
+    #[test]
+    fn undo_restores_everything() {
+        let mut grid = grid_from(RuleSet::RELATIVE, &[(C, C), (C, NW), (NW, C)]);
+        let mut positions = vec![];
+        while !grid.status().is_over() {
+            positions.push(*grid.position());
+            let coords = grid.get_valid_boxes(grid.get_track())[0];
+            grid.apply_turn(coords).unwrap();
+        }
+        while let Some(position) = positions.pop() {
+            grid.undo_turn().unwrap();
+            assert_eq!(*grid.position(), position);
+            assert_eq!(grid.hash(), crate::zobrist::hash(&position));
         }
+        while grid.redo_turn().is_some() {}
+        assert!(grid.status().is_over());
+    }
 
-        self.winner = get_win(
-            &self
-                .inner
-                .iter()
-                .map(|b| b.winner)
-                .collect::<Vec<Option<Player>>>(),
+    #[test]
+    fn a_new_turn_clears_redo() {
+        let mut grid = grid_from(RuleSet::CLASSIC, &[(C, C)]);
+        assert_eq!(grid.undo_turn().map(|turn| turn.coords), Some((C, C)));
+        assert!(grid.undo_turn().is_none());
+        assert!(grid.can_redo());
+        grid.apply_turn((NW, C)).unwrap();
+        assert!(!grid.can_redo());
+        assert!(grid.redo_turn().is_none());
+    }
+
+    #[test]
+    fn illegal_turns() {
+        let mut grid = grid_from(RuleSet::CLASSIC, &[(C, N)]);
+        assert!(matches!(
+            grid.apply_turn((C, S)),
+            Err(UT3Error::WrongTrack {
+                required: N,
+                got: C
+            })
+        ));
+        let mut grid = grid_from(RuleSet::CLASSIC, &[(C, C)]);
+        assert!(matches!(
+            grid.apply_turn((C, C)),
+            Err(UT3Error::PositionTaken {
+                position: (C, C),
+                value: Player::X
+            })
+        ));
+    }
+
+    #[test]
+    fn game_over() {
+        // X is about to win the top row of boxes
+        let mut grid = position(
+            "XXX....../XXX....../XX......./OO......./OO......./OO......./O......../O......../......... XX....... X NE absolute",
         );
+        assert_eq!(grid.status(), GameStatus::Ongoing);
+        grid.apply_turn((NE, NE)).unwrap();
+        assert_eq!(grid.status(), GameStatus::Won(Player::X));
+        assert_eq!(grid.get_track(), None);
+        assert!(grid.get_valid_boxes(None).is_empty());
+        assert!(matches!(
+            grid.apply_turn((E, C)),
+            Err(UT3Error::GameOver(GameStatus::Won(Player::X)))
+        ));
+        grid.undo_turn().unwrap();
+        assert_eq!(grid.status(), GameStatus::Ongoing);
     }
 
-    pub fn with_variant(mut self, variant: Variant) -> Self {
-        self.variant = variant;
-        self
+    #[test]
+    fn rules_are_set_on_a_new_grid() {
+        let grid = Grid::default().with_variant(Variant::Relative);
+        assert_eq!(grid.variant(), Variant::Relative);
+        assert_eq!(grid.with_rules(RuleSet::CLASSIC).rules(), RuleSet::CLASSIC);
     }
-}
 
-impl ToString for Grid {
-    fn to_string(&self) -> String {
-        self.turns
-            .iter()
-            .map(|turn| turn.to_string())
-            .collect::<Vec<_>>()
-            .join("\n")
+    #[test]
+    #[should_panic(expected = "rules can only be set on a new grid")]
+    fn rules_are_not_set_after_a_turn() {
+        let _ = grid_from(RuleSet::CLASSIC, &[(C, C)]).with_variant(Variant::Relative);
     }
-}
 
-impl Default for Grid {
-    fn default() -> Self {
-        Self {
-            winner: None,
-            variant: Variant::Absolute,
-            current_turn_number: 1,
-            turns: Vec::new(),
-            inner: Default::default(), // essentially just [Box::default(); 9], but that would require having Copy on Box, which is probably not good
+    #[test]
+    fn drawn_when_no_line_is_left() {
+        // O fills SE, the last unfinished box, without winning it. Nobody has a line on the grid,
+        // so the game is drawn, even though X won more boxes.
+        let tiles = "XXXOO..../OOO....../XXXO...../OOO....../XXX....../XXX....../OOO....../XOXXOOOXX/XOXXOOOX.";
+        let winners = "XOXOXXO..";
+        let mut grid = position(&format!("{tiles} {winners} O SE absolute"));
+        grid.apply_turn((SE, SE)).unwrap();
+        assert_eq!(grid.status(), GameStatus::Drawn);
+        assert!(grid.get_valid_boxes(None).is_empty());
+    }
+
+    #[test]
+    fn relative_addition_wraps_around() {
+        assert_eq!(NE + E, NW);
+        assert_eq!(SE + SE, NW);
+        assert_eq!(NW + NW, SE);
+        assert_eq!(N + S, C);
+        assert_eq!(W + NW, NE);
+        for dir in Direction::ALL {
+            assert_eq!(dir + C, dir);
+            assert_eq!(C + dir, dir);
         }
+
+        let grid = grid_from(RuleSet::RELATIVE, &[(NE, E)]);
+        assert_eq!(grid.get_track(), Some(NW));
+        let grid = grid_from(RuleSet::CLASSIC, &[(NE, E)]);
+        assert_eq!(grid.get_track(), Some(E));
+    }
+
+    #[test]
+    fn sent_to_won_box() {
+        // X has won NW and O would be sent there
+        let tiles = "XXX.OO.../.O......./........./........./........./........./........./X......../.........";
+        assert!(Position::try_from(format!("{tiles} X........ O NW absolute").as_str()).is_err());
+        let grid = position(&format!("{tiles} X........ O - absolute"));
+        assert_eq!(valid_boxes(&grid).len(), 8);
+        assert!(!valid_boxes(&grid).contains(&NW));
+        let grid = position(&format!("{tiles} X........ O NW absolute+open-boxes"));
+        assert_eq!(valid_boxes(&grid), vec![NW]);
+
+        // Winning a box and then being sent back to it
+        let moves = [(NW, C), (C, NW), (NW, N), (N, NW), (NW, S), (S, NW)];
+        let mut grid = grid_from(RuleSet::CLASSIC, &moves);
+        assert_eq!(grid.get_box(NW).winner, Some(Player::X));
+        assert_eq!(grid.get_track(), None);
+        assert_eq!(valid_boxes(&grid).len(), 8);
+        assert!(matches!(
+            grid.apply_turn((NW, NE)),
+            Err(UT3Error::BoxHasWinner(NW))
+        ));
+
+        let mut grid = grid_from(RuleSet::OPEN_BOXES, &moves);
+        assert_eq!(grid.get_track(), Some(NW));
+        grid.apply_turn((NW, NE)).unwrap();
+        grid.apply_turn((NE, NW)).unwrap();
+        assert_eq!(grid.get_track(), Some(NW));
+        assert_eq!(grid.get_box(NW).winner, Some(Player::X));
+    }
+
+    #[test]
+    fn sent_to_full_box() {
+        // NW is full with no winner, and O is sent there
+        let grid = position(
+            "XOXXOOOXX/........./........./........./........./........./........./........./......... ......... O - absolute",
+        );
+        assert_eq!(grid.get_box(NW).winner, None);
+        assert!(grid.box_is_finished(NW));
+        assert_eq!(valid_boxes(&grid).len(), 8);
+        assert!(Position::try_from(
+            "XOXXOOOXX/........./........./........./........./........./........./........./......... ......... O NW absolute",
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn wild_drawn_boxes() {
+        // Filling SE without a winner completes NW-C-SE for X when drawn boxes count for both
+        let position_string = |rules| {
+            format!("XXX....../OOO....../........./........./XXX....../........./O......../O......../XOXXOOOX. XO..X.... O SE {rules}")
+        };
+        let mut grid = position(&position_string("absolute"));
+        grid.apply_turn((SE, SE)).unwrap();
+        assert_eq!(grid.status(), GameStatus::Ongoing);
+        let mut grid = position(&position_string("absolute+wild-draws"));
+        grid.apply_turn((SE, SE)).unwrap();
+        assert_eq!(grid.status(), GameStatus::Won(Player::X));
+    }
+
+    #[test]
+    fn box_count_decides_draws() {
+        // SE is the last unfinished box, nobody has a line and X won more boxes
+        let tiles = "XXXOO..../OOO....../XXXO...../OOO....../XXX....../XXX....../OOO....../XOXXOOOXX/XOXXOOOX.";
+        let winners = "XOXOXXO..";
+        let mut grid = position(&format!("{tiles} {winners} O SE absolute+box-count"));
+        grid.apply_turn((SE, SE)).unwrap();
+        assert_eq!(grid.status(), GameStatus::Won(Player::X));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn grid_api_from_before_bitboards() {
+        assert_eq!(Turn::new(1, (C, C)).player(), Player::X);
+        assert_eq!(Turn::new(2, (C, C)).player(), Player::O);
+
+        // X wins NW, and with it the grid's copies of the box and the winner change
+        let moves = [(NW, C), (C, NW), (NW, N), (N, NW), (NW, S)];
+        assert_eq!(grid_from(RuleSet::RELATIVE, &[]).variant, Variant::Relative);
+        let mut grid = grid_from(RuleSet::CLASSIC, &moves);
+        assert_eq!(grid.variant, Variant::Absolute);
+        assert_eq!(grid.get_box(NW).winner, Some(Player::X));
+        assert_eq!(*grid.get_box(NW).get_tile(S), Some(Player::X));
+        assert_eq!(*grid.get_box(C).get_tile(NW), Some(Player::O));
+        grid.undo_turn();
+        assert_eq!(grid.get_box(NW).winner, None);
+        assert_eq!(*grid.get_box(NW).get_tile(S), None);
+
+        let mut grid = position(
+            "XXX....../XXX....../XX......./OO......./OO......./OO......./O......../O......../......... XX....... X NE absolute",
+        );
+        assert_eq!(grid.winner, None);
+        grid.apply_turn((NE, NE)).unwrap();
+        assert_eq!(grid.winner, Some(Player::X));
+        assert_eq!(grid.winner, grid.winner());
+        grid.undo_turn();
+        assert_eq!(grid.winner, None);
     }
 }
@@ -0,0 +1,106 @@
+//! JSON for games and positions. A game is its starting position and the turns played from it:
+//!
+//! ```json
+//! {
+//!   "start": "........./........./........./........./........./........./........./........./......... ......... X - absolute",
+//!   "turns": [{ "turn_number": 1, "player": "X", "coords": ["C", "NW"] }]
+//! }
+//! ```
+//!
+//! and a position is a position string.
+
+use crate::bitboard::Position;
+use crate::error::UT3Error;
+use crate::game::Grid;
+
+pub fn grid_to_json(grid: &Grid) -> String {
+    serde_json::to_string_pretty(grid).expect("grids can always be serialized")
+}
+
+/// Replays the turns, so the game has to be legal
+pub fn grid_from_json(json: &str) -> Result<Grid, UT3Error> {
+    Ok(serde_json::from_str(json)?)
+}
+
+pub fn position_to_json(position: &Position) -> String {
+    serde_json::to_string(position).expect("positions can always be serialized")
+}
+
+pub fn position_from_json(json: &str) -> Result<Position, UT3Error> {
+    Ok(serde_json::from_str(json)?)
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use crate::game::{Direction, RuleSet};
+    use Direction::*;
+
+    fn grid() -> Grid {
+        let mut grid = Grid::default().with_rules(RuleSet::CLASSIC);
+        for coords in [(C, N), (N, C), (C, NW)] {
+            grid.apply_turn(coords).unwrap();
+        }
+        grid
+    }
+
+    #[test]
+    fn grid_round_trip() {
+        let grid = grid();
+        let copy = grid_from_json(&grid_to_json(&grid)).unwrap();
+        assert_eq!(copy.start(), grid.start());
+        assert_eq!(copy.to_string(), grid.to_string());
+        assert_eq!(copy.position(), grid.position());
+
+        // From a position part way through a game
+        let mut later = Grid::from_position(*grid.position());
+        later.apply_turn((NW, C)).unwrap();
+        let copy = grid_from_json(&grid_to_json(&later)).unwrap();
+        assert_eq!(copy.start(), grid.position());
+        assert_eq!(copy.current_turn_number, 5);
+        assert_eq!(copy.position(), later.position());
+    }
+
+    #[test]
+    fn position_round_trip() {
+        for position in [Position::default(), *grid().position()] {
+            assert_eq!(
+                position_from_json(&position_to_json(&position)).unwrap(),
+                position
+            );
+        }
+        assert!(position_from_json("\"not a position\"").is_err());
+    }
+
+    // A game from the empty grid with `turns` as its JSON turn objects
+    fn game_json(turns: &[&str]) -> String {
+        format!(
+            r#"{{ "start": "{}", "turns": [{}] }}"#,
+            Position::default(),
+            turns.join(", ")
+        )
+    }
+
+    #[test]
+    fn illegal_turns_are_rejected() {
+        let first = r#"{ "turn_number": 1, "player": "X", "coords": ["C", "N"] }"#;
+        assert!(grid_from_json(&game_json(&[
+            first,
+            r#"{ "turn_number": 2, "player": "O", "coords": ["N", "C"] }"#
+        ]))
+        .is_ok());
+
+        for second in [
+            // Sent to the north box
+            r#"{ "turn_number": 2, "player": "O", "coords": ["S", "C"] }"#,
+            r#"{ "turn_number": 3, "player": "O", "coords": ["N", "C"] }"#,
+            r#"{ "turn_number": 2, "player": "X", "coords": ["N", "C"] }"#,
+        ] {
+            assert!(
+                grid_from_json(&game_json(&[first, second])).is_err(),
+                "{second}"
+            );
+        }
+        assert!(grid_from_json(&game_json(&[first, first])).is_err());
+    }
+}
@@ -1,5 +1,30 @@
+#[cfg(feature = "gui")]
 mod app;
+#[cfg(feature = "gui")]
 pub use app::App;
 
-mod game;
-mod error;
+pub mod bitboard;
+pub mod engine;
+pub mod error;
+pub mod eval;
+pub mod game;
+#[cfg(feature = "serde")]
+pub mod json;
+pub mod mate;
+pub mod mcts;
+pub mod nn;
+pub mod notation;
+pub mod perft;
+pub mod pgn;
+pub mod protocol;
+pub mod rng;
+pub mod search;
+pub mod selfplay;
+pub mod solver;
+pub mod symmetry;
+pub mod tablebase;
+pub mod tournament;
+pub mod train;
+pub mod tree;
+pub mod tune;
+pub mod zobrist;
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::bitboard::{Move, Moves, Position};
+use crate::game::{GameStatus, Grid, Player};
+
+/// A proof that the attacker wins: a move for them, and how to go on winning after every reply
+/// that doesn't already lose. `replies` is empty when `mv` itself wins.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct WinTree {
+    pub mv: Move,
+    pub replies: Replies,
+}
+
+/// Each reply with how the attacker goes on to win, or `None` if the reply loses straight away
+pub type Replies = Vec<(Move, Option<WinTree>)>;
+
+impl WinTree {
+    /// The most moves the attacker needs to win, counting `mv`
+    pub fn moves_to_win(&self) -> u32 {
+        1 + self
+            .replies
+            .iter()
+            .filter_map(|(_, tree)| tree.as_ref().map(WinTree::moves_to_win))
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// How many positions the proof has to show
+    pub fn size(&self) -> usize {
+        1 + self
+            .replies
+            .iter()
+            .map(|(_, tree)| 1 + tree.as_ref().map_or(0, WinTree::size))
+            .sum::<usize>()
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>, indent: usize) -> fmt::Result {
+        writeln!(f, "{:indent$}{}", "", self.mv)?;
+        for (reply, tree) in &self.replies {
+            match tree {
+                Some(tree) => {
+                    writeln!(f, "{:width$}{reply}", "", width = indent + 2)?;
+                    tree.write(f, indent + 4)?;
+                }
+                None => writeln!(f, "{:width$}{reply} (loses)", "", width = indent + 2)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// One line per move, each reply indented under the move it answers and each answer under that
+impl fmt::Display for WinTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, 0)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum MateResult {
+    /// The side to move wins, in as few moves as possible
+    Win(WinTree),
+    /// The side to move can't force a win within the number of moves
+    NoWin,
+    Cancelled,
+}
+
+/// How far a search has got, readable from other threads while it runs
+#[derive(Default, Debug)]
+pub struct Progress {
+    /// The number of attacker moves currently being tried
+    pub moves: AtomicU32,
+    pub nodes: AtomicU64,
+}
+
+// Returned up the search once the stop flag is set
+struct Cancelled;
+
+/// Finds forced wins by proving, for every reply, that the attacker still wins. Positions where
+/// the attacker is known not to win within some number of moves are remembered, so each search
+/// for one move more mostly only has to look at new positions.
+pub struct MateFinder {
+    stop: Arc<AtomicBool>,
+    progress: Arc<Progress>,
+    // The most attacker moves a position is known not to be won in, keyed by hash
+    no_win: HashMap<u64, u32>,
+    attacker: Player,
+}
+
+impl MateFinder {
+    pub fn new() -> Self {
+        Self {
+            stop: Arc::new(AtomicBool::new(false)),
+            progress: Arc::new(Progress::default()),
+            no_win: HashMap::new(),
+            attacker: Player::X,
+        }
+    }
+
+    /// Setting this flag from another thread makes the search return `MateResult::Cancelled`
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    pub fn progress(&self) -> Arc<Progress> {
+        self.progress.clone()
+    }
+
+    /// Looks for a way for the player to move in `grid` to win within `moves` of their own moves
+    pub fn find(&mut self, grid: &Grid, moves: u32) -> MateResult {
+        self.find_position(grid.position(), moves)
+    }
+
+    pub fn find_position(&mut self, position: &Position, moves: u32) -> MateResult {
+        self.stop.store(false, Ordering::Relaxed);
+        self.progress.nodes.store(0, Ordering::Relaxed);
+        self.no_win.clear();
+        self.attacker = position.side_to_move();
+
+        let mut position = *position;
+        if position.status().is_over() {
+            return MateResult::NoWin;
+        }
+        // Trying the shorter wins first means the first win found is the quickest
+        for n in 1..=moves {
+            self.progress.moves.store(n, Ordering::Relaxed);
+            match self.attack(&mut position, n) {
+                Ok(Some(tree)) => return MateResult::Win(tree),
+                Ok(None) => (),
+                Err(Cancelled) => return MateResult::Cancelled,
+            }
+        }
+        MateResult::NoWin
+    }
+
+    // The attacker is to move and has `n` moves left to win
+    fn attack(&mut self, position: &mut Position, n: u32) -> Result<Option<WinTree>, Cancelled> {
+        if n == 0
+            || self
+                .no_win
+                .get(&position.hash())
+                .is_some_and(|&max| max >= n)
+        {
+            return Ok(None);
+        }
+        self.progress.nodes.fetch_add(1, Ordering::Relaxed);
+        if self.stop.load(Ordering::Relaxed) {
+            return Err(Cancelled);
+        }
+
+        // Moves that win a box are the likeliest to lead anywhere
+        let moves = position.legal_moves();
+        let wins = moves
+            .filter(|&mv| position.wins_box(mv))
+            .fold(0, |mask, mv| mask | 1 << mv.index());
+        let ordered = Moves::from_mask(wins).chain(Moves::from_mask(moves.mask() & !wins));
+        for mv in ordered {
+            let undo = position.play(mv);
+            let result = match position.status() {
+                GameStatus::Won(player) if player == self.attacker => Ok(Some(Vec::new())),
+                GameStatus::Ongoing => self.defend(position, n - 1),
+                _ => Ok(None),
+            };
+            position.undo(mv, undo);
+            if let Some(replies) = result? {
+                return Ok(Some(WinTree { mv, replies }));
+            }
+        }
+
+        self.no_win.insert(position.hash(), n);
+        Ok(None)
+    }
+
+    // The defender is to move and the attacker has `n` moves left after this. Every reply has to
+    // lose for the attacker to win.
+    fn defend(&mut self, position: &mut Position, n: u32) -> Result<Option<Replies>, Cancelled> {
+        let mut replies = Vec::new();
+        for reply in position.legal_moves() {
+            let undo = position.play(reply);
+            let result = match position.status() {
+                // With wild drawn boxes a reply can complete the attacker's line
+                GameStatus::Won(player) if player == self.attacker => Ok(Some(None)),
+                GameStatus::Ongoing => self.attack(position, n).map(|tree| tree.map(Some)),
+                _ => Ok(None),
+            };
+            position.undo(reply, undo);
+            match result? {
+                Some(tree) => replies.push((reply, tree)),
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(replies))
+    }
+}
+
+impl Default for MateFinder {
+    fn default() -> Self {
+        MateFinder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Direction::*;
+
+    // X sends O to S with SW/S, where O has to send X to NE or let them play anywhere, and X
+    // then wins with NE/NE
+    const MATE_IN_2: &str = "XXXOO..../XXXOO..../XX.OO..../O......../........./OO......./\
+                             ........./.X.OXOXOX/......... XX....... X SW absolute";
+
+    // Checks that `tree` wins for the side to move in `position` within `moves` of their moves,
+    // whatever the defender does
+    fn assert_wins(position: &Position, tree: &WinTree, moves: u32) {
+        assert!(moves > 0, "the win takes too long");
+        let attacker = position.side_to_move();
+        let mut position = *position;
+        assert!(position.legal_moves().contains(tree.mv));
+        position.play(tree.mv);
+        if position.status() == GameStatus::Won(attacker) {
+            assert!(tree.replies.is_empty());
+            return;
+        }
+        assert_eq!(position.status(), GameStatus::Ongoing);
+
+        let replies = tree.replies.iter().map(|&(reply, _)| reply);
+        assert!(
+            replies.eq(position.legal_moves()),
+            "not every reply is answered"
+        );
+        for (reply, answer) in &tree.replies {
+            let mut after = position;
+            after.play(*reply);
+            match answer {
+                Some(answer) => assert_wins(&after, answer, moves - 1),
+                None => assert_eq!(after.status(), GameStatus::Won(attacker)),
+            }
+        }
+    }
+
+    #[test]
+    fn finds_forced_wins() {
+        let position = Position::try_from(MATE_IN_2).unwrap();
+        let tree = match MateFinder::new().find_position(&position, 4) {
+            MateResult::Win(tree) => tree,
+            other => panic!("expected a win, got {other:?}"),
+        };
+        assert_eq!(tree.mv, Move::new((SW, S)));
+        assert_eq!(tree.moves_to_win(), 2);
+        assert_wins(&position, &tree, 2);
+
+        let mut position = position;
+        position.play(Move::new((SW, S)));
+        position.play(Move::new((S, NW)));
+        match MateFinder::new().find_position(&position, 1) {
+            MateResult::Win(tree) => assert_wins(&position, &tree, 1),
+            other => panic!("expected a win, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn no_win_where_there_is_none() {
+        let position = Position::try_from(MATE_IN_2).unwrap();
+        assert_eq!(
+            MateFinder::new().find_position(&position, 1),
+            MateResult::NoWin
+        );
+        assert_eq!(
+            MateFinder::new().find_position(&Position::default(), 3),
+            MateResult::NoWin
+        );
+    }
+
+    #[test]
+    fn stop_flag_cancels() {
+        let mut finder = MateFinder::new();
+        let stop = finder.stop_flag();
+        let progress = finder.progress();
+        let stopper = std::thread::spawn(move || {
+            while progress.nodes.load(Ordering::Relaxed) < 1_000 {
+                std::thread::yield_now();
+            }
+            stop.store(true, Ordering::Relaxed);
+        });
+        // Far too many moves to finish from the start
+        assert_eq!(
+            finder.find_position(&Position::default(), 20),
+            MateResult::Cancelled
+        );
+        stopper.join().unwrap();
+    }
+}
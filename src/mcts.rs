@@ -0,0 +1,424 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::bitboard::{Move, Moves, Position};
+use crate::eval::{win_probability, Evaluator, Heuristic};
+use crate::game::{GameStatus, Grid, Player};
+use crate::rng::Rng;
+
+/// How the rest of a game is played out from a new node
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Playout {
+    /// Uniformly random moves
+    Random,
+    /// Random moves, but always taking a box when possible and trying not to give the opponent a
+    /// free choice of box or a box they can win straight away
+    Heuristic,
+    /// No playout at all, just the evaluator's score for the new node as a chance of winning
+    Evaluation,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MctsConfig {
+    /// The `c` in UCT. Higher values explore less visited moves more.
+    pub exploration: f64,
+    pub playout: Playout,
+    /// Whether to guide the search with the evaluator's policy, for evaluators that have one.
+    /// Moves are then expanded most likely first, and picked by PUCT rather than UCT.
+    pub priors: bool,
+    pub seed: u64,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        Self {
+            exploration: std::f64::consts::SQRT_2,
+            playout: Playout::Heuristic,
+            priors: false,
+            seed: 0,
+        }
+    }
+}
+
+/// How long to search for. A time budget needs a clock, which isn't available on the web.
+#[derive(Copy, Clone, Debug)]
+pub enum Budget {
+    Iterations(u64),
+    Time(Duration),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct MoveStats {
+    pub mv: Move,
+    pub visits: u64,
+    /// The expected result for the side to move after playing `mv`, counting a draw as half a win
+    pub win_rate: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct MctsResult {
+    /// The most visited move, `None` only if the game is already over
+    pub best_move: Option<Move>,
+    /// Every move at the root, most visited first
+    pub moves: Vec<MoveStats>,
+    pub iterations: u64,
+}
+
+struct Node {
+    mv: Option<Move>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    // Legal moves that don't have a child yet
+    untried: u128,
+    visits: u64,
+    // The total result for the player who played `mv`
+    value: f64,
+    // The chance the parent's policy gave `mv`
+    prior: f32,
+    // The evaluator's policy for this node, when using priors
+    policy: Option<Box<[f32; 81]>>,
+}
+
+/// Monte Carlo tree search with UCT. Each iteration walks down the tree picking the child with
+/// the best upper confidence bound (PUCT with `MctsConfig::priors`), adds one new child, plays
+/// the game out from there and counts the result back up the path.
+pub struct Mcts<E: Evaluator = Heuristic> {
+    pub config: MctsConfig,
+    /// Only used with `Playout::Evaluation` or `MctsConfig::priors`
+    pub evaluator: E,
+    rng: Rng,
+    stop: Arc<AtomicBool>,
+    nodes: Vec<Node>,
+}
+
+impl Mcts {
+    pub fn new(config: MctsConfig) -> Self {
+        Self::with_evaluator(config, Heuristic::default())
+    }
+}
+
+impl<E: Evaluator> Mcts<E> {
+    pub fn with_evaluator(config: MctsConfig, evaluator: E) -> Self {
+        Self {
+            config,
+            evaluator,
+            rng: Rng::new(config.seed),
+            stop: Arc::new(AtomicBool::new(false)),
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Setting this flag from another thread makes the search return after the current iteration
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    pub fn search_grid(&mut self, grid: &Grid, budget: Budget) -> MctsResult {
+        self.search(grid.position(), budget)
+    }
+
+    pub fn search(&mut self, position: &Position, budget: Budget) -> MctsResult {
+        self.stop.store(false, Ordering::Relaxed);
+        let deadline = match budget {
+            Budget::Time(time) => Some(Instant::now() + time),
+            Budget::Iterations(_) => None,
+        };
+
+        self.nodes.clear();
+        self.nodes.push(Node {
+            mv: None,
+            parent: None,
+            children: Vec::new(),
+            untried: position.legal_moves().mask(),
+            visits: 0,
+            value: 0.0,
+            prior: 1.0,
+            policy: self.policy(position),
+        });
+
+        let mut iterations = 0;
+        if !position.status().is_over() {
+            loop {
+                match budget {
+                    Budget::Iterations(max) if iterations >= max => break,
+                    // Checking the clock is comparatively slow, so only do it every so often
+                    Budget::Time(_)
+                        if iterations.is_multiple_of(64)
+                            && deadline.is_some_and(|deadline| Instant::now() >= deadline) =>
+                    {
+                        break
+                    }
+                    _ => (),
+                }
+                if self.stop.load(Ordering::Relaxed) {
+                    break;
+                }
+                self.iterate(*position);
+                iterations += 1;
+            }
+        }
+
+        let mut moves = self.nodes[0]
+            .children
+            .iter()
+            .map(|&child| {
+                let node = &self.nodes[child];
+                MoveStats {
+                    mv: node.mv.unwrap(),
+                    visits: node.visits,
+                    win_rate: if node.visits == 0 {
+                        0.0
+                    } else {
+                        node.value / node.visits as f64
+                    },
+                }
+            })
+            .collect::<Vec<_>>();
+        moves.sort_by_key(|stats| std::cmp::Reverse(stats.visits));
+
+        MctsResult {
+            best_move: moves
+                .first()
+                .map(|stats| stats.mv)
+                .or_else(|| position.legal_moves().next()),
+            moves,
+            iterations,
+        }
+    }
+
+    fn iterate(&mut self, mut position: Position) {
+        // Selection
+        let mut idx = 0;
+        while self.nodes[idx].untried == 0 && !self.nodes[idx].children.is_empty() {
+            idx = self.select(idx);
+            position.play(self.nodes[idx].mv.unwrap());
+        }
+
+        // Expansion
+        let untried = Moves::from_mask(self.nodes[idx].untried);
+        let expand = match &self.nodes[idx].policy {
+            Some(policy) => untried.max_by(|a, b| policy[a.index()].total_cmp(&policy[b.index()])),
+            None => self.rng.choose(untried),
+        };
+        if let Some(mv) = expand {
+            self.nodes[idx].untried &= !(1 << mv.index());
+            let prior = self.nodes[idx]
+                .policy
+                .as_ref()
+                .map_or(1.0, |policy| policy[mv.index()]);
+            position.play(mv);
+            let child = self.nodes.len();
+            self.nodes.push(Node {
+                mv: Some(mv),
+                parent: Some(idx),
+                children: Vec::new(),
+                untried: position.legal_moves().mask(),
+                visits: 0,
+                value: 0.0,
+                prior,
+                policy: self.policy(&position),
+            });
+            self.nodes[idx].children.push(child);
+            idx = child;
+        }
+
+        // Simulation
+        let mover = position.side_to_move().opponent();
+        let mut result = self.playout(&mut position, mover);
+
+        // Backpropagation, alternating who the result is for
+        let mut node = Some(idx);
+        while let Some(idx) = node {
+            let node_ref = &mut self.nodes[idx];
+            node_ref.visits += 1;
+            node_ref.value += result;
+            result = 1.0 - result;
+            node = node_ref.parent;
+        }
+    }
+
+    fn select(&self, idx: usize) -> usize {
+        let parent = &self.nodes[idx];
+        let bound = |child: usize| match parent.policy {
+            Some(_) => self.puct(child, parent.visits),
+            None => self.uct(child, parent.visits),
+        };
+        *parent
+            .children
+            .iter()
+            .max_by(|&&a, &&b| bound(a).partial_cmp(&bound(b)).unwrap())
+            .unwrap()
+    }
+
+    fn uct(&self, idx: usize, parent_visits: u64) -> f64 {
+        let node = &self.nodes[idx];
+        let visits = node.visits as f64;
+        node.value / visits
+            + self.config.exploration * ((parent_visits as f64).ln() / visits).sqrt()
+    }
+
+    fn puct(&self, idx: usize, parent_visits: u64) -> f64 {
+        let node = &self.nodes[idx];
+        let visits = node.visits as f64;
+        node.value / visits
+            + self.config.exploration * node.prior as f64 * (parent_visits as f64).sqrt()
+                / (1.0 + visits)
+    }
+
+    fn policy(&self, position: &Position) -> Option<Box<[f32; 81]>> {
+        if !self.config.priors || position.status().is_over() {
+            return None;
+        }
+        self.evaluator.policy(position).map(Box::new)
+    }
+
+    // The result of the rest of the game for `player`
+    fn playout(&mut self, position: &mut Position, player: Player) -> f64 {
+        loop {
+            let status = position.status();
+            if status.is_over() {
+                return score_for(status, player);
+            }
+            let mv = match self.config.playout {
+                Playout::Random => self.rng.choose(position.legal_moves()).unwrap(),
+                Playout::Heuristic => self.heuristic_move(position),
+                Playout::Evaluation => {
+                    let result = win_probability(self.evaluator.evaluate(position));
+                    return if position.side_to_move() == player {
+                        result
+                    } else {
+                        1.0 - result
+                    };
+                }
+            };
+            position.play(mv);
+        }
+    }
+
+    // How many random moves to try before giving up on finding a safe one
+    const TRIES: usize = 4;
+
+    fn heuristic_move(&mut self, position: &mut Position) -> Move {
+        let moves = position.legal_moves();
+        if let Some(mv) = moves.filter(|&mv| position.wins_box(mv)).last() {
+            return mv;
+        }
+
+        let mut mv = self.rng.choose(moves).unwrap();
+        for _ in 0..Self::TRIES {
+            if is_safe(position, mv) {
+                break;
+            }
+            mv = self.rng.choose(moves).unwrap();
+        }
+        mv
+    }
+}
+
+impl Default for Mcts {
+    fn default() -> Self {
+        Mcts::new(MctsConfig::default())
+    }
+}
+
+// Whether `mv` keeps the opponent to a box they can't win on their next move
+fn is_safe(position: &mut Position, mv: Move) -> bool {
+    let undo = position.play(mv);
+    let safe = position.status().is_over()
+        || (position.track().is_some()
+            && !position.legal_moves().any(|reply| position.wins_box(reply)));
+    position.undo(mv, undo);
+    safe
+}
+
+// The result of `status` for `player`, counting a draw as half a win
+fn score_for(status: GameStatus, player: Player) -> f64 {
+    match status {
+        GameStatus::Won(winner) if winner == player => 1.0,
+        GameStatus::Won(_) => 0.0,
+        _ => 0.5,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Direction::*;
+
+    // X has won NW and N, and wins NE and the game with NE/NE
+    const MATE_IN_1: &str = "XXXOO..../XXXOO..../XX.OO..../........./OO......./........./\
+                             ........./........./......... XX....... X NE absolute";
+
+    #[test]
+    fn iterations_budget() {
+        let position = Position::default();
+        for playout in [Playout::Random, Playout::Heuristic, Playout::Evaluation] {
+            let mut mcts = Mcts::new(MctsConfig {
+                playout,
+                ..MctsConfig::default()
+            });
+            let result = mcts.search(&position, Budget::Iterations(300));
+            assert_eq!(result.iterations, 300);
+            assert_eq!(
+                result.moves.iter().map(|stats| stats.visits).sum::<u64>(),
+                300
+            );
+            assert!(result
+                .moves
+                .windows(2)
+                .all(|pair| pair[0].visits >= pair[1].visits));
+            assert!(result
+                .moves
+                .iter()
+                .all(|stats| (0.0..=1.0).contains(&stats.win_rate)));
+            assert_eq!(result.best_move, Some(result.moves[0].mv));
+        }
+    }
+
+    #[test]
+    fn takes_a_winning_move() {
+        let position = Position::try_from(MATE_IN_1).unwrap();
+        for playout in [Playout::Random, Playout::Heuristic] {
+            let mut mcts = Mcts::new(MctsConfig {
+                playout,
+                ..MctsConfig::default()
+            });
+            let result = mcts.search(&position, Budget::Iterations(500));
+            assert_eq!(result.best_move, Some(Move::new((NE, NE))), "{playout:?}");
+            assert_eq!(result.moves[0].win_rate, 1.0);
+        }
+    }
+
+    #[test]
+    fn finished_games_have_no_move() {
+        let mut position = Position::try_from(MATE_IN_1).unwrap();
+        position.play(Move::new((NE, NE)));
+        let result = Mcts::default().search(&position, Budget::Iterations(100));
+        assert_eq!(result.best_move, None);
+        assert!(result.moves.is_empty());
+        assert_eq!(result.iterations, 0);
+    }
+
+    #[test]
+    fn same_seed_same_result() {
+        let mut position = Position::new(crate::game::RuleSet::RELATIVE);
+        position.play(Move::new((C, NE)));
+        let search = |seed| {
+            Mcts::new(MctsConfig {
+                seed,
+                ..MctsConfig::default()
+            })
+            .search(&position, Budget::Iterations(400))
+        };
+        let summary = |result: MctsResult| {
+            result
+                .moves
+                .iter()
+                .map(|stats| (stats.mv, stats.visits, stats.win_rate))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(summary(search(7)), summary(search(7)));
+        assert_ne!(summary(search(7)), summary(search(8)));
+    }
+}
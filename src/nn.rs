@@ -0,0 +1,452 @@
+//! A small fully connected neural network for evaluating positions, run on the CPU.
+//!
+//! The input is `INPUTS` features of the position, from the point of view of the side to move
+//! (see `features`). Hidden layers use ReLU. The value head is a single `tanh` output, the
+//! expected result for the side to move from -1 (a loss) to 1 (a win), and the optional policy
+//! head has a logit for each of the 81 tiles, which is turned into a distribution over the legal
+//! moves with a softmax.
+//!
+//! A network file is little endian, and laid out as:
+//!
+//! | bytes    | contents                                                        |
+//! |----------|-----------------------------------------------------------------|
+//! | 4        | `UT3N`                                                          |
+//! | 1        | format version, currently 1                                     |
+//! | 4        | number of inputs (`u32`), currently always `INPUTS`             |
+//! | 1        | number of hidden layers                                         |
+//! | 4 each   | size of each hidden layer (`u32`)                               |
+//! | 1        | 1 if there is a policy head, 0 otherwise                        |
+//! | 4 each   | the layers' parameters (`f32`)                                  |
+//!
+//! The layers come in order: the hidden layers, then the value head, then the policy head. Each
+//! is its weights, one row of inputs for each output, followed by a bias for each output. Hidden
+//! layers have at most `MAX_LAYER_SIZE` outputs, and the file ends after the last layer.
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use crate::bitboard::Position;
+use crate::error::UT3Error;
+use crate::eval::{Evaluator, MAX_SCORE, SCALE};
+use crate::game::Grid;
+use crate::rng::Rng;
+
+const MAGIC: &[u8; 4] = b"UT3N";
+const VERSION: u8 = 1;
+
+/// The number of input features: the side to move's tiles and then the opponent's (81 each),
+/// the boxes each of them has won (9 each), and the boxes the side to move may play in (9)
+pub const INPUTS: usize = 189;
+/// The most outputs a hidden layer in a file can have, so that a bad file can't make `read`
+/// allocate huge layers
+pub const MAX_LAYER_SIZE: usize = 4096;
+
+/// The input features of `position`, see `INPUTS`. Tiles and boxes are numbered like
+/// `Move::index` and `Direction::index`.
+pub fn features(position: &Position) -> [f32; INPUTS] {
+    let mut features = [0.0; INPUTS];
+    let side = position.side_to_move();
+    for (offset, player) in [(0, side), (81, side.opponent())] {
+        let tiles = position.tiles(player);
+        for idx in 0..81 {
+            if tiles & (1 << idx) != 0 {
+                features[offset + idx] = 1.0;
+            }
+        }
+    }
+    for (offset, player) in [(162, side), (171, side.opponent())] {
+        let boxes = position.box_winners(player);
+        for idx in 0..9 {
+            if boxes & (1 << idx) != 0 {
+                features[offset + idx] = 1.0;
+            }
+        }
+    }
+    for mv in position.legal_moves() {
+        features[180 + mv.index() / 9] = 1.0;
+    }
+    features
+}
+
+pub fn grid_features(grid: &Grid) -> [f32; INPUTS] {
+    features(grid.position())
+}
+
+/// A fully connected layer
+#[derive(Clone, Debug)]
+pub struct Layer {
+    pub(crate) inputs: usize,
+    pub(crate) outputs: usize,
+    /// One row of `inputs` weights for each output
+    pub(crate) weights: Vec<f32>,
+    pub(crate) biases: Vec<f32>,
+}
+
+impl Layer {
+    pub(crate) fn zeros(inputs: usize, outputs: usize) -> Self {
+        Self {
+            inputs,
+            outputs,
+            weights: vec![0.0; inputs * outputs],
+            biases: vec![0.0; outputs],
+        }
+    }
+
+    /// A layer with random weights, scaled so that activations keep about the same size through
+    /// ReLU layers
+    fn random(inputs: usize, outputs: usize, rng: &mut Rng) -> Self {
+        let limit = (6.0 / inputs as f64).sqrt();
+        Self {
+            inputs,
+            outputs,
+            weights: (0..inputs * outputs)
+                .map(|_| ((rng.next_f64() * 2.0 - 1.0) * limit) as f32)
+                .collect(),
+            biases: vec![0.0; outputs],
+        }
+    }
+
+    pub(crate) fn forward(&self, input: &[f32], output: &mut Vec<f32>) {
+        output.clear();
+        output.extend(
+            self.weights
+                .chunks_exact(self.inputs)
+                .zip(&self.biases)
+                .map(|(row, bias)| bias + row.iter().zip(input).map(|(w, x)| w * x).sum::<f32>()),
+        );
+    }
+
+    fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        for value in self.weights.iter().chain(&self.biases) {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    // Takes the weights and then the biases from `values`, like `write` writes them
+    fn from_values(inputs: usize, outputs: usize, values: &mut impl Iterator<Item = f32>) -> Self {
+        Self {
+            inputs,
+            outputs,
+            weights: values.take(inputs * outputs).collect(),
+            biases: values.take(outputs).collect(),
+        }
+    }
+}
+
+/// What the network thinks of a position
+#[derive(Clone, Debug)]
+pub struct Output {
+    /// The expected result for the side to move, from -1 to 1
+    pub value: f32,
+    /// The chance of each move (by `Move::index`) being the best, or `None` without a policy
+    /// head. Illegal moves get 0.
+    pub policy: Option<[f32; 81]>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Network {
+    pub(crate) hidden: Vec<Layer>,
+    pub(crate) value: Layer,
+    pub(crate) policy: Option<Layer>,
+}
+
+impl Network {
+    /// A network with random weights and hidden layers of the given sizes
+    pub fn new(hidden: &[usize], policy: bool, rng: &mut Rng) -> Self {
+        let mut inputs = INPUTS;
+        let hidden = hidden
+            .iter()
+            .map(|&outputs| {
+                let layer = Layer::random(inputs, outputs, rng);
+                inputs = outputs;
+                layer
+            })
+            .collect();
+        Self {
+            hidden,
+            value: Layer::random(inputs, 1, rng),
+            policy: policy.then(|| Layer::random(inputs, 81, rng)),
+        }
+    }
+
+    pub fn hidden_sizes(&self) -> Vec<usize> {
+        self.hidden.iter().map(|layer| layer.outputs).collect()
+    }
+
+    pub fn has_policy(&self) -> bool {
+        self.policy.is_some()
+    }
+
+    /// Every layer, in the order they're stored in a file
+    pub(crate) fn layers(&self) -> impl Iterator<Item = &Layer> {
+        self.hidden.iter().chain([&self.value]).chain(&self.policy)
+    }
+
+    pub(crate) fn layers_mut(&mut self) -> impl Iterator<Item = &mut Layer> {
+        self.hidden
+            .iter_mut()
+            .chain([&mut self.value])
+            .chain(&mut self.policy)
+    }
+
+    /// The last hidden layer's activations, which both heads read from
+    fn body(&self, features: &[f32]) -> Vec<f32> {
+        let mut input = features.to_vec();
+        let mut output = Vec::new();
+        for layer in &self.hidden {
+            layer.forward(&input, &mut output);
+            output.iter_mut().for_each(|x| *x = x.max(0.0));
+            std::mem::swap(&mut input, &mut output);
+        }
+        input
+    }
+
+    pub fn run(&self, position: &Position) -> Output {
+        let body = self.body(&features(position));
+        let mut output = Vec::new();
+        self.value.forward(&body, &mut output);
+        let value = output[0].tanh();
+
+        let policy = self.policy.as_ref().map(|head| {
+            head.forward(&body, &mut output);
+            masked_softmax(&output, position)
+        });
+        Output { value, policy }
+    }
+
+    pub fn run_grid(&self, grid: &Grid) -> Output {
+        self.run(grid.position())
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&(INPUTS as u32).to_le_bytes())?;
+        writer.write_all(&[self.hidden.len() as u8])?;
+        for layer in &self.hidden {
+            writer.write_all(&(layer.outputs as u32).to_le_bytes())?;
+        }
+        writer.write_all(&[self.policy.is_some() as u8])?;
+        for layer in self.layers() {
+            layer.write(writer)?;
+        }
+        Ok(())
+    }
+
+    pub fn read(reader: &mut impl Read) -> Result<Self, UT3Error> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(UT3Error::InvalidFile("not a network".to_string()));
+        }
+        let mut byte = [0; 1];
+        let mut word = [0; 4];
+        reader.read_exact(&mut byte)?;
+        if byte[0] != VERSION {
+            return Err(UT3Error::InvalidFile(format!(
+                "unsupported network version {}",
+                byte[0]
+            )));
+        }
+        reader.read_exact(&mut word)?;
+        if u32::from_le_bytes(word) as usize != INPUTS {
+            return Err(UT3Error::InvalidFile(format!(
+                "networks need {INPUTS} inputs, not {}",
+                u32::from_le_bytes(word)
+            )));
+        }
+
+        reader.read_exact(&mut byte)?;
+        let mut sizes = Vec::new();
+        for _ in 0..byte[0] {
+            reader.read_exact(&mut word)?;
+            match u32::from_le_bytes(word) as usize {
+                0 => return Err(UT3Error::InvalidFile("empty hidden layer".to_string())),
+                size if size > MAX_LAYER_SIZE => {
+                    return Err(UT3Error::InvalidFile(format!(
+                        "hidden layer of {size}, more than {MAX_LAYER_SIZE}"
+                    )))
+                }
+                size => sizes.push(size),
+            }
+        }
+        reader.read_exact(&mut byte)?;
+        let has_policy = byte[0] != 0;
+
+        // The inputs and outputs of every layer, in file order
+        let mut shapes = Vec::new();
+        let mut inputs = INPUTS;
+        for size in sizes {
+            shapes.push((inputs, size));
+            inputs = size;
+        }
+        shapes.push((inputs, 1));
+        if has_policy {
+            shapes.push((inputs, 81));
+        }
+
+        // Only as much is read (and allocated) as the file really has, before checking that it is
+        // the size the layers need
+        let expected = shapes
+            .iter()
+            .map(|&(inputs, outputs)| 4 * (inputs as u64 + 1) * outputs as u64)
+            .sum::<u64>();
+        let mut bytes = Vec::new();
+        reader.by_ref().take(expected).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 != expected || reader.read(&mut byte)? != 0 {
+            return Err(UT3Error::InvalidFile(format!(
+                "the layers should take {expected} bytes"
+            )));
+        }
+
+        let mut values = bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()));
+        let mut layers = shapes
+            .into_iter()
+            .map(|(inputs, outputs)| Layer::from_values(inputs, outputs, &mut values))
+            .collect::<Vec<_>>();
+        let policy = has_policy.then(|| layers.pop().unwrap());
+        let value = layers.pop().unwrap();
+        Ok(Self {
+            hidden: layers,
+            value,
+            policy,
+        })
+    }
+
+    /// Reads a network file, see `Network`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, UT3Error> {
+        Self::read(&mut io::BufReader::new(std::fs::File::open(path)?))
+    }
+}
+
+/// A softmax of `logits` over just the legal moves of `position`
+pub(crate) fn masked_softmax(logits: &[f32], position: &Position) -> [f32; 81] {
+    let moves = position.legal_moves();
+    let max = moves
+        .map(|mv| logits[mv.index()])
+        .fold(f32::NEG_INFINITY, f32::max);
+    let mut policy = [0.0; 81];
+    let mut total = 0.0;
+    for mv in moves {
+        policy[mv.index()] = (logits[mv.index()] - max).exp();
+        total += policy[mv.index()];
+    }
+    if total > 0.0 {
+        policy.iter_mut().for_each(|p| *p /= total);
+    }
+    policy
+}
+
+/// Scores positions by the value head, on the same scale as `eval::win_probability`
+impl Evaluator for Network {
+    fn evaluate(&self, position: &Position) -> i32 {
+        let probability = ((self.run(position).value as f64 + 1.0) / 2.0).clamp(1e-9, 1.0 - 1e-9);
+        (SCALE * (probability / (1.0 - probability)).ln())
+            .clamp(-MAX_SCORE as f64, MAX_SCORE as f64) as i32
+    }
+
+    fn policy(&self, position: &Position) -> Option<[f32; 81]> {
+        self.run(position).policy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bitboard::Move;
+    use crate::game::RuleSet;
+
+    fn bytes(network: &Network) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        network.write(&mut bytes).unwrap();
+        bytes
+    }
+
+    // One hidden layer of 2: the first counts the side to move's tiles and the second is never
+    // active. The value is `tanh(0.5 * tiles + 0.1)`, and the policy logits are the tile indices.
+    fn counting_network() -> Network {
+        let mut hidden = Layer::zeros(INPUTS, 2);
+        hidden.weights[..81].fill(1.0);
+        hidden.weights[INPUTS..INPUTS + 81].fill(-1.0);
+        let mut value = Layer::zeros(2, 1);
+        value.weights = vec![0.5, 3.0];
+        value.biases = vec![0.1];
+        let mut policy = Layer::zeros(2, 81);
+        policy.biases = (0..81).map(|idx| idx as f32).collect();
+        Network {
+            hidden: vec![hidden],
+            value,
+            policy: Some(policy),
+        }
+    }
+
+    #[test]
+    fn forward_pass() {
+        let network = counting_network();
+        let mut position = Position::new(RuleSet::CLASSIC);
+        let output = network.run(&position);
+        assert!((output.value - 0.1f32.tanh()).abs() < 1e-6);
+        let policy = output.policy.unwrap();
+        assert!((policy.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert!(policy.windows(2).all(|pair| pair[0] < pair[1]));
+
+        for mv in ["C/C", "C/SE"] {
+            position.play(Move::try_from(mv).unwrap());
+        }
+        // X to move with one tile, and only the SE box to play in
+        let output = network.run(&position);
+        assert!((output.value - 0.6f32.tanh()).abs() < 1e-6);
+        let policy = output.policy.unwrap();
+        let legal = position
+            .legal_moves()
+            .map(|mv| mv.index())
+            .collect::<Vec<_>>();
+        assert_eq!(legal.len(), 9);
+        for (idx, &share) in policy.iter().enumerate() {
+            assert_eq!(share > 0.0, legal.contains(&idx), "{idx}");
+        }
+        assert!(network.evaluate(&position) > 0);
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let mut rng = Rng::new(1);
+        for network in [
+            Network::new(&[16, 8], true, &mut rng),
+            Network::new(&[], false, &mut rng),
+            counting_network(),
+        ] {
+            let bytes = bytes(&network);
+            let copy = Network::read(&mut bytes.as_slice()).unwrap();
+            assert_eq!(copy.hidden_sizes(), network.hidden_sizes());
+            assert_eq!(copy.has_policy(), network.has_policy());
+            for (layer, copied) in network.layers().zip(copy.layers()) {
+                assert_eq!(copied.weights, layer.weights);
+                assert_eq!(copied.biases, layer.biases);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_files() {
+        let network = Network::new(&[4], true, &mut Rng::new(2));
+        let bytes = bytes(&network);
+        let read = |bytes: &[u8]| Network::read(&mut &bytes[..]);
+
+        assert!(read(&bytes[..bytes.len() - 1]).is_err());
+        assert!(read(&[&bytes[..], &[0]].concat()).is_err());
+        assert!(read(b"UT3T").is_err());
+
+        // A huge hidden layer is turned down before anything is allocated for it
+        let mut huge = bytes[..10].to_vec();
+        huge.extend_from_slice(&(u32::MAX).to_le_bytes());
+        assert!(matches!(read(&huge), Err(UT3Error::InvalidFile(_))));
+        let mut big = bytes[..10].to_vec();
+        big.extend_from_slice(&(MAX_LAYER_SIZE as u32).to_le_bytes());
+        big.push(0);
+        assert!(matches!(read(&big), Err(UT3Error::InvalidFile(_))));
+    }
+}
@@ -0,0 +1,227 @@
+use crate::error::UT3Error;
+use crate::game::{Direction, Grid, Turn};
+
+// Lines of notation can have a comment at the end, starting with `#`, which is ignored along with
+// blank lines
+const COMMENT: char = '#';
+
+// The whitespace separated words of `line`, with the (1 based) column each one starts at
+fn words(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut words = Vec::new();
+    let mut start = None;
+    for (column, (idx, c)) in line.char_indices().enumerate() {
+        match (c.is_whitespace(), start) {
+            (false, None) => start = Some((column + 1, idx)),
+            (true, Some((word_column, word_start))) => {
+                words.push((word_column, &line[word_start..idx]));
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    if let Some((word_column, word_start)) = start {
+        words.push((word_column, &line[word_start..]));
+    }
+    words.into_iter()
+}
+
+fn strip_comment(line: &str) -> &str {
+    match line.find(COMMENT) {
+        Some(idx) => &line[..idx],
+        None => line,
+    }
+}
+
+/// Parses a single `<turn number> <player> <outer>/<inner>` turn, as written by `Turn`'s
+/// `Display`. Errors come with the column they were found at.
+pub fn parse_turn(line: &str) -> Result<Turn, (usize, UT3Error)> {
+    let end = line.chars().count() + 1;
+    let mut words = words(line);
+
+    let (column, word) = words
+        .next()
+        .ok_or((end, UT3Error::MissingField("turn number")))?;
+    let turn_number = word.parse().map_err(|err| (column, UT3Error::from(err)))?;
+
+    let (column, word) = words
+        .next()
+        .ok_or((end, UT3Error::MissingField("player symbol")))?;
+    let player = word.try_into().map_err(|err| (column, err))?;
+
+    let (column, word) = words
+        .next()
+        .ok_or((end, UT3Error::MissingField("coordinates")))?;
+    let (outer_word, inner_word) = word.split_once('/').ok_or((
+        column + word.chars().count(),
+        UT3Error::MissingField("`/` between the box and the tile"),
+    ))?;
+    let outer: Direction = outer_word.try_into().map_err(|err| (column, err))?;
+    let inner: Direction = inner_word
+        .try_into()
+        .map_err(|err| (column + outer_word.chars().count() + 1, err))?;
+
+    if let Some((column, word)) = words.next() {
+        return Err((column, UT3Error::TrailingInput(word.to_string())));
+    }
+
+    Ok(Turn::with_player(turn_number, player, (outer, inner)))
+}
+
+/// Plays every turn in `text` on `grid`, one per line, checking that the turn numbers and players
+/// follow on from the grid and each other. Errors are wrapped in `UT3Error::At` with the line and
+/// column they were found at, and leave `grid` with the turns before them played.
+pub fn replay(grid: &mut Grid, text: &str) -> Result<(), UT3Error> {
+    for (line_idx, line) in text.lines().enumerate() {
+        let at = |column, err| UT3Error::At {
+            line: line_idx + 1,
+            column,
+            source: Box::new(err),
+        };
+
+        let line = strip_comment(line);
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let turn = parse_turn(line).map_err(|(column, err)| at(column, err))?;
+        let mut words = words(line);
+        let mut column = || words.next().map_or(1, |(column, _)| column);
+
+        let number_column = column();
+        if turn.turn_number != grid.current_turn_number {
+            return Err(at(
+                number_column,
+                UT3Error::WrongTurnNumber {
+                    expected: grid.current_turn_number,
+                    got: turn.turn_number,
+                },
+            ));
+        }
+        let player_column = column();
+        if turn.player() != grid.current_player() {
+            return Err(at(
+                player_column,
+                UT3Error::WrongPlayer {
+                    expected: grid.current_player(),
+                    got: turn.player(),
+                },
+            ));
+        }
+        grid.apply_turn(turn.coords)
+            .map_err(|err| at(column(), err))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Player;
+    use Direction::*;
+
+    // The error `replay` gives for `text` on a new grid, unwrapped from `UT3Error::At`
+    fn replay_error(text: &str) -> (usize, usize, UT3Error) {
+        match replay(&mut Grid::default(), text) {
+            Err(UT3Error::At {
+                line,
+                column,
+                source,
+            }) => (line, column, *source),
+            other => panic!("expected an error with a position, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn parse_turn_columns() {
+        let turn = parse_turn("  12 O\tNW/SE").unwrap();
+        assert_eq!(turn.turn_number, 12);
+        assert_eq!(turn.player(), Player::O);
+        assert_eq!(turn.coords, (NW, SE));
+
+        assert!(matches!(
+            parse_turn("1 X"),
+            Err((4, UT3Error::MissingField("coordinates")))
+        ));
+        assert!(matches!(
+            parse_turn("1 X CN"),
+            Err((7, UT3Error::MissingField(_)))
+        ));
+        assert!(matches!(
+            parse_turn("x X C/N"),
+            Err((1, UT3Error::ParseIntError(_)))
+        ));
+        assert!(matches!(
+            parse_turn("1  Y C/N"),
+            Err((4, UT3Error::InvalidPlayer(_)))
+        ));
+        assert!(matches!(
+            parse_turn("1 X C/Q"),
+            Err((7, UT3Error::InvalidDirection(direction))) if direction == "Q"
+        ));
+        assert!(matches!(
+            parse_turn("1 X C/N extra"),
+            Err((9, UT3Error::TrailingInput(word))) if word == "extra"
+        ));
+    }
+
+    #[test]
+    fn replay_skips_comments_and_blank_lines() {
+        let mut grid = Grid::default();
+        replay(
+            &mut grid,
+            "# a game\n1 X C/N\n\n2 O N/C  # back to the centre\n",
+        )
+        .unwrap();
+        assert_eq!(grid.current_turn_number, 3);
+        assert_eq!(grid.get_track(), Some(C));
+    }
+
+    #[test]
+    fn replay_error_positions() {
+        assert!(matches!(
+            replay_error("1 X C/N\n\n 3 O N/C"),
+            (
+                3,
+                2,
+                UT3Error::WrongTurnNumber {
+                    expected: 2,
+                    got: 3
+                }
+            )
+        ));
+        assert!(matches!(
+            replay_error("1 X C/N\n2   X N/C"),
+            (
+                2,
+                5,
+                UT3Error::WrongPlayer {
+                    expected: Player::O,
+                    got: Player::X
+                }
+            )
+        ));
+        assert!(matches!(
+            replay_error("# comment\n1 X C/N\n2 O S/C"),
+            (
+                3,
+                5,
+                UT3Error::WrongTrack {
+                    required: N,
+                    got: S
+                }
+            )
+        ));
+        assert!(matches!(
+            replay_error("1 X C/N\n2 O N/C/"),
+            (2, 7, UT3Error::InvalidDirection(_))
+        ));
+    }
+
+    #[test]
+    fn replay_keeps_the_turns_before_an_error() {
+        let mut grid = Grid::default();
+        assert!(replay(&mut grid, "1 X C/N\n2 O N/N\n3 X N/N").is_err());
+        assert_eq!(grid.current_turn_number, 3);
+    }
+}
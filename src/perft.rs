@@ -0,0 +1,211 @@
+use crate::bitboard::Position;
+use crate::game::{Direction, Grid};
+
+/// Counts the move paths of exactly `depth` turns from `position`. Games that end sooner don't
+/// count.
+pub fn perft_position(position: &mut Position, depth: u32) -> u64 {
+    let moves = position.legal_moves();
+    if depth == 0 {
+        return 1;
+    } else if depth == 1 {
+        return moves.len() as u64;
+    }
+
+    let mut nodes = 0;
+    for mv in moves {
+        let undo = position.play(mv);
+        nodes += perft_position(position, depth - 1);
+        position.undo(mv, undo);
+    }
+    nodes
+}
+
+pub fn perft(grid: &Grid, depth: u32) -> u64 {
+    let mut position = *grid.position();
+    perft_position(&mut position, depth)
+}
+
+/// `perft` split up by the first turn, which makes it easier to find where two engines disagree
+pub fn divide(grid: &Grid, depth: u32) -> Vec<((Direction, Direction), u64)> {
+    let mut position = *grid.position();
+    position
+        .legal_moves()
+        .map(|mv| {
+            let undo = position.play(mv);
+            let nodes = if depth == 0 {
+                0
+            } else {
+                perft_position(&mut position, depth - 1)
+            };
+            position.undo(mv, undo);
+            (mv.coords(), nodes)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::{Player, RuleSet, Variant};
+
+    // A deliberately naive implementation of the classic rules, sharing nothing with `Position`, to
+    // check the bitboards against
+    #[derive(Clone)]
+    struct Reference {
+        tiles: [[Option<Player>; 9]; 9],
+        variant: Variant,
+        to_move: Player,
+        last: Option<(usize, usize)>,
+    }
+
+    fn line_winner(cells: [Option<Player>; 9]) -> Option<Player> {
+        let lines = [
+            [0, 1, 2],
+            [3, 4, 5],
+            [6, 7, 8],
+            [0, 3, 6],
+            [1, 4, 7],
+            [2, 5, 8],
+            [0, 4, 8],
+            [2, 4, 6],
+        ];
+        lines
+            .iter()
+            .find(|l| {
+                cells[l[0]].is_some() && cells[l[0]] == cells[l[1]] && cells[l[1]] == cells[l[2]]
+            })
+            .and_then(|l| cells[l[0]])
+    }
+
+    impl Reference {
+        fn new(variant: Variant) -> Self {
+            Self {
+                tiles: [[None; 9]; 9],
+                variant,
+                to_move: Player::X,
+                last: None,
+            }
+        }
+
+        fn box_winners(&self) -> [Option<Player>; 9] {
+            self.tiles.map(line_winner)
+        }
+
+        fn finished(&self, b: usize) -> bool {
+            self.box_winners()[b].is_some() || self.tiles[b].iter().all(|t| t.is_some())
+        }
+
+        fn moves(&self) -> Vec<(usize, usize)> {
+            if line_winner(self.box_winners()).is_some() {
+                return Vec::new();
+            }
+            let forced = self.last.map(|(b, t)| match self.variant {
+                Variant::Absolute => t,
+                Variant::Relative => {
+                    let (bx, by) = (b % 3, b / 3);
+                    let (tx, ty) = (t % 3, t / 3);
+                    (by + ty + 2) % 3 * 3 + (bx + tx + 2) % 3
+                }
+            });
+            let boxes = match forced {
+                Some(b) if !self.finished(b) => vec![b],
+                _ => (0..9).filter(|&b| !self.finished(b)).collect(),
+            };
+            boxes
+                .into_iter()
+                .flat_map(|b| (0..9).map(move |t| (b, t)))
+                .filter(|&(b, t)| self.tiles[b][t].is_none())
+                .collect()
+        }
+
+        fn perft(&self, depth: u32) -> u64 {
+            if depth == 0 {
+                return 1;
+            }
+            self.moves()
+                .into_iter()
+                .map(|(b, t)| {
+                    let mut next = self.clone();
+                    next.tiles[b][t] = Some(self.to_move);
+                    next.to_move = self.to_move.opponent();
+                    next.last = Some((b, t));
+                    next.perft(depth - 1)
+                })
+                .sum()
+        }
+    }
+
+    #[test]
+    fn perft_from_start() {
+        let expected = [81, 720, 6336, 55080, 473256, 4020960];
+        for (depth, &nodes) in expected.iter().enumerate() {
+            let grid = Grid::default().with_variant(Variant::Absolute);
+            assert_eq!(perft(&grid, depth as u32 + 1), nodes);
+        }
+
+        // The variants only start to differ once a box can be won
+        let expected = [81, 720, 6336, 55080, 473256, 4022496];
+        for (depth, &nodes) in expected.iter().enumerate() {
+            let grid = Grid::default().with_variant(Variant::Relative);
+            assert_eq!(perft(&grid, depth as u32 + 1), nodes);
+        }
+    }
+
+    #[test]
+    fn divide_adds_up_to_perft() {
+        for variant in [Variant::Absolute, Variant::Relative] {
+            let grid = Grid::default().with_variant(variant);
+            let split = divide(&grid, 4);
+            assert_eq!(split.len(), 81);
+            assert_eq!(split.iter().map(|(_, n)| n).sum::<u64>(), perft(&grid, 4));
+        }
+    }
+
+    #[test]
+    fn perft_matches_reference() {
+        for variant in [Variant::Absolute, Variant::Relative] {
+            let rules = RuleSet {
+                variant,
+                ..RuleSet::CLASSIC
+            };
+            assert_eq!(
+                perft(&Grid::default().with_rules(rules), 5),
+                Reference::new(variant).perft(5)
+            );
+        }
+
+        // A game for each variant that has won and drawn boxes, so that the rules about them
+        // matter, as `<outer>/<inner>` turns. The absolute one ends with a free choice of box.
+        let games = [
+            (
+                Variant::Absolute,
+                "C/NE NE/N N/SE SE/NW NW/SW SW/NW NW/SE SE/N N/NE NE/E E/SE SE/E E/SW SW/SE SE/S \
+                 S/E E/S S/NE NE/W W/E SE/NE NE/SE SE/SE SE/C C/SE SE/SW SW/W W/SE SE/W W/N N/E",
+                "......X.X/..X..X..X/.O.X.O..O/.O...O..O/..X.....X/......XXX/O..X....O/..O..O.../\
+                 OOXXOOOXX .X...X... O - absolute",
+            ),
+            (
+                Variant::Relative,
+                "SE/E SW/SE N/E NE/NW S/W SW/C SW/S NW/NE S/N C/S S/S N/NE SE/SW N/SE E/NW N/C \
+                 N/NW SW/W SE/NE W/E C/SW SW/NW E/C E/SW S/C N/S C/NW NW/E N/N N/W NW/S W/NE N/SW",
+                "..O..O.X./XXOOOXXOO/O......../..O..O.../X.....XO./X...X.O../O..OO..XO/.X.XX..X./\
+                 ..X..XX.. ......OX. O W relative",
+            ),
+        ];
+        for (variant, game, expected) in games {
+            let mut grid = Grid::default().with_variant(variant);
+            let mut reference = Reference::new(variant);
+            for turn in game.split_whitespace() {
+                let (outer, inner) = turn.split_once('/').unwrap();
+                let (outer, inner) = (outer.try_into().unwrap(), inner.try_into().unwrap());
+                grid.apply_turn((outer, inner))
+                    .expect("the fixture games are legal");
+                reference.tiles[outer.index()][inner.index()] = Some(reference.to_move);
+                reference.to_move = reference.to_move.opponent();
+                reference.last = Some((outer.index(), inner.index()));
+            }
+            assert_eq!(*grid.position(), Position::try_from(expected).unwrap());
+            assert_eq!(perft(&grid, 4), reference.perft(4));
+        }
+    }
+}
@@ -0,0 +1,804 @@
+//! A PGN-like format for annotated games. A game is a list of `[Tag "value"]` headers followed by
+//! the moves, each numbered like `Turn` and optionally followed by an annotation glyph, a
+//! `{comment}` and any number of `(variations)`, which are alternatives to the move they follow and
+//! can be nested. The moves end with the result. A `}` or `\` in a comment is escaped with a
+//! backslash.
+//!
+//! ```text
+//! [Event "Casual game"]
+//! [Date "2022.10.30"]
+//! [X "Alice"]
+//! [O "Bob"]
+//! [Variant "relative"]
+//! [Rules "relative"]
+//! [Result "1-0"]
+//!
+//! 1. C/C {The usual start} 2. C/NW?! (2. C/N 3. N/C) 3. NW/C! 1-0
+//! ```
+//!
+//! The rules come from the `Rules` header, or failing that the `Variant` header, and games that
+//! don't start from an empty grid have a `Position` header with a position string.
+
+use std::fmt;
+
+use crate::bitboard::Position;
+use crate::error::UT3Error;
+use crate::game::{Direction, GameStatus, Grid, Player, RuleSet, Variant};
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Annotation {
+    /// `!!`
+    Brilliant,
+    /// `!`
+    Good,
+    /// `!?`
+    Interesting,
+    /// `?!`
+    Dubious,
+    /// `?`
+    Mistake,
+    /// `??`
+    Blunder,
+}
+
+impl Annotation {
+    pub const ALL: [Annotation; 6] = [
+        Annotation::Brilliant,
+        Annotation::Good,
+        Annotation::Interesting,
+        Annotation::Dubious,
+        Annotation::Mistake,
+        Annotation::Blunder,
+    ];
+
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Annotation::Brilliant => "!!",
+            Annotation::Good => "!",
+            Annotation::Interesting => "!?",
+            Annotation::Dubious => "?!",
+            Annotation::Mistake => "?",
+            Annotation::Blunder => "??",
+        }
+    }
+}
+
+impl fmt::Display for Annotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.symbol())
+    }
+}
+
+impl TryFrom<&str> for Annotation {
+    type Error = UT3Error;
+    fn try_from(string: &str) -> Result<Self, Self::Error> {
+        Annotation::ALL
+            .into_iter()
+            .find(|annotation| annotation.symbol() == string)
+            .ok_or_else(|| UT3Error::UnexpectedToken(string.to_string()))
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct MoveNode {
+    pub coords: (Direction, Direction),
+    pub annotation: Option<Annotation>,
+    pub comment: Option<String>,
+    /// Lines that could have been played instead of this move
+    pub variations: Vec<Vec<MoveNode>>,
+}
+
+impl MoveNode {
+    pub fn new(coords: (Direction, Direction)) -> Self {
+        Self {
+            coords,
+            annotation: None,
+            comment: None,
+            variations: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Game {
+    pub headers: Vec<(String, String)>,
+    /// A comment before the first move
+    pub comment: Option<String>,
+    pub moves: Vec<MoveNode>,
+    pub result: GameStatus,
+}
+
+fn result_token(status: GameStatus) -> &'static str {
+    match status {
+        GameStatus::Won(Player::X) => "1-0",
+        GameStatus::Won(Player::O) => "0-1",
+        GameStatus::Drawn => "1/2-1/2",
+        GameStatus::Ongoing => "*",
+    }
+}
+
+fn parse_result(token: &str) -> Option<GameStatus> {
+    match token {
+        "1-0" => Some(GameStatus::Won(Player::X)),
+        "0-1" => Some(GameStatus::Won(Player::O)),
+        "1/2-1/2" => Some(GameStatus::Drawn),
+        "*" => Some(GameStatus::Ongoing),
+        _ => None,
+    }
+}
+
+fn variant_name(variant: Variant) -> &'static str {
+    match variant {
+        Variant::Relative => "relative",
+        Variant::Absolute => "absolute",
+    }
+}
+
+impl Game {
+    pub fn new(rules: RuleSet) -> Self {
+        let mut game = Self {
+            headers: Vec::new(),
+            comment: None,
+            moves: Vec::new(),
+            result: GameStatus::Ongoing,
+        };
+        game.set_rules(rules);
+        game
+    }
+
+    /// The main line of `grid`, with the headers needed to replay it
+    pub fn from_grid(grid: &Grid) -> Self {
+        let mut game = Game::new(grid.rules());
+        if *grid.start() != Position::new(grid.rules()) {
+            game.set_header("Position", &grid.start().to_string());
+        }
+        game.moves = grid
+            .turns
+            .iter()
+            .map(|turn| MoveNode::new(turn.coords))
+            .collect();
+        game.result = grid.status();
+        game
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Replaces the value of the header `name`, or adds it to the end if there isn't one yet
+    pub fn set_header(&mut self, name: &str, value: &str) {
+        match self.headers.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, old)) => *old = value.to_string(),
+            None => self.headers.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    pub fn set_rules(&mut self, rules: RuleSet) {
+        self.set_header("Variant", variant_name(rules.variant));
+        self.set_header("Rules", &rules.to_string());
+    }
+
+    pub fn rules(&self) -> Result<RuleSet, UT3Error> {
+        let variant = self
+            .header("Variant")
+            .map(|variant| match variant.to_ascii_lowercase().as_str() {
+                "relative" => Ok(Variant::Relative),
+                "absolute" => Ok(Variant::Absolute),
+                _ => Err(UT3Error::InvalidHeader(format!(
+                    "unknown variant `{variant}`"
+                ))),
+            })
+            .transpose()?;
+
+        match (self.header("Rules"), variant) {
+            (Some(rules), variant) => {
+                let rules = RuleSet::try_from(rules)?;
+                if variant.is_some_and(|variant| variant != rules.variant) {
+                    Err(UT3Error::InvalidHeader(
+                        "the `Variant` and `Rules` headers disagree".to_string(),
+                    ))
+                } else {
+                    Ok(rules)
+                }
+            }
+            (None, Some(variant)) => Ok(RuleSet {
+                variant,
+                ..RuleSet::CLASSIC
+            }),
+            (None, None) => Ok(RuleSet::CLASSIC),
+        }
+    }
+
+    /// The position the game starts from
+    pub fn start(&self) -> Result<Position, UT3Error> {
+        let rules = self.rules()?;
+        match self.header("Position") {
+            Some(position) => {
+                let position = Position::try_from(position)?;
+                if position.rules() != rules {
+                    Err(UT3Error::InvalidHeader(
+                        "the `Position` header has different rules".to_string(),
+                    ))
+                } else {
+                    Ok(position)
+                }
+            }
+            None => Ok(Position::new(rules)),
+        }
+    }
+
+    /// Plays the main line
+    pub fn grid(&self) -> Result<Grid, UT3Error> {
+        let mut grid = Grid::from_position(self.start()?);
+        for node in &self.moves {
+            grid.apply_turn(node.coords)?;
+        }
+        Ok(grid)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Header(String, String),
+    Comment(String),
+    Open,
+    Close,
+    Number(u32),
+    Move((Direction, Direction), Option<Annotation>),
+    Result(GameStatus),
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(text: &'a str) -> Self {
+        Self {
+            chars: text.chars().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn next_char(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn at(&self, line: usize, column: usize, err: UT3Error) -> UT3Error {
+        UT3Error::At {
+            line,
+            column,
+            source: Box::new(err),
+        }
+    }
+
+    fn until(&mut self, end: char, what: &'static str) -> Result<String, UT3Error> {
+        let (line, column) = (self.line, self.column - 1);
+        let mut string = String::new();
+        // Header values are quoted, and can contain `]` and escaped quotes
+        let mut quoted = false;
+        loop {
+            match self.next_char() {
+                Some(c) if c == end && !quoted => return Ok(string),
+                Some('"') if end == ']' => {
+                    quoted = !quoted;
+                    string.push('"');
+                }
+                // Left for `header` to unescape
+                Some('\\') if quoted => {
+                    string.push('\\');
+                    string.extend(self.next_char());
+                }
+                // Only `\}` and `\\` are escapes in comments, other backslashes are kept
+                Some('\\') if end == '}' => match self.chars.peek() {
+                    Some(&c) if c == '}' || c == '\\' => {
+                        self.next_char();
+                        string.push(c);
+                    }
+                    _ => string.push('\\'),
+                },
+                Some(c) => string.push(c),
+                None => return Err(self.at(line, column, UT3Error::Unterminated(what))),
+            }
+        }
+    }
+
+    fn header(&mut self) -> Result<Token, UT3Error> {
+        let (line, column) = (self.line, self.column - 1);
+        let header = self.until(']', "header")?;
+        let invalid = || self.at(line, column, UT3Error::InvalidHeader(format!("[{header}]")));
+
+        let (name, value) = header
+            .trim()
+            .split_once(char::is_whitespace)
+            .ok_or_else(invalid)?;
+        let value = value.trim();
+        if !value.starts_with('"') || !value.ends_with('"') || value.len() < 2 {
+            return Err(invalid());
+        }
+        let mut unescaped = String::new();
+        let mut chars = value[1..value.len() - 1].chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => unescaped.extend(chars.next()),
+                c => unescaped.push(c),
+            }
+        }
+
+        Ok(Token::Header(name.to_string(), unescaped))
+    }
+
+    fn word(&mut self, first: char) -> Result<Vec<Token>, UT3Error> {
+        let (line, column) = (self.line, self.column - 1);
+        let mut word = String::from(first);
+        while let Some(&c) = self.chars.peek() {
+            if c.is_whitespace() || "{}()[]".contains(c) {
+                break;
+            }
+            word.push(c);
+            self.next_char();
+        }
+
+        if let Some(result) = parse_result(&word) {
+            return Ok(vec![Token::Result(result)]);
+        }
+
+        let mut tokens = Vec::new();
+        let mut rest = word.as_str();
+        // Move numbers can be written right up against the move, like `1.C/C`
+        if let Some((number, after)) = rest.split_once('.') {
+            let number = number
+                .parse()
+                .map_err(|err| self.at(line, column, UT3Error::from(err)))?;
+            tokens.push(Token::Number(number));
+            rest = after;
+        }
+        if rest.is_empty() {
+            return Ok(tokens);
+        }
+
+        let glyph_start = rest.find(['!', '?']).unwrap_or(rest.len());
+        let (coords, glyph) = rest.split_at(glyph_start);
+        let err_column = column + word.len() - rest.len();
+        let (outer, inner) = coords.split_once('/').ok_or_else(|| {
+            self.at(
+                line,
+                err_column,
+                UT3Error::UnexpectedToken(rest.to_string()),
+            )
+        })?;
+        let coords = (
+            outer
+                .try_into()
+                .map_err(|err| self.at(line, err_column, err))?,
+            inner
+                .try_into()
+                .map_err(|err| self.at(line, err_column, err))?,
+        );
+        let annotation = if glyph.is_empty() {
+            None
+        } else {
+            Some(
+                Annotation::try_from(glyph)
+                    .map_err(|err| self.at(line, err_column + coords_len(coords), err))?,
+            )
+        };
+        tokens.push(Token::Move(coords, annotation));
+
+        Ok(tokens)
+    }
+
+    fn tokens(mut self) -> Result<Vec<(usize, usize, Token)>, UT3Error> {
+        let mut tokens = Vec::new();
+        loop {
+            let (line, column) = (self.line, self.column);
+            let c = match self.next_char() {
+                Some(c) => c,
+                None => return Ok(tokens),
+            };
+            let new = match c {
+                c if c.is_whitespace() => continue,
+                '[' => vec![self.header()?],
+                '{' => vec![Token::Comment(
+                    self.until('}', "comment")?.trim().to_string(),
+                )],
+                '(' => vec![Token::Open],
+                ')' => vec![Token::Close],
+                c => self.word(c)?,
+            };
+            tokens.extend(new.into_iter().map(|token| (line, column, token)));
+        }
+    }
+}
+
+fn coords_len(coords: (Direction, Direction)) -> usize {
+    coords.0.to_string().len() + 1 + coords.1.to_string().len()
+}
+
+struct Parser {
+    tokens: Vec<(usize, usize, Token)>,
+    idx: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.idx).map(|(_, _, token)| token)
+    }
+
+    fn err(&self, err: UT3Error) -> UT3Error {
+        let (line, column) = self
+            .tokens
+            .get(self.idx)
+            .or(self.tokens.last())
+            .map_or((1, 1), |(line, column, _)| (*line, *column));
+        UT3Error::At {
+            line,
+            column,
+            source: Box::new(err),
+        }
+    }
+
+    fn unexpected(&self) -> UT3Error {
+        let token = match self.peek() {
+            Some(Token::Header(name, _)) => format!("[{name}"),
+            Some(Token::Open) => "(".to_string(),
+            Some(Token::Close) => ")".to_string(),
+            Some(Token::Comment(_)) => "{".to_string(),
+            Some(Token::Number(number)) => format!("{number}."),
+            Some(Token::Move((outer, inner), _)) => format!("{outer}/{inner}"),
+            Some(&Token::Result(result)) => result_token(result).to_string(),
+            None => "end of input".to_string(),
+        };
+        self.err(UT3Error::UnexpectedToken(token))
+    }
+
+    // Parses moves played from `grid` until the end of the line, checking that they are legal
+    fn line(
+        &mut self,
+        mut grid: Grid,
+        comment: &mut Option<String>,
+    ) -> Result<Vec<MoveNode>, UT3Error> {
+        let mut moves: Vec<MoveNode> = Vec::new();
+        let mut before = grid.clone();
+        loop {
+            match self.peek().cloned() {
+                Some(Token::Number(number)) => {
+                    if number != grid.current_turn_number {
+                        return Err(self.err(UT3Error::WrongTurnNumber {
+                            expected: grid.current_turn_number,
+                            got: number,
+                        }));
+                    }
+                    self.idx += 1;
+                }
+                Some(Token::Move(coords, annotation)) => {
+                    before = grid.clone();
+                    grid.apply_turn(coords).map_err(|err| self.err(err))?;
+                    let mut node = MoveNode::new(coords);
+                    node.annotation = annotation;
+                    moves.push(node);
+                    self.idx += 1;
+                }
+                Some(Token::Comment(text)) => {
+                    let target = match moves.last_mut() {
+                        Some(node) => &mut node.comment,
+                        None => &mut *comment,
+                    };
+                    match target {
+                        Some(existing) => {
+                            existing.push(' ');
+                            existing.push_str(&text);
+                        }
+                        None => *target = Some(text),
+                    }
+                    self.idx += 1;
+                }
+                Some(Token::Open) => {
+                    if moves.is_empty() {
+                        return Err(self.unexpected());
+                    }
+                    self.idx += 1;
+                    let mut variation_comment = None;
+                    let mut variation = self.line(before.clone(), &mut variation_comment)?;
+                    if self.peek() != Some(&Token::Close) {
+                        return Err(self.unexpected());
+                    }
+                    self.idx += 1;
+                    // A comment before the first move of a variation is kept with that move
+                    if let (Some(text), Some(first)) = (variation_comment, variation.first_mut()) {
+                        first.comment = Some(match first.comment.take() {
+                            Some(after) => format!("{text} {after}"),
+                            None => text,
+                        });
+                    }
+                    if !variation.is_empty() {
+                        moves.last_mut().unwrap().variations.push(variation);
+                    }
+                }
+                _ => return Ok(moves),
+            }
+        }
+    }
+
+    fn game(&mut self) -> Result<Game, UT3Error> {
+        let mut game = Game {
+            headers: Vec::new(),
+            comment: None,
+            moves: Vec::new(),
+            result: GameStatus::Ongoing,
+        };
+        while let Some(Token::Header(name, value)) = self.peek().cloned() {
+            game.headers.push((name, value));
+            self.idx += 1;
+        }
+
+        let start = game.start().map_err(|err| self.err(err))?;
+        let mut comment = None;
+        game.moves = self.line(Grid::from_position(start), &mut comment)?;
+        game.comment = comment;
+
+        let header_result = game.header("Result").map(|result| {
+            parse_result(result)
+                .ok_or_else(|| UT3Error::InvalidHeader(format!("unknown result `{result}`")))
+        });
+        let header_result = header_result.transpose().map_err(|err| self.err(err))?;
+        game.result = match (self.peek(), header_result) {
+            (Some(&Token::Result(result)), header) => {
+                if header.is_some_and(|header| header != result) {
+                    return Err(self.err(UT3Error::InvalidHeader(
+                        "the `Result` header disagrees with the result after the moves".to_string(),
+                    )));
+                }
+                self.idx += 1;
+                result
+            }
+            (None | Some(Token::Header(..)), header) => header.unwrap_or(GameStatus::Ongoing),
+            _ => return Err(self.unexpected()),
+        };
+
+        let status = game.grid().map_err(|err| self.err(err))?.status();
+        if status.is_over() && game.result != status {
+            return Err(self.err(UT3Error::InvalidHeader(format!(
+                "the result should be {} since the game is {status}",
+                result_token(status)
+            ))));
+        }
+
+        Ok(game)
+    }
+}
+
+/// Reads every game in `text`
+pub fn parse_all(text: &str) -> Result<Vec<Game>, UT3Error> {
+    let mut parser = Parser {
+        tokens: Lexer::new(text).tokens()?,
+        idx: 0,
+    };
+    let mut games = Vec::new();
+    while parser.peek().is_some() {
+        games.push(parser.game()?);
+    }
+    Ok(games)
+}
+
+/// Reads a single game
+pub fn parse(text: &str) -> Result<Game, UT3Error> {
+    let mut games = parse_all(text)?;
+    match games.len() {
+        1 => Ok(games.pop().unwrap()),
+        0 => Err(UT3Error::MissingField("game")),
+        _ => Err(UT3Error::UnexpectedToken("a second game".to_string())),
+    }
+}
+
+// Breaks movetext into lines of at most this many characters where it can
+const LINE_WIDTH: usize = 80;
+
+fn comment_word(comment: &str) -> String {
+    format!("{{{}}}", comment.replace('\\', "\\\\").replace('}', "\\}"))
+}
+
+fn write_line(words: &mut Vec<String>, moves: &[MoveNode], mut turn_number: u32) {
+    for node in moves {
+        let annotation = node.annotation.map_or(String::new(), |a| a.to_string());
+        words.push(format!(
+            "{turn_number}. {}/{}{annotation}",
+            node.coords.0, node.coords.1
+        ));
+        if let Some(comment) = &node.comment {
+            words.push(comment_word(comment));
+        }
+        for variation in &node.variations {
+            let mut inner = Vec::new();
+            write_line(&mut inner, variation, turn_number);
+            words.push(format!("({})", inner.join(" ")));
+        }
+        turn_number += 1;
+    }
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.headers {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(f, "[{name} \"{value}\"]")?;
+        }
+        if !self.headers.is_empty() {
+            writeln!(f)?;
+        }
+
+        let first_turn = self
+            .start()
+            .map_or(1, |start| Grid::from_position(start).current_turn_number);
+        let mut words = Vec::new();
+        if let Some(comment) = &self.comment {
+            words.push(comment_word(comment));
+        }
+        write_line(&mut words, &self.moves, first_turn);
+        words.push(result_token(self.result).to_string());
+
+        let mut width = 0;
+        for word in words {
+            if width > 0 && width + 1 + word.len() > LINE_WIDTH {
+                writeln!(f)?;
+                width = 0;
+            } else if width > 0 {
+                write!(f, " ")?;
+                width += 1;
+            }
+            write!(f, "{word}")?;
+            width += word.len();
+        }
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Direction::*;
+
+    const GAME: &str = r#"[Event "Casual \"game\" [1]"]
+[Site "C:\\games"]
+[Variant "relative"]
+[Rules "relative"]
+
+{Before the \} first move} 1. C/C {The usual start} 2. C/NW?! (2. C/N!! {a \\ b \c}
+3. N/C (3. N/N? {Nested})) (2. C/S 3. S/C??) 3. NW/C! *
+"#;
+
+    // Parses `text` and checks that writing the game out and parsing it again gives it back
+    fn round_trip(text: &str) -> Game {
+        let game = parse(text).unwrap();
+        let written = game.to_string();
+        assert_eq!(parse(&written).unwrap(), game, "written as:\n{written}");
+        game
+    }
+
+    fn error_at(text: &str) -> (usize, usize, UT3Error) {
+        match parse(text) {
+            Err(UT3Error::At {
+                line,
+                column,
+                source,
+            }) => (line, column, *source),
+            other => panic!("expected an error with a position, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn headers_round_trip() {
+        let game = round_trip(GAME);
+        assert_eq!(game.header("Event"), Some(r#"Casual "game" [1]"#));
+        assert_eq!(game.header("Site"), Some(r"C:\games"));
+        assert_eq!(game.rules().unwrap().variant, Variant::Relative);
+    }
+
+    #[test]
+    fn variations_round_trip() {
+        let game = round_trip(GAME);
+        let coords = |line: &[MoveNode]| line.iter().map(|node| node.coords).collect::<Vec<_>>();
+        assert_eq!(coords(&game.moves), [(C, C), (C, NW), (NW, C)]);
+
+        let variations = &game.moves[1].variations;
+        assert_eq!(variations.len(), 2);
+        assert_eq!(coords(&variations[0]), [(C, N), (N, C)]);
+        assert_eq!(coords(&variations[1]), [(C, S), (S, C)]);
+        assert_eq!(coords(&variations[0][1].variations[0]), [(N, N)]);
+    }
+
+    #[test]
+    fn annotations_round_trip() {
+        let game = round_trip(GAME);
+        let variation = &game.moves[1].variations[0];
+        assert_eq!(game.moves[0].annotation, None);
+        assert_eq!(game.moves[1].annotation, Some(Annotation::Dubious));
+        assert_eq!(game.moves[2].annotation, Some(Annotation::Good));
+        assert_eq!(variation[0].annotation, Some(Annotation::Brilliant));
+        assert_eq!(
+            variation[1].variations[0][0].annotation,
+            Some(Annotation::Mistake)
+        );
+        assert_eq!(
+            game.moves[1].variations[1][1].annotation,
+            Some(Annotation::Blunder)
+        );
+    }
+
+    #[test]
+    fn comments_round_trip() {
+        let game = round_trip(GAME);
+        let variation = &game.moves[1].variations[0];
+        assert_eq!(game.comment.as_deref(), Some("Before the } first move"));
+        assert_eq!(game.moves[0].comment.as_deref(), Some("The usual start"));
+        assert_eq!(variation[0].comment.as_deref(), Some(r"a \ b \c"));
+        assert_eq!(
+            variation[1].variations[0][0].comment.as_deref(),
+            Some("Nested")
+        );
+
+        let mut game = Game::new(RuleSet::CLASSIC);
+        game.moves.push(MoveNode::new((C, C)));
+        game.moves[0].comment = Some(r"ends with a brace } and a backslash \".to_string());
+        assert_eq!(round_trip(&game.to_string()), game);
+    }
+
+    #[test]
+    fn error_positions() {
+        assert!(matches!(
+            error_at("1. C/C 3. C/N"),
+            (
+                1,
+                8,
+                UT3Error::WrongTurnNumber {
+                    expected: 2,
+                    got: 3
+                }
+            )
+        ));
+        assert!(matches!(
+            error_at("1. C/C\n2. N/C"),
+            (
+                2,
+                4,
+                UT3Error::WrongTrack {
+                    required: C,
+                    got: N
+                }
+            )
+        ));
+        assert!(matches!(
+            error_at("1. C/C\n2. C/N?x"),
+            (2, 7, UT3Error::UnexpectedToken(glyph)) if glyph == "?x"
+        ));
+        assert!(matches!(
+            error_at("1. C/C {never closed"),
+            (1, 8, UT3Error::Unterminated("comment"))
+        ));
+        assert!(matches!(
+            error_at("[Event \"never closed]\n\n1. C/C"),
+            (1, 1, UT3Error::Unterminated("header"))
+        ));
+        assert!(matches!(
+            error_at("1. C/C (1. C/N"),
+            (1, 12, UT3Error::UnexpectedToken(token)) if token == "end of input"
+        ));
+        assert!(matches!(
+            error_at("[Result \"1-0\"]\n\n1. C/C 0-1"),
+            (3, 8, UT3Error::InvalidHeader(_))
+        ));
+    }
+}
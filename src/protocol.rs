@@ -0,0 +1,425 @@
+//! The text protocol `ut3e-engine` speaks on stdin and stdout, modelled on UCI. Every message is
+//! one line, and moves are written `<box>/<tile>`, like `NW/C`.
+//!
+//! Commands to the engine:
+//!
+//! - `ut3e`: start talking. The engine answers with `id` and `option` lines, then `ut3eok`.
+//! - `isready`: answered with `readyok` straight away, even during a search, like in UCI.
+//! - `setoption name <name> value <value>`: see the `option` lines for what there is.
+//! - `newgame`: the next position is from a new game, so forget what was learned.
+//! - `position startpos [moves <move>...]`: the start position, with the rules from the `Rules`
+//!   option, after `moves`.
+//! - `position pos <position string> [moves <move>...]`: a position string (see
+//!   `bitboard::Position`'s `Display`), after `moves`.
+//! - `go [depth <plies>] [movetime <ms>] [nodes <count>] [infinite]`: search the position. The
+//!   engine sends `info` lines as it goes, then `bestmove`.
+//! - `stop`: finish searching as soon as possible.
+//! - `quit`
+//!
+//! Messages from the engine:
+//!
+//! - `id name <name>`
+//! - `option name <name> type <type> default <value> ...`
+//! - `ut3eok`, `readyok`
+//! - `info depth <plies> score <score> nodes <count> time <ms> nps <count> pv <move>...`, where
+//!   the score is `cp <value>` for an evaluation, or `win <plies>` or `loss <plies>` for a forced
+//!   result, all for the side to move.
+//! - `info string <text>`: anything else, like errors.
+//! - `bestmove <move>`, or `bestmove none` if the game is over.
+
+use std::fmt;
+use std::time::Duration;
+
+use crate::bitboard::{Move, Position};
+use crate::error::UT3Error;
+use crate::search::{plies_to_end, Limits, WIN};
+
+#[derive(Clone, Debug)]
+pub enum Command {
+    Hello,
+    IsReady,
+    SetOption {
+        name: String,
+        value: String,
+    },
+    NewGame,
+    Position {
+        /// `None` for the start position
+        start: Option<Position>,
+        moves: Vec<Move>,
+    },
+    /// `Limits::default()` searches until stopped
+    Go(Limits),
+    Stop,
+    Quit,
+}
+
+fn parse_moves<'a>(words: impl Iterator<Item = &'a str>) -> Result<Vec<Move>, UT3Error> {
+    words.map(Move::try_from).collect()
+}
+
+fn parse_number<T: std::str::FromStr>(
+    word: Option<&str>,
+    name: &'static str,
+) -> Result<T, UT3Error> {
+    let word = word.ok_or(UT3Error::MissingField(name))?;
+    word.parse()
+        .map_err(|_| UT3Error::UnexpectedToken(word.to_string()))
+}
+
+impl TryFrom<&str> for Command {
+    type Error = UT3Error;
+    fn try_from(line: &str) -> Result<Self, Self::Error> {
+        let mut words = line.split_ascii_whitespace();
+        let command = match words.next().ok_or(UT3Error::MissingField("command"))? {
+            "ut3e" => Command::Hello,
+            "isready" => Command::IsReady,
+            "newgame" => Command::NewGame,
+            "stop" => Command::Stop,
+            "quit" => Command::Quit,
+            "setoption" => {
+                let rest = line.trim().strip_prefix("setoption").unwrap().trim();
+                let rest = rest
+                    .strip_prefix("name ")
+                    .ok_or(UT3Error::MissingField("option name"))?;
+                let (name, value) = rest.split_once(" value ").unwrap_or((rest, ""));
+                return Ok(Command::SetOption {
+                    name: name.trim().to_string(),
+                    value: value.trim().to_string(),
+                });
+            }
+            "position" => {
+                let words = words.collect::<Vec<_>>();
+                let (start, rest) = match words.iter().position(|&word| word == "moves") {
+                    Some(idx) => (&words[..idx], &words[idx + 1..]),
+                    None => (&words[..], &[][..]),
+                };
+                let start = match start.split_first() {
+                    Some((&"startpos", [])) => None,
+                    Some((&"pos", fields)) => Some(Position::try_from(fields.join(" ").as_str())?),
+                    Some((word, _)) => return Err(UT3Error::UnexpectedToken(word.to_string())),
+                    None => return Err(UT3Error::MissingField("position")),
+                };
+                return Ok(Command::Position {
+                    start,
+                    moves: parse_moves(rest.iter().copied())?,
+                });
+            }
+            "go" => {
+                let mut limits = Limits::default();
+                while let Some(word) = words.next() {
+                    match word {
+                        "depth" => limits.depth = Some(parse_number(words.next(), "depth")?),
+                        "movetime" => {
+                            limits.time = Some(Duration::from_millis(parse_number(
+                                words.next(),
+                                "move time",
+                            )?))
+                        }
+                        "nodes" => limits.nodes = Some(parse_number(words.next(), "nodes")?),
+                        "infinite" => (),
+                        _ => return Err(UT3Error::UnexpectedToken(word.to_string())),
+                    }
+                }
+                return Ok(Command::Go(limits));
+            }
+            word => return Err(UT3Error::UnexpectedToken(word.to_string())),
+        };
+        match words.next() {
+            Some(word) => Err(UT3Error::TrailingInput(word.to_string())),
+            None => Ok(command),
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Command::Hello => write!(f, "ut3e"),
+            Command::IsReady => write!(f, "isready"),
+            Command::SetOption { name, value } => write!(f, "setoption name {name} value {value}"),
+            Command::NewGame => write!(f, "newgame"),
+            Command::Position { start, moves } => {
+                match start {
+                    Some(position) => write!(f, "position pos {position}")?,
+                    None => write!(f, "position startpos")?,
+                }
+                if !moves.is_empty() {
+                    write!(f, " moves")?;
+                    for mv in moves {
+                        write!(f, " {mv}")?;
+                    }
+                }
+                Ok(())
+            }
+            Command::Go(limits) => {
+                write!(f, "go")?;
+                if let Some(depth) = limits.depth {
+                    write!(f, " depth {depth}")?;
+                }
+                if let Some(time) = limits.time {
+                    write!(f, " movetime {}", time.as_millis())?;
+                }
+                if let Some(nodes) = limits.nodes {
+                    write!(f, " nodes {nodes}")?;
+                }
+                if limits.depth.is_none() && limits.time.is_none() && limits.nodes.is_none() {
+                    write!(f, " infinite")?;
+                }
+                Ok(())
+            }
+            Command::Stop => write!(f, "stop"),
+            Command::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+/// The progress of a search, after each depth
+#[derive(Clone, Debug)]
+pub struct Info {
+    pub depth: u32,
+    /// See `search::WIN`
+    pub score: i32,
+    pub nodes: u64,
+    pub time: Duration,
+    pub pv: Vec<Move>,
+}
+
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "info depth {} score ", self.depth)?;
+        match plies_to_end(self.score) {
+            Some(plies) if self.score > 0 => write!(f, "win {plies}")?,
+            Some(plies) => write!(f, "loss {plies}")?,
+            None => write!(f, "cp {}", self.score)?,
+        }
+        let nps = self.nodes as f64 / self.time.as_secs_f64().max(0.001);
+        write!(
+            f,
+            " nodes {} time {} nps {}",
+            self.nodes,
+            self.time.as_millis(),
+            nps as u64
+        )?;
+        if !self.pv.is_empty() {
+            write!(f, " pv")?;
+            for mv in &self.pv {
+                write!(f, " {mv}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Response {
+    Id {
+        /// Like `name`
+        field: String,
+        value: String,
+    },
+    /// An `option` line, without the `option`
+    Option(String),
+    HelloOk,
+    ReadyOk,
+    Info(Info),
+    /// An `info string` line, without the `info string`
+    Message(String),
+    /// `None` if the game is over
+    BestMove(Option<Move>),
+}
+
+impl TryFrom<&str> for Response {
+    type Error = UT3Error;
+    fn try_from(line: &str) -> Result<Self, Self::Error> {
+        let line = line.trim();
+        let (first, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let rest = rest.trim();
+        Ok(match first {
+            "ut3eok" => Response::HelloOk,
+            "readyok" => Response::ReadyOk,
+            "option" => Response::Option(rest.to_string()),
+            "id" => {
+                let (field, value) = rest.split_once(' ').unwrap_or((rest, ""));
+                Response::Id {
+                    field: field.to_string(),
+                    value: value.trim().to_string(),
+                }
+            }
+            "bestmove" => match rest {
+                "none" => Response::BestMove(None),
+                mv => Response::BestMove(Some(Move::try_from(mv)?)),
+            },
+            "info" => match rest.strip_prefix("string") {
+                Some(text) => Response::Message(text.trim().to_string()),
+                None => Response::Info(parse_info(rest)?),
+            },
+            _ => return Err(UT3Error::UnexpectedToken(first.to_string())),
+        })
+    }
+}
+
+fn parse_info(text: &str) -> Result<Info, UT3Error> {
+    let mut info = Info {
+        depth: 0,
+        score: 0,
+        nodes: 0,
+        time: Duration::ZERO,
+        pv: Vec::new(),
+    };
+    let mut words = text.split_ascii_whitespace();
+    while let Some(word) = words.next() {
+        match word {
+            "depth" => info.depth = parse_number(words.next(), "depth")?,
+            "score" => {
+                let kind = words.next().ok_or(UT3Error::MissingField("score"))?;
+                let value: i32 = parse_number(words.next(), "score")?;
+                info.score = match kind {
+                    "cp" => value,
+                    "win" => WIN - value,
+                    "loss" => -(WIN - value),
+                    _ => return Err(UT3Error::UnexpectedToken(kind.to_string())),
+                };
+            }
+            "nodes" => info.nodes = parse_number(words.next(), "nodes")?,
+            "time" => info.time = Duration::from_millis(parse_number(words.next(), "time")?),
+            "pv" => {
+                info.pv = parse_moves(words)?;
+                break;
+            }
+            // Anything else, like `nps`, is just for people to read
+            _ => {
+                words.next();
+            }
+        }
+    }
+    Ok(info)
+}
+
+impl fmt::Display for Response {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Response::Id { field, value } => write!(f, "id {field} {value}"),
+            Response::Option(option) => write!(f, "option {option}"),
+            Response::HelloOk => write!(f, "ut3eok"),
+            Response::ReadyOk => write!(f, "readyok"),
+            Response::Info(info) => write!(f, "{info}"),
+            Response::Message(text) => write!(f, "info string {text}"),
+            Response::BestMove(Some(mv)) => write!(f, "bestmove {mv}"),
+            Response::BestMove(None) => write!(f, "bestmove none"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::RuleSet;
+
+    #[test]
+    fn command_round_trip() {
+        let position = Position::new(RuleSet::RELATIVE);
+        for line in [
+            "ut3e".to_string(),
+            "isready".to_string(),
+            "setoption name Weights value tuned weights.txt".to_string(),
+            "newgame".to_string(),
+            "position startpos".to_string(),
+            "position startpos moves C/C C/NW".to_string(),
+            format!("position pos {position} moves NW/C"),
+            "go depth 8 movetime 500 nodes 10000".to_string(),
+            "go infinite".to_string(),
+            "stop".to_string(),
+            "quit".to_string(),
+        ] {
+            let command = Command::try_from(line.as_str()).unwrap();
+            assert_eq!(command.to_string(), line);
+        }
+
+        match Command::try_from("setoption name Weights value tuned weights.txt").unwrap() {
+            Command::SetOption { name, value } => {
+                assert_eq!(
+                    (name.as_str(), value.as_str()),
+                    ("Weights", "tuned weights.txt")
+                )
+            }
+            other => panic!("expected setoption, got {other:?}"),
+        }
+        match Command::try_from(format!("position pos {position} moves NW/C").as_str()).unwrap() {
+            Command::Position { start, moves } => {
+                assert_eq!(start, Some(position));
+                assert_eq!(moves, [Move::try_from("NW/C").unwrap()]);
+            }
+            other => panic!("expected position, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn invalid_commands() {
+        for line in [
+            "",
+            "dance",
+            "isready now",
+            "setoption Hash",
+            "position",
+            "position startpos moves C/X",
+            "go depth",
+            "go depth deep",
+            "go ponder",
+        ] {
+            assert!(Command::try_from(line).is_err(), "{line}");
+        }
+    }
+
+    #[test]
+    fn response_round_trip() {
+        let pv = vec![
+            Move::try_from("C/C").unwrap(),
+            Move::try_from("C/NW").unwrap(),
+        ];
+        let responses = [
+            Response::Id {
+                field: "name".to_string(),
+                value: "ut3e 0.1.0".to_string(),
+            },
+            Response::Option("name Hash type spin default 16 min 1 max 4096".to_string()),
+            Response::HelloOk,
+            Response::ReadyOk,
+            Response::Info(Info {
+                depth: 6,
+                score: -35,
+                nodes: 12345,
+                time: Duration::from_millis(250),
+                pv: pv.clone(),
+            }),
+            Response::Info(Info {
+                depth: 9,
+                score: WIN - 7,
+                nodes: 1,
+                time: Duration::ZERO,
+                pv: Vec::new(),
+            }),
+            Response::Info(Info {
+                depth: 9,
+                score: -(WIN - 8),
+                nodes: 1,
+                time: Duration::ZERO,
+                pv,
+            }),
+            Response::Message("error: unexpected `dance`".to_string()),
+            Response::BestMove(Move::try_from("SE/SE").ok()),
+            Response::BestMove(None),
+        ];
+        for response in responses {
+            let line = response.to_string();
+            let parsed = Response::try_from(line.as_str()).unwrap();
+            assert_eq!(parsed.to_string(), line);
+            if let (Response::Info(info), Response::Info(parsed)) = (&response, &parsed) {
+                assert_eq!(parsed.score, info.score);
+                assert_eq!(parsed.pv, info.pv);
+            }
+        }
+        assert!(Response::try_from("bestmove C/X").is_err());
+        assert!(Response::try_from("info depth 1 score mate 3").is_err());
+    }
+}
@@ -0,0 +1,48 @@
+use crate::zobrist::splitmix64;
+
+/// A small, fast, seedable random number generator (splitmix64). Not for anything that needs to
+/// be unpredictable, but the same seed always gives the same numbers on every platform, which
+/// makes engine runs reproducible.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let (state, value) = splitmix64(self.state);
+        self.state = state;
+        value
+    }
+
+    /// A number in `0..n`, which must not be empty
+    pub fn below(&mut self, n: u64) -> u64 {
+        assert!(n > 0, "empty range");
+        // Multiplying instead of taking the remainder avoids most of the bias for small `n`
+        ((self.next_u64() as u128 * n as u128) >> 64) as u64
+    }
+
+    /// A number in `0.0..1.0`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A random element of `iter`, or `None` if it is empty
+    pub fn choose<I: ExactSizeIterator>(&mut self, mut iter: I) -> Option<I::Item> {
+        match iter.len() {
+            0 => None,
+            len => iter.nth(self.below(len as u64) as usize),
+        }
+    }
+
+    /// Puts `items` in a random order
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for idx in (1..items.len()).rev() {
+            items.swap(idx, self.below(idx as u64 + 1) as usize);
+        }
+    }
+}
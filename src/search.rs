@@ -0,0 +1,533 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::bitboard::{Move, Position};
+use crate::eval::{Evaluator, Heuristic};
+use crate::game::{GameStatus, Grid};
+use crate::tablebase::Tablebase;
+
+/// The score of winning right now. Wins further away score a point less for every extra ply, so
+/// the search goes for the quickest win and the slowest loss.
+pub const WIN: i32 = 1_000_000;
+/// No game can last longer than this many plies
+pub const MAX_PLY: usize = 81;
+
+/// How many plies until the end of the game a score promises, if it is a forced win (positive) or
+/// loss (negative) rather than an evaluation
+pub fn plies_to_end(score: i32) -> Option<u32> {
+    let plies = WIN - score.abs();
+    (plies <= MAX_PLY as i32).then_some(plies as u32)
+}
+
+/// When to stop searching. The search always finishes at least depth 1, and stops at whichever of
+/// the limits is reached first, or when there is nothing more to find. A time limit needs a clock,
+/// which isn't available on the web, so use the other limits there.
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Limits {
+    pub depth: Option<u32>,
+    pub time: Option<Duration>,
+    pub nodes: Option<u64>,
+}
+
+impl Limits {
+    pub fn depth(depth: u32) -> Self {
+        Self {
+            depth: Some(depth),
+            ..Self::default()
+        }
+    }
+
+    pub fn time(time: Duration) -> Self {
+        Self {
+            time: Some(time),
+            ..Self::default()
+        }
+    }
+
+    pub fn nodes(nodes: u64) -> Self {
+        Self {
+            nodes: Some(nodes),
+            ..Self::default()
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct SearchResult {
+    /// `None` only if the game is already over
+    pub best_move: Option<Move>,
+    /// From the point of view of the side to move, see `WIN`
+    pub score: i32,
+    /// The moves both sides are expected to play, starting with `best_move`
+    pub pv: Vec<Move>,
+    /// The last depth that was searched completely
+    pub depth: u32,
+    pub nodes: u64,
+}
+
+/// A table size for `Searcher::new` that fits in `bytes` of memory. Sizes are rounded to a power
+/// of two, so this is the largest one that fits.
+pub fn table_entries(bytes: usize) -> usize {
+    let entries = (bytes / std::mem::size_of::<Option<Entry>>()).max(1);
+    1 << entries.ilog2()
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Bound {
+    Exact,
+    // The score is at least this much (the search failed high)
+    Lower,
+    // The score is at most this much (the search failed low)
+    Upper,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Entry {
+    hash: u64,
+    depth: u32,
+    score: i32,
+    bound: Bound,
+    best_move: Option<Move>,
+}
+
+/// A negamax alpha-beta searcher with iterative deepening, a transposition table keyed by the
+/// Zobrist hash, and move ordering by the table's move, box wins, killer moves and history. The
+/// table is kept between searches, so searching the same game again later starts off ahead.
+/// Positions where the search stops are scored by `E`.
+pub struct Searcher<E: Evaluator = Heuristic> {
+    evaluator: E,
+    table: Vec<Option<Entry>>,
+    killers: [[Option<Move>; 2]; MAX_PLY + 1],
+    history: [[u32; 81]; 2],
+    pv: Vec<Vec<Move>>,
+    stop: Arc<AtomicBool>,
+    tablebase: Option<Arc<Tablebase>>,
+    deadline: Option<Instant>,
+    max_nodes: Option<u64>,
+    nodes: u64,
+    aborted: bool,
+}
+
+impl Searcher {
+    /// A searcher with a transposition table of (about) `table_size` entries
+    pub fn new(table_size: usize) -> Self {
+        Self::with_evaluator(table_size, Heuristic::default())
+    }
+}
+
+impl<E: Evaluator> Searcher<E> {
+    pub fn with_evaluator(table_size: usize, evaluator: E) -> Self {
+        Self {
+            evaluator,
+            table: vec![None; table_size.max(1).next_power_of_two()],
+            killers: [[None; 2]; MAX_PLY + 1],
+            history: [[0; 81]; 2],
+            pv: vec![Vec::new(); MAX_PLY + 1],
+            stop: Arc::new(AtomicBool::new(false)),
+            tablebase: None,
+            deadline: None,
+            max_nodes: None,
+            nodes: 0,
+            aborted: false,
+        }
+    }
+
+    /// Setting this flag from another thread makes the search return as soon as possible, with
+    /// the result of the last depth it finished. It is cleared when the search returns, so
+    /// setting it before the search has started still stops it.
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    /// Positions in `tablebase` get their exact values from it instead of being searched
+    pub fn set_tablebase(&mut self, tablebase: Option<Arc<Tablebase>>) {
+        self.tablebase = tablebase;
+    }
+
+    pub fn evaluator(&self) -> &E {
+        &self.evaluator
+    }
+
+    /// Also forgets everything learned from previous searches, since it was scored differently
+    pub fn set_evaluator(&mut self, evaluator: E) {
+        self.evaluator = evaluator;
+        self.clear();
+    }
+
+    /// Forgets everything learned from previous searches
+    pub fn clear(&mut self) {
+        self.table.iter_mut().for_each(|entry| *entry = None);
+        self.history = [[0; 81]; 2];
+    }
+
+    pub fn search_grid(&mut self, grid: &Grid, limits: Limits) -> SearchResult {
+        self.search(grid.position(), limits)
+    }
+
+    pub fn search(&mut self, position: &Position, limits: Limits) -> SearchResult {
+        self.search_with_info(position, limits, |_| ())
+    }
+
+    /// Searches like `search`, calling `info` with the result of each depth as it finishes
+    pub fn search_with_info(
+        &mut self,
+        position: &Position,
+        limits: Limits,
+        mut info: impl FnMut(&SearchResult),
+    ) -> SearchResult {
+        self.deadline = limits.time.map(|time| Instant::now() + time);
+        self.max_nodes = limits.nodes;
+        self.nodes = 0;
+        self.aborted = false;
+        self.killers = [[None; 2]; MAX_PLY + 1];
+        // Older searches count for less, which also keeps the history from growing without end
+        // when one searcher is used for a whole session
+        self.history
+            .iter_mut()
+            .flatten()
+            .for_each(|score| *score /= 2);
+
+        let mut position = *position;
+        let empty = position.empty_tiles().count_ones();
+        let max_depth = limits.depth.unwrap_or(empty).clamp(1, empty.max(1));
+        let mut result = SearchResult {
+            best_move: position.legal_moves().next(),
+            score: 0,
+            pv: Vec::new(),
+            depth: 0,
+            nodes: 0,
+        };
+        if result.best_move.is_none() {
+            self.stop.store(false, Ordering::Relaxed);
+            return result;
+        }
+
+        for depth in 1..=max_depth {
+            let score = self.negamax(&mut position, depth, 0, -WIN - 1, WIN + 1);
+            if self.aborted {
+                break;
+            }
+
+            let mut pv = self.pv[0].clone();
+            self.extend_pv(&position, &mut pv);
+            result = SearchResult {
+                best_move: pv.first().copied(),
+                score,
+                pv,
+                depth,
+                nodes: self.nodes,
+            };
+            info(&result);
+            // There is no point in looking further than the end of a forced win or loss
+            if plies_to_end(score).is_some_and(|plies| plies <= depth) {
+                break;
+            }
+        }
+
+        self.stop.store(false, Ordering::Relaxed);
+        result.nodes = self.nodes;
+        result
+    }
+
+    // The PV from the search stops at transposition table cutoffs, so follow the table's moves
+    // from there
+    fn extend_pv(&self, position: &Position, pv: &mut Vec<Move>) {
+        let mut position = *position;
+        for &mv in pv.iter() {
+            position.play(mv);
+        }
+        while pv.len() < MAX_PLY {
+            let entry = match self.probe(position.hash()) {
+                Some(entry) => entry,
+                None => return,
+            };
+            match entry.best_move {
+                Some(mv) if position.legal_moves().contains(mv) => {
+                    position.play(mv);
+                    pv.push(mv);
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn should_stop(&mut self) -> bool {
+        if self.max_nodes.is_some_and(|max| self.nodes >= max) || self.stop.load(Ordering::Relaxed)
+        {
+            return true;
+        }
+        // Checking the clock is comparatively slow, so only do it every so often
+        self.nodes.is_multiple_of(1024)
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    fn probe(&self, hash: u64) -> Option<Entry> {
+        self.table[hash as usize & (self.table.len() - 1)].filter(|entry| entry.hash == hash)
+    }
+
+    fn store(&mut self, entry: Entry) {
+        let idx = entry.hash as usize & (self.table.len() - 1);
+        let slot = &mut self.table[idx];
+        if slot.is_none_or(|old| old.hash != entry.hash || old.depth <= entry.depth) {
+            *slot = Some(entry);
+        }
+    }
+
+    fn negamax(
+        &mut self,
+        position: &mut Position,
+        depth: u32,
+        ply: usize,
+        mut alpha: i32,
+        beta: i32,
+    ) -> i32 {
+        self.nodes += 1;
+        self.pv[ply].clear();
+        // Not checking near the leaves means the first iteration always finishes, so that there is
+        // a move to return
+        if depth > 1 && ply > 0 && self.should_stop() {
+            self.aborted = true;
+            return 0;
+        }
+
+        let side = position.side_to_move();
+        match position.status() {
+            GameStatus::Won(player) if player == side => return WIN - ply as i32,
+            GameStatus::Won(_) => return -(WIN - ply as i32),
+            GameStatus::Drawn => return 0,
+            GameStatus::Ongoing => (),
+        }
+        if ply > 0 {
+            if let Some(outcome) = self.tablebase.as_ref().and_then(|tb| tb.probe(position)) {
+                return outcome.score(ply as u32);
+            }
+        }
+        if depth == 0 {
+            return self.evaluator.evaluate(position);
+        }
+
+        let hash = position.hash();
+        let mut table_move = None;
+        if let Some(entry) = self.probe(hash) {
+            table_move = entry.best_move;
+            if ply > 0 && entry.depth >= depth {
+                let score = from_table(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => (),
+                }
+            }
+        }
+
+        let mut moves = [(Move::from_index(0), 0); 81];
+        let mut len = 0;
+        for mv in position.legal_moves() {
+            let order = if Some(mv) == table_move {
+                u32::MAX
+            } else if position.wins_box(mv) {
+                u32::MAX - 1
+            } else if self.killers[ply].contains(&Some(mv)) {
+                u32::MAX - 2
+            } else {
+                self.history[side.index()][mv.index()]
+            };
+            moves[len] = (mv, order);
+            len += 1;
+        }
+        let moves = &mut moves[..len];
+        moves.sort_unstable_by_key(|&(_, order)| std::cmp::Reverse(order));
+
+        let original_alpha = alpha;
+        let mut best = -WIN - 1;
+        let mut best_move = None;
+        for &(mv, _) in moves.iter() {
+            let undo = position.play(mv);
+            let score = -self.negamax(position, depth - 1, ply + 1, -beta, -alpha);
+            position.undo(mv, undo);
+            if self.aborted {
+                return 0;
+            }
+
+            if score > best {
+                best = score;
+                best_move = Some(mv);
+                if score > alpha {
+                    alpha = score;
+                    let (head, tail) = self.pv.split_at_mut(ply + 1);
+                    head[ply].clear();
+                    head[ply].push(mv);
+                    head[ply].extend_from_slice(&tail[0]);
+                }
+                if alpha >= beta {
+                    if !position.wins_box(mv) && self.killers[ply][0] != Some(mv) {
+                        self.killers[ply] = [Some(mv), self.killers[ply][0]];
+                    }
+                    let score = &mut self.history[side.index()][mv.index()];
+                    *score = score.saturating_add(depth * depth);
+                    break;
+                }
+            }
+        }
+
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.store(Entry {
+            hash,
+            depth,
+            score: to_table(best, ply),
+            bound,
+            best_move,
+        });
+
+        best
+    }
+}
+
+impl Default for Searcher {
+    fn default() -> Self {
+        Searcher::new(1 << 20)
+    }
+}
+
+// Wins and losses are stored relative to the position they were found in, rather than the root,
+// so they stay right when the position is reached at a different ply
+fn to_table(score: i32, ply: usize) -> i32 {
+    match plies_to_end(score) {
+        Some(_) => score + score.signum() * ply as i32,
+        None => score,
+    }
+}
+
+fn from_table(score: i32, ply: usize) -> i32 {
+    match plies_to_end(score) {
+        Some(_) => score - score.signum() * ply as i32,
+        None => score,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::Direction::*;
+
+    // X has won NW and N, and wins NE and the game with NE/NE
+    const MATE_IN_1: &str = "XXXOO..../XXXOO..../XX.OO..../........./OO......./........./\
+                             ........./........./......... XX....... X NE absolute";
+    // X sends O to S with SW/S, where O has to send X to NE or let them play anywhere, and X
+    // then wins with NE/NE
+    const MATE_IN_2: &str = "XXXOO..../XXXOO..../XX.OO..../O......../........./OO......./\
+                             ........./.X.OXOXOX/......... XX....... X SW absolute";
+    // O to move in C, where C/NW and C/NE let X win with NE/NE
+    const BLUNDER: &str = "XXXOO..../XXXOO..../XX.OO..../........./.O.X.OOXX/O......../\
+                           ........./........./......... XX....... O C absolute";
+
+    fn position(string: &str) -> Position {
+        Position::try_from(string).unwrap()
+    }
+
+    // Checks that `result.pv` starts with the best move and can be played out from `position`
+    fn assert_pv_replays(position: &Position, result: &SearchResult) {
+        assert_eq!(result.pv.first().copied(), result.best_move);
+        let mut position = *position;
+        for &mv in &result.pv {
+            assert!(position.legal_moves().contains(mv), "{mv:?} in {position}");
+            position.play(mv);
+        }
+    }
+
+    #[test]
+    fn finds_mates() {
+        for (string, best_move, plies) in [(MATE_IN_1, (NE, NE), 1), (MATE_IN_2, (SW, S), 3)] {
+            let position = position(string);
+            let result = Searcher::new(1 << 16).search(&position, Limits::depth(6));
+            assert_eq!(result.best_move, Some(Move::new(best_move)), "{string}");
+            assert_eq!(plies_to_end(result.score), Some(plies), "{string}");
+            assert!(result.score > 0);
+            assert_eq!(result.pv.len(), plies as usize);
+            assert_pv_replays(&position, &result);
+
+            let mut end = position;
+            for &mv in &result.pv {
+                end.play(mv);
+            }
+            assert_eq!(end.status(), GameStatus::Won(position.side_to_move()));
+        }
+    }
+
+    #[test]
+    fn avoids_losing_at_once() {
+        let position = position(BLUNDER);
+        for depth in 2..=4 {
+            let result = Searcher::new(1 << 16).search(&position, Limits::depth(depth));
+            assert_eq!(result.best_move, Some(Move::new((C, C))), "depth {depth}");
+            assert_ne!(plies_to_end(result.score), Some(2));
+        }
+    }
+
+    #[test]
+    fn limits_stop_the_search() {
+        let position = Position::default();
+        let mut searcher = Searcher::new(1 << 16);
+
+        let result = searcher.search(&position, Limits::depth(3));
+        assert_eq!(result.depth, 3);
+        assert_pv_replays(&position, &result);
+
+        let result = searcher.search(&position, Limits::nodes(2_000));
+        assert!(result.depth >= 1 && result.depth < 81);
+        // Nodes with fewer than two plies left don't check the limit, so it overshoots a little
+        assert!(result.nodes < 2 * 2_000, "{} nodes", result.nodes);
+        assert!(position.legal_moves().contains(result.best_move.unwrap()));
+        assert_pv_replays(&position, &result);
+
+        // Only nodes below the root with at least two plies left check the flag, so depth 2 still
+        // finishes
+        searcher.stop_flag().store(true, Ordering::Relaxed);
+        let result = searcher.search(&position, Limits::default());
+        assert_eq!(result.depth, 2);
+        assert!(position.legal_moves().contains(result.best_move.unwrap()));
+        assert_pv_replays(&position, &result);
+        assert!(!searcher.stop_flag().load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn pv_replays_from_the_root() {
+        let mut searcher = Searcher::new(1 << 16);
+        let mut position = Position::new(crate::game::RuleSet::RELATIVE);
+        for _ in 0..6 {
+            let result = searcher.search(&position, Limits::depth(5));
+            assert_eq!(result.depth, 5);
+            assert_pv_replays(&position, &result);
+            position.play(result.best_move.unwrap());
+        }
+    }
+
+    #[test]
+    fn history_does_not_overflow() {
+        let mut searcher = Searcher::new(1 << 16);
+        searcher.history = [[u32::MAX; 81]; 2];
+        for _ in 0..3 {
+            searcher.search(&Position::default(), Limits::depth(4));
+        }
+        assert!(searcher.history.iter().flatten().all(|&score| score < u32::MAX));
+    }
+
+    #[test]
+    fn finished_games_have_no_move() {
+        let mut position = position(MATE_IN_1);
+        position.play(Move::new((NE, NE)));
+        let result = Searcher::new(1 << 16).search(&position, Limits::default());
+        assert_eq!(result.best_move, None);
+        assert!(result.pv.is_empty());
+    }
+}
@@ -0,0 +1,485 @@
+//! Games played by engines against themselves, recorded as training data: every position, how
+//! the engine spread its search over the moves there, and how the game ended.
+//!
+//! A self-play file is little endian, and laid out as:
+//!
+//! | bytes      | contents                                                  |
+//! |------------|-----------------------------------------------------------|
+//! | 4          | `UT3S`                                                    |
+//! | 1          | format version, currently 1                               |
+//! | 1          | length of the rules string                                |
+//! | n          | the rules, as written by `RuleSet`'s `Display`            |
+//! | 210 each   | records, until the end of the file                        |
+//!
+//! Every record is the same size, so they can be read straight into an array:
+//!
+//! | bytes | contents                                                                   |
+//! |-------|----------------------------------------------------------------------------|
+//! | 8     | game number (`u64`)                                                        |
+//! | 1     | how many records the game has (`u8`)                                       |
+//! | 16    | X's tiles (`u128`), bit `9 * box + tile`                                   |
+//! | 16    | O's tiles (`u128`)                                                         |
+//! | 2     | boxes won by X (`u16`), bit `box`                                          |
+//! | 2     | boxes won by O (`u16`)                                                     |
+//! | 1     | side to move: 0 for X, 1 for O                                             |
+//! | 1     | box the side to move has to play in, or 255 for any                        |
+//! | 162   | for each of the 81 tiles (`u16`), its share of the search out of 65535     |
+//! | 1     | the result for the side to move (`i8`): 1 for a win, 0 a draw, -1 a loss   |
+//!
+//! Boxes and tiles are numbered like `Direction::index`. The records of a game are written
+//! together, after the game ends.
+
+use std::collections::{HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::bitboard::Position;
+use crate::engine::Engine;
+use crate::error::UT3Error;
+use crate::eval::Evaluator;
+use crate::game::{Direction, GameStatus, Player, RuleSet};
+use crate::mcts::{Budget, Mcts, MctsConfig};
+use crate::rng::Rng;
+use crate::tournament::random_opening;
+use crate::zobrist::splitmix64;
+
+const MAGIC: &[u8; 4] = b"UT3S";
+const VERSION: u8 = 1;
+/// The size of a record in bytes
+pub const RECORD_SIZE: usize = 210;
+// What a whole policy adds up to
+const POLICY_SCALE: f32 = 65535.0;
+
+#[derive(Clone, Debug)]
+pub struct Record {
+    pub game: u64,
+    /// How many records `game` has
+    pub length: u8,
+    pub position: Position,
+    /// How much of the search went to each move, by `Move::index`, adding up to 1
+    pub policy: [f32; 81],
+    /// For the side to move: 1 for a win, 0 for a draw and -1 for a loss
+    pub result: i8,
+}
+
+impl Record {
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let position = &self.position;
+        let mut bytes = Vec::with_capacity(RECORD_SIZE);
+        bytes.extend_from_slice(&self.game.to_le_bytes());
+        bytes.push(self.length);
+        for player in [Player::X, Player::O] {
+            bytes.extend_from_slice(&position.tiles(player).to_le_bytes());
+        }
+        for player in [Player::X, Player::O] {
+            bytes.extend_from_slice(&position.box_winners(player).to_le_bytes());
+        }
+        bytes.push(position.side_to_move().index() as u8);
+        bytes.push(position.track().map_or(255, |track| track.index() as u8));
+        for share in self.policy {
+            let share = (share * POLICY_SCALE).round() as u16;
+            bytes.extend_from_slice(&share.to_le_bytes());
+        }
+        bytes.push(self.result as u8);
+        writer.write_all(&bytes)
+    }
+
+    /// Reads the next record, or `None` at the end of the file. A record cut off part way through
+    /// is an error.
+    pub fn read(reader: &mut impl Read, rules: RuleSet) -> Result<Option<Self>, UT3Error> {
+        let mut bytes = [0; RECORD_SIZE];
+        let mut len = 0;
+        while len < RECORD_SIZE {
+            match reader.read(&mut bytes[len..])? {
+                0 if len == 0 => return Ok(None),
+                0 => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+                read => len += read,
+            }
+        }
+
+        let u128_at = |idx: usize| u128::from_le_bytes(bytes[idx..idx + 16].try_into().unwrap());
+        let u16_at = |idx: usize| u16::from_le_bytes([bytes[idx], bytes[idx + 1]]);
+        let side_to_move = match bytes[45] {
+            0 => Player::X,
+            1 => Player::O,
+            side => {
+                return Err(UT3Error::InvalidFile(format!(
+                    "invalid side to move {side}"
+                )))
+            }
+        };
+        let track = match bytes[46] {
+            255 => None,
+            track => Some(Direction::try_from(track as u32)?),
+        };
+        let position = Position::from_bitboards(
+            [u128_at(9), u128_at(25)],
+            [u16_at(41), u16_at(43)],
+            side_to_move,
+            track,
+            rules,
+        )?;
+
+        let mut policy = [0.0; 81];
+        for (idx, share) in policy.iter_mut().enumerate() {
+            *share = u16_at(47 + 2 * idx) as f32 / POLICY_SCALE;
+        }
+        Ok(Some(Self {
+            game: u64::from_le_bytes(bytes[..8].try_into().unwrap()),
+            length: bytes[8],
+            position,
+            policy,
+            result: bytes[RECORD_SIZE - 1] as i8,
+        }))
+    }
+}
+
+pub fn write_header(writer: &mut impl Write, rules: RuleSet) -> io::Result<()> {
+    let rules = rules.to_string();
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[VERSION, rules.len() as u8])?;
+    writer.write_all(rules.as_bytes())
+}
+
+/// Reads the header, returning the rules of the games in the file
+pub fn read_header(reader: &mut impl Read) -> Result<RuleSet, UT3Error> {
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(UT3Error::InvalidFile("not a self-play file".to_string()));
+    }
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+    if header[0] != VERSION {
+        return Err(UT3Error::InvalidFile(format!(
+            "unsupported self-play version {}",
+            header[0]
+        )));
+    }
+    let mut rules = vec![0; header[1] as usize];
+    reader.read_exact(&mut rules)?;
+    let rules = String::from_utf8(rules)
+        .map_err(|_| UT3Error::InvalidFile("rules aren't UTF-8".to_string()))?;
+    RuleSet::try_from(rules.as_str())
+}
+
+/// Reads a whole file, returning the rules and every record
+pub fn read_all(reader: &mut impl Read) -> Result<(RuleSet, Vec<Record>), UT3Error> {
+    let rules = read_header(reader)?;
+    let mut records = Vec::new();
+    while let Some(record) = Record::read(reader, rules)? {
+        records.push(record);
+    }
+    Ok((rules, records))
+}
+
+/// The size of the header for `rules`, where the records start
+pub fn header_size(rules: RuleSet) -> usize {
+    6 + rules.to_string().len()
+}
+
+/// Who plays the games
+pub enum SelfPlayer {
+    /// MCTS playing itself. The visits of each move are the policy, and for the first
+    /// `sample_plies` plies the move is picked at random in proportion to them, rather than
+    /// always the most visited, so that games differ.
+    Mcts {
+        config: MctsConfig,
+        evaluator: Box<dyn Evaluator>,
+        budget: Budget,
+        sample_plies: u32,
+    },
+    /// Two engines, the first playing X. The policy is all on the move they played.
+    Engines([Box<dyn Engine>; 2]),
+}
+
+// A `SelfPlayer` set up for one game
+enum Mover<'a> {
+    Mcts {
+        mcts: Mcts<&'a dyn Evaluator>,
+        budget: Budget,
+        sample_plies: u32,
+    },
+    Engines(&'a mut [Box<dyn Engine>; 2]),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SelfPlayConfig {
+    pub rules: RuleSet,
+    /// How many random moves each game starts with, see `tournament::random_opening`. They aren't
+    /// recorded, and never end the game, so that every game has records to mark it finished.
+    pub opening_plies: u32,
+    pub seed: u64,
+}
+
+/// The seed for game number `game`, so that each game plays out the same however many threads
+/// are playing and in whatever order
+pub fn game_seed(seed: u64, game: u64) -> u64 {
+    splitmix64(seed ^ splitmix64(game).1).1
+}
+
+impl SelfPlayer {
+    /// Plays game number `game`, returning a record for every position after the opening
+    pub fn play(&mut self, game: u64, config: &SelfPlayConfig) -> Result<Vec<Record>, UT3Error> {
+        let mut rng = Rng::new(game_seed(config.seed, game));
+        let mut grid = random_opening(config.rules, config.opening_plies, &mut rng);
+
+        let mut mover = match self {
+            SelfPlayer::Mcts {
+                config,
+                evaluator,
+                budget,
+                sample_plies,
+            } => Mover::Mcts {
+                mcts: Mcts::with_evaluator(
+                    MctsConfig {
+                        seed: rng.next_u64(),
+                        ..*config
+                    },
+                    &**evaluator,
+                ),
+                budget: *budget,
+                sample_plies: *sample_plies,
+            },
+            SelfPlayer::Engines(engines) => {
+                for engine in engines.iter_mut() {
+                    engine.new_game()?;
+                }
+                Mover::Engines(engines)
+            }
+        };
+
+        let mut records = Vec::new();
+        while !grid.status().is_over() {
+            let mut policy = [0.0; 81];
+            let mv = match &mut mover {
+                Mover::Mcts {
+                    mcts,
+                    budget,
+                    sample_plies,
+                } => {
+                    let result = mcts.search_grid(&grid, *budget);
+                    let total = result.moves.iter().map(|stats| stats.visits).sum::<u64>();
+                    for stats in &result.moves {
+                        policy[stats.mv.index()] = stats.visits as f32 / total.max(1) as f32;
+                    }
+                    if (records.len() as u32) < *sample_plies && total > 0 {
+                        let mut pick = rng.below(total);
+                        let stats = result.moves.iter().find(|stats| {
+                            let found = pick < stats.visits;
+                            pick = pick.saturating_sub(stats.visits);
+                            found
+                        });
+                        stats.map(|stats| stats.mv)
+                    } else {
+                        result.best_move
+                    }
+                }
+                Mover::Engines(engines) => {
+                    engines[grid.current_player().index()].best_move(&grid)?
+                }
+            }
+            .ok_or(UT3Error::MissingField("move"))?;
+            if policy.iter().all(|&share| share == 0.0) {
+                policy[mv.index()] = 1.0;
+            }
+
+            records.push(Record {
+                game,
+                length: 0,
+                position: *grid.position(),
+                policy,
+                result: 0,
+            });
+            grid.apply_turn(mv.coords())?;
+        }
+
+        let status = grid.status();
+        let length = records.len() as u8;
+        for record in &mut records {
+            record.length = length;
+            record.result = match status {
+                GameStatus::Won(winner) if winner == record.position.side_to_move() => 1,
+                GameStatus::Won(_) => -1,
+                _ => 0,
+            };
+        }
+        Ok(records)
+    }
+}
+
+/// The games already in the self-play file at `path`, cutting off the records of a game that was
+/// only partly written so that more can be appended. Anything wrong with the file other than a
+/// record cut off at the end is an error, and leaves the file as it was.
+pub fn finished_games(path: impl AsRef<Path>, rules: RuleSet) -> Result<HashSet<u64>, UT3Error> {
+    let path = path.as_ref();
+    let mut reader = BufReader::new(File::open(path)?);
+    if read_header(&mut reader)? != rules {
+        return Err(UT3Error::InvalidFile(format!(
+            "`{}` has games with other rules",
+            path.display()
+        )));
+    }
+
+    let mut records = HashMap::<u64, u8>::new();
+    let mut finished = HashSet::new();
+    let mut valid_records = 0;
+    let mut count = 0;
+    loop {
+        let record = match Record::read(&mut reader, rules) {
+            Ok(Some(record)) => record,
+            Ok(None) => break,
+            Err(UT3Error::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => return Err(err),
+        };
+        count += 1;
+        let seen = records.entry(record.game).or_default();
+        *seen += 1;
+        if *seen == record.length {
+            finished.insert(record.game);
+            valid_records = count;
+        }
+    }
+
+    let valid_len = header_size(rules) + valid_records * RECORD_SIZE;
+    OpenOptions::new()
+        .write(true)
+        .open(path)?
+        .set_len(valid_len as u64)?;
+    Ok(finished)
+}
+
+/// Plays `games` on `threads` threads, each with its own player from `player`, and hands the
+/// records of each game to `on_game` on this thread as they finish. Stops at the first error.
+pub fn play_games(
+    games: &[u64],
+    threads: usize,
+    config: &SelfPlayConfig,
+    player: impl Fn() -> Result<SelfPlayer, UT3Error> + Sync,
+    mut on_game: impl FnMut(Vec<Record>) -> Result<(), UT3Error>,
+) -> Result<(), UT3Error> {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            let sender = sender.clone();
+            let (next, player) = (&next, &player);
+            scope.spawn(move || {
+                let mut player = match player() {
+                    Ok(player) => player,
+                    Err(err) => return sender.send(Err(err)).unwrap_or(()),
+                };
+                while let Some(&game) = games.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let records = player.play(game, config);
+                    let failed = records.is_err();
+                    // The receiver only goes away after an error, when there's no point going on
+                    if sender.send(records).is_err() || failed {
+                        return;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for records in receiver {
+            on_game(records?)?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULES: RuleSet = RuleSet::RELATIVE;
+
+    // `lengths[game]` records for each game, in order
+    fn file_bytes(lengths: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_header(&mut bytes, RULES).unwrap();
+        for (game, &length) in lengths.iter().enumerate() {
+            for _ in 0..length {
+                let mut policy = [0.0; 81];
+                policy[40] = 1.0;
+                Record {
+                    game: game as u64,
+                    length,
+                    position: Position::new(RULES),
+                    policy,
+                    result: 0,
+                }
+                .write(&mut bytes)
+                .unwrap();
+            }
+        }
+        bytes
+    }
+
+    // Writes `bytes` to a file of its own, and returns what `finished_games` makes of it along
+    // with the file's length afterwards
+    fn resume(name: &str, bytes: &[u8]) -> (Result<HashSet<u64>, UT3Error>, usize) {
+        let path = std::env::temp_dir().join(format!("ut3e-{}-{name}.ut3s", std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        let finished = finished_games(&path, RULES);
+        let len = std::fs::metadata(&path).unwrap().len() as usize;
+        std::fs::remove_file(&path).unwrap();
+        (finished, len)
+    }
+
+    #[test]
+    fn resume_keeps_finished_games() {
+        let bytes = file_bytes(&[3, 2]);
+        let (finished, len) = resume("finished", &bytes);
+        assert_eq!(finished.unwrap(), HashSet::from([0, 1]));
+        assert_eq!(len, bytes.len());
+        assert_eq!(read_all(&mut bytes.as_slice()).unwrap().1.len(), 5);
+    }
+
+    #[test]
+    fn resume_cuts_off_a_partly_written_game() {
+        let finished_len = header_size(RULES) + 3 * RECORD_SIZE;
+        let bytes = file_bytes(&[3, 4]);
+
+        // Two whole records of the second game and half of the third
+        let (finished, len) = resume("partial", &bytes[..finished_len + 5 * RECORD_SIZE / 2]);
+        assert_eq!(finished.unwrap(), HashSet::from([0]));
+        assert_eq!(len, finished_len);
+    }
+
+    #[test]
+    fn resume_stops_at_a_corrupt_record() {
+        let mut bytes = file_bytes(&[3, 2]);
+        // The side to move of the second record
+        bytes[header_size(RULES) + RECORD_SIZE + 45] = 7;
+        let (finished, len) = resume("corrupt", &bytes);
+        assert!(matches!(finished, Err(UT3Error::InvalidFile(_))));
+        assert_eq!(len, bytes.len());
+    }
+
+    #[test]
+    fn long_openings_still_leave_records() {
+        let mut player = SelfPlayer::Mcts {
+            config: MctsConfig::default(),
+            evaluator: Box::new(crate::eval::Heuristic::default()),
+            budget: Budget::Iterations(10),
+            sample_plies: 0,
+        };
+        let config = SelfPlayConfig {
+            rules: RULES,
+            opening_plies: 81,
+            seed: 5,
+        };
+        for game in 0..10 {
+            let records = player.play(game, &config).unwrap();
+            assert!(!records.is_empty());
+            assert!(records
+                .iter()
+                .all(|record| record.length as usize == records.len()));
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::bitboard::{box_tiles, Move, Position, LINES};
+use crate::game::{Direction, GameStatus, Grid, Player};
+use crate::search::{plies_to_end, WIN};
+use crate::symmetry::{canonical, Transform};
+
+/// The game-theoretic value of a position for the side to move, with how many plies the game
+/// lasts when both sides play perfectly (the winner as quickly as possible, the loser as slowly)
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Outcome {
+    Win(u32),
+    Draw,
+    Loss(u32),
+}
+
+impl Outcome {
+    /// Reads a score from the searchers, see `search::WIN`
+    pub fn from_score(score: i32) -> Self {
+        match plies_to_end(score) {
+            Some(plies) if score > 0 => Outcome::Win(plies),
+            Some(plies) => Outcome::Loss(plies),
+            None => Outcome::Draw,
+        }
+    }
+
+    /// The score the searchers would give this outcome, `ply` plies from where they started
+    pub fn score(&self, ply: u32) -> i32 {
+        match self {
+            Outcome::Win(plies) => WIN - (ply + plies) as i32,
+            Outcome::Draw => 0,
+            Outcome::Loss(plies) => -(WIN - (ply + plies) as i32),
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Win(plies) => write!(f, "win in {plies} plies"),
+            Outcome::Draw => write!(f, "draw"),
+            Outcome::Loss(plies) => write!(f, "loss in {plies} plies"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Solution {
+    pub outcome: Outcome,
+    /// A perfect continuation to the end of the game (or to where it is a dead draw)
+    pub pv: Vec<Move>,
+    pub nodes: u64,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum Bound {
+    Exact,
+    Lower,
+    Upper,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Entry {
+    key: u64,
+    score: i32,
+    bound: Bound,
+    // In the canonical orientation
+    best_move: Option<Move>,
+}
+
+/// Solves positions exactly with alpha-beta searched all the way to the end of the game. The
+/// transposition table is keyed by the canonical position, so all 8 symmetric versions of a
+/// position share an entry, and positions where neither player can make a line on the grid any
+/// more are known draws without playing them out. Only practical for late positions.
+pub struct Solver {
+    table: Vec<Option<Entry>>,
+    stop: Arc<AtomicBool>,
+    nodes: u64,
+    aborted: bool,
+}
+
+impl Solver {
+    /// A solver with a transposition table of (about) `table_size` entries
+    pub fn new(table_size: usize) -> Self {
+        Self {
+            table: vec![None; table_size.max(1).next_power_of_two()],
+            stop: Arc::new(AtomicBool::new(false)),
+            nodes: 0,
+            aborted: false,
+        }
+    }
+
+    /// Setting this flag from another thread makes `solve` give up and return `None`
+    pub fn stop_flag(&self) -> Arc<AtomicBool> {
+        self.stop.clone()
+    }
+
+    pub fn solve(&mut self, grid: &Grid) -> Option<Solution> {
+        self.solve_position(grid.position())
+    }
+
+    pub fn solve_position(&mut self, position: &Position) -> Option<Solution> {
+        self.stop.store(false, Ordering::Relaxed);
+        self.nodes = 0;
+        self.aborted = false;
+
+        let mut position = *position;
+        let score = self.value(&mut position)?;
+
+        // Walk down the moves that keep the value, which are quick to check with the table full
+        let mut pv = Vec::new();
+        let mut current = score;
+        while !position.status().is_over() && !is_dead_draw(&position) {
+            let mut next = None;
+            for mv in position.legal_moves() {
+                let undo = position.play(mv);
+                let child = self.value(&mut position);
+                position.undo(mv, undo);
+                if parent_score(child?) == current {
+                    next = Some((mv, child?));
+                    break;
+                }
+            }
+            let (mv, child) = next.expect("some move keeps the value of the position");
+            position.play(mv);
+            pv.push(mv);
+            current = child;
+        }
+
+        Some(Solution {
+            outcome: Outcome::from_score(score),
+            pv,
+            nodes: self.nodes,
+        })
+    }
+
+    /// The exact value of `position` for the side to move, see `search::WIN`
+    fn value(&mut self, position: &mut Position) -> Option<i32> {
+        let score = self.negamax(position, 0, -WIN - 1, WIN + 1);
+        (!self.aborted).then_some(score)
+    }
+
+    fn slot(&self, key: u64) -> usize {
+        key as usize & (self.table.len() - 1)
+    }
+
+    fn negamax(&mut self, position: &mut Position, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(1024) && self.stop.load(Ordering::Relaxed) {
+            self.aborted = true;
+        }
+        if self.aborted {
+            return 0;
+        }
+
+        let side = position.side_to_move();
+        match position.status() {
+            GameStatus::Won(player) if player == side => return WIN - ply as i32,
+            GameStatus::Won(_) => return -(WIN - ply as i32),
+            GameStatus::Drawn => return 0,
+            GameStatus::Ongoing => (),
+        }
+        if is_dead_draw(position) {
+            return 0;
+        }
+
+        let (canonical_position, symmetry) = canonical(position);
+        let key = canonical_position.hash();
+        let mut table_move = None;
+        if let Some(entry) = self.table[self.slot(key)].filter(|entry| entry.key == key) {
+            let score = from_table(entry.score, ply);
+            match entry.bound {
+                Bound::Exact => return score,
+                Bound::Lower if score >= beta => return score,
+                Bound::Upper if score <= alpha => return score,
+                _ => (),
+            }
+            table_move = entry.best_move.map(|mv| mv.transform(symmetry.inverse()));
+        }
+
+        let mut moves = [(Move::from_index(0), 0); 81];
+        let mut len = 0;
+        for mv in position.legal_moves() {
+            moves[len] = (mv, move_order(position, mv, table_move));
+            len += 1;
+        }
+        let moves = &mut moves[..len];
+        moves.sort_unstable_by_key(|&(_, order)| std::cmp::Reverse(order));
+
+        let original_alpha = alpha;
+        let mut best = -WIN - 1;
+        let mut best_move = None;
+        for &(mv, _) in moves.iter() {
+            let undo = position.play(mv);
+            let score = -self.negamax(position, ply + 1, -beta, -alpha);
+            position.undo(mv, undo);
+            // Nothing found after stopping can be trusted, so it mustn't go in the table
+            if self.aborted {
+                return 0;
+            }
+            if score > best {
+                best = score;
+                best_move = Some(mv);
+                alpha = alpha.max(score);
+                if alpha >= beta {
+                    break;
+                }
+            }
+        }
+
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        let slot = self.slot(key);
+        self.table[slot] = Some(Entry {
+            key,
+            score: to_table(best, ply),
+            bound,
+            best_move: best_move.map(|mv| mv.transform(symmetry)),
+        });
+
+        best
+    }
+}
+
+impl Default for Solver {
+    fn default() -> Self {
+        Solver::new(1 << 20)
+    }
+}
+
+// The table's move first, then moves that win a box, and moves that give the opponent a free
+// choice of box last
+fn move_order(position: &mut Position, mv: Move, table_move: Option<Move>) -> u32 {
+    if Some(mv) == table_move {
+        return 3;
+    }
+    if position.wins_box(mv) {
+        return 2;
+    }
+    let undo = position.play(mv);
+    let free = position.track().is_none();
+    position.undo(mv, undo);
+    if free {
+        0
+    } else {
+        1
+    }
+}
+
+/// A child's score seen from its parent, one ply further from the end
+pub(crate) fn parent_score(child: i32) -> i32 {
+    match plies_to_end(child) {
+        Some(_) => -child - (-child).signum(),
+        None => -child,
+    }
+}
+
+// Wins and losses are stored relative to the position they were found in, rather than the root,
+// so they stay right when the position is reached at a different ply
+fn to_table(score: i32, ply: usize) -> i32 {
+    match plies_to_end(score) {
+        Some(_) => score + score.signum() * ply as i32,
+        None => score,
+    }
+}
+
+fn from_table(score: i32, ply: usize) -> i32 {
+    match plies_to_end(score) {
+        Some(_) => score - score.signum() * ply as i32,
+        None => score,
+    }
+}
+
+// Boxes `player` has won or could still win
+fn winnable_boxes(position: &Position, player: Player) -> u16 {
+    let won = position.box_winners(player.opponent()) | position.box_winners(player);
+    let full = position.full_boxes();
+    let theirs = position.tiles(player.opponent());
+
+    let mut boxes = position.box_winners(player);
+    for dir in Direction::ALL {
+        let idx = dir.index();
+        if (won | full) & (1 << idx) != 0 {
+            continue;
+        }
+        let blocked = box_tiles(theirs, idx);
+        if LINES.iter().any(|&line| line & blocked == 0) {
+            boxes |= 1 << idx;
+        }
+    }
+    boxes
+}
+
+/// Whether neither player can make a line on the grid any more, so the game is bound to end in
+/// a draw. Drawn boxes counting for both players or draws going to whoever won more boxes make
+/// that much harder to know, so then this only looks for the simple cases.
+pub fn is_dead_draw(position: &Position) -> bool {
+    let rules = position.rules();
+    if rules.decide_draws_by_box_count {
+        return false;
+    }
+
+    [Player::X, Player::O].into_iter().all(|player| {
+        let usable = if rules.drawn_boxes_count_for_both {
+            // Any box the opponent hasn't won might still fill up without a winner
+            !position.box_winners(player.opponent())
+        } else {
+            winnable_boxes(position, player)
+        };
+        !LINES.iter().any(|&line| line & !usable == 0)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::RuleSet;
+    use crate::rng::Rng;
+
+    // The first position of random games under `rules` with at most `open` tiles left to play in
+    fn late_positions(rules: RuleSet, open: usize, count: usize, rng: &mut Rng) -> Vec<Position> {
+        let mut positions = Vec::new();
+        while positions.len() < count {
+            let mut position = Position::new(rules);
+            while !position.status().is_over() {
+                if position.moves_in(None).len() <= open {
+                    positions.push(position);
+                    break;
+                }
+                position.play(rng.choose(position.legal_moves()).unwrap());
+            }
+        }
+        positions
+    }
+
+    // Plain negamax over every move to the end of the game, scored like the solver
+    fn brute_force(position: &mut Position, ply: usize) -> i32 {
+        let side = position.side_to_move();
+        match position.status() {
+            GameStatus::Won(player) if player == side => return WIN - ply as i32,
+            GameStatus::Won(_) => return -(WIN - ply as i32),
+            GameStatus::Drawn => return 0,
+            GameStatus::Ongoing => (),
+        }
+        let mut best = -WIN - 1;
+        for mv in position.legal_moves() {
+            let undo = position.play(mv);
+            best = best.max(-brute_force(position, ply + 1));
+            position.undo(mv, undo);
+        }
+        best
+    }
+
+    // Whether some way of playing on, by both sides, ends with a line on the grid
+    fn anyone_can_win(position: &mut Position) -> bool {
+        match position.status() {
+            GameStatus::Won(_) => return true,
+            GameStatus::Drawn => return false,
+            GameStatus::Ongoing => (),
+        }
+        position.legal_moves().any(|mv| {
+            let undo = position.play(mv);
+            let win = anyone_can_win(position);
+            position.undo(mv, undo);
+            win
+        })
+    }
+
+    #[test]
+    fn agrees_with_brute_force() {
+        let mut rng = Rng::new(11);
+        let mut solver = Solver::new(1 << 16);
+        for rules in [RuleSet::CLASSIC, RuleSet::RELATIVE, RuleSet::BOX_COUNT] {
+            for position in late_positions(rules, 9, 12, &mut rng) {
+                let expected = Outcome::from_score(brute_force(&mut position.clone(), 0));
+                let solution = solver.solve_position(&position).unwrap();
+                assert_eq!(solution.outcome, expected, "{position}");
+            }
+        }
+    }
+
+    #[test]
+    fn pv_reaches_the_outcome() {
+        let mut rng = Rng::new(12);
+        let mut solver = Solver::new(1 << 16);
+        for (_, rules) in RuleSet::PRESETS {
+            for position in late_positions(rules, 12, 8, &mut rng) {
+                let solution = solver.solve_position(&position).unwrap();
+                let side = position.side_to_move();
+                let mut end = position;
+                for &mv in &solution.pv {
+                    assert!(end.legal_moves().contains(mv), "{mv:?} in {end}");
+                    end.play(mv);
+                }
+                let plies = solution.pv.len() as u32;
+                match solution.outcome {
+                    Outcome::Win(win) => {
+                        assert_eq!((end.status(), plies), (GameStatus::Won(side), win))
+                    }
+                    Outcome::Loss(loss) => assert_eq!(
+                        (end.status(), plies),
+                        (GameStatus::Won(side.opponent()), loss)
+                    ),
+                    Outcome::Draw => assert!(
+                        end.status() == GameStatus::Drawn || is_dead_draw(&end),
+                        "{end}"
+                    ),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn dead_draws_only_without_lines_left() {
+        let mut rng = Rng::new(13);
+        let mut dead = 0;
+        for (_, rules) in RuleSet::PRESETS {
+            for position in late_positions(rules, 9, 40, &mut rng) {
+                if is_dead_draw(&position) {
+                    assert!(!rules.decide_draws_by_box_count);
+                    assert!(!anyone_can_win(&mut position.clone()), "{position}");
+                    dead += 1;
+                }
+            }
+        }
+        assert!(dead > 0);
+
+        // Every line on the grid has a box won by each player, with SE still open
+        let string = "OOO....../OOO....../XXX....../XXX....../XXX....../OOO....../\
+                      OOO....../XXX....../......... OOXXXOOX. X SE absolute";
+        assert!(is_dead_draw(&Position::try_from(string).unwrap()));
+        // Unless draws go to whoever won more boxes
+        let box_count = string.replace("absolute", "absolute+box-count");
+        assert!(!is_dead_draw(
+            &Position::try_from(box_count.as_str()).unwrap()
+        ));
+    }
+}
@@ -0,0 +1,267 @@
+use crate::bitboard::{Move, Position};
+use crate::game::{Direction, Grid, Player, Turn};
+
+// Every symmetry acts the same way on a box's position in the grid and on a tile's position in its
+// box. They are all linear maps around the center, so they also commute with the wrapping addition
+// `Variant::Relative` uses to pick the next box (`g(a) + g(b) == g(a + b)`), which means
+// transforming a whole game gives a game that is legal under the same rules.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum Symmetry {
+    Identity,
+    /// A quarter turn clockwise
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    /// Swaps west and east
+    FlipHorizontal,
+    /// Swaps north and south
+    FlipVertical,
+    /// Mirrors along the line from NW to SE
+    FlipDiagonal,
+    /// Mirrors along the line from NE to SW
+    FlipAntiDiagonal,
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 8] = [
+        Symmetry::Identity,
+        Symmetry::Rotate90,
+        Symmetry::Rotate180,
+        Symmetry::Rotate270,
+        Symmetry::FlipHorizontal,
+        Symmetry::FlipVertical,
+        Symmetry::FlipDiagonal,
+        Symmetry::FlipAntiDiagonal,
+    ];
+
+    pub fn inverse(&self) -> Symmetry {
+        match self {
+            Symmetry::Rotate90 => Symmetry::Rotate270,
+            Symmetry::Rotate270 => Symmetry::Rotate90,
+            other => *other,
+        }
+    }
+
+    /// The symmetry that is the same as applying `self` and then `other`
+    pub fn then(&self, other: Symmetry) -> Symmetry {
+        *Symmetry::ALL
+            .iter()
+            .find(|sym| {
+                Direction::ALL
+                    .iter()
+                    .all(|&dir| sym.apply(dir) == other.apply(self.apply(dir)))
+            })
+            .expect("the symmetries of the square are closed under composition")
+    }
+
+    pub fn apply(&self, dir: Direction) -> Direction {
+        let (x, y): (u32, u32) = dir.into();
+        match self {
+            Symmetry::Identity => (x, y),
+            Symmetry::Rotate90 => (2 - y, x),
+            Symmetry::Rotate180 => (2 - x, 2 - y),
+            Symmetry::Rotate270 => (y, 2 - x),
+            Symmetry::FlipHorizontal => (2 - x, y),
+            Symmetry::FlipVertical => (x, 2 - y),
+            Symmetry::FlipDiagonal => (y, x),
+            Symmetry::FlipAntiDiagonal => (2 - y, 2 - x),
+        }
+        .try_into()
+        .unwrap()
+    }
+
+    fn apply_mask(&self, mask: u16) -> u16 {
+        Direction::ALL
+            .iter()
+            .filter(|dir| mask & (1 << dir.index()) != 0)
+            .fold(0, |out, &dir| out | 1 << self.apply(dir).index())
+    }
+}
+
+pub trait Transform {
+    fn transform(&self, symmetry: Symmetry) -> Self;
+}
+
+impl Transform for Direction {
+    fn transform(&self, symmetry: Symmetry) -> Self {
+        symmetry.apply(*self)
+    }
+}
+
+impl Transform for (Direction, Direction) {
+    fn transform(&self, symmetry: Symmetry) -> Self {
+        (symmetry.apply(self.0), symmetry.apply(self.1))
+    }
+}
+
+impl Transform for Move {
+    fn transform(&self, symmetry: Symmetry) -> Self {
+        Move::new(self.coords().transform(symmetry))
+    }
+}
+
+impl Transform for Turn {
+    fn transform(&self, symmetry: Symmetry) -> Self {
+        Turn::with_player(
+            self.turn_number,
+            self.player(),
+            self.coords.transform(symmetry),
+        )
+    }
+}
+
+impl Transform for Position {
+    fn transform(&self, symmetry: Symmetry) -> Self {
+        let mut tiles = [0; 2];
+        for (player, out) in [Player::X, Player::O].into_iter().zip(&mut tiles) {
+            let mut mask = self.tiles(player);
+            while mask != 0 {
+                let mv = Move::from_index(mask.trailing_zeros() as usize);
+                *out |= 1 << mv.transform(symmetry).index();
+                mask &= mask - 1;
+            }
+        }
+
+        Position::from_parts(
+            tiles,
+            [Player::X, Player::O].map(|player| symmetry.apply_mask(self.box_winners(player))),
+            self.winner(),
+            self.side_to_move(),
+            self.track().map(|track| symmetry.apply(track)),
+            self.rules(),
+        )
+    }
+}
+
+/// Replays the transformed turns from the transformed starting position, so the turn history and
+/// undo work on the result like on the original.
+impl Transform for Grid {
+    fn transform(&self, symmetry: Symmetry) -> Self {
+        let mut grid = Grid::from_position(self.start().transform(symmetry));
+        for turn in &self.turns {
+            grid.apply_turn(turn.coords.transform(symmetry))
+                .expect("symmetries keep turns legal");
+        }
+        grid
+    }
+}
+
+// Orders positions that only differ by symmetry. It only has to be consistent, not meaningful.
+fn canonical_key(position: &Position) -> (u128, u128, u16, u16, Option<usize>) {
+    (
+        position.tiles(Player::X),
+        position.tiles(Player::O),
+        position.box_winners(Player::X),
+        position.box_winners(Player::O),
+        position.track().map(|track| track.index()),
+    )
+}
+
+/// Picks the same representative out of all 8 transformations of `position`, no matter which of
+/// them it is given. Returns the representative and the symmetry that turns `position` into it.
+pub fn canonical(position: &Position) -> (Position, Symmetry) {
+    Symmetry::ALL
+        .iter()
+        .map(|&sym| (position.transform(sym), sym))
+        .min_by_key(|(transformed, _)| canonical_key(transformed))
+        .unwrap()
+}
+
+/// Like `canonical`, but transforms the whole game so that it ends in the canonical position
+pub fn canonical_grid(grid: &Grid) -> (Grid, Symmetry) {
+    let (_, sym) = canonical(grid.position());
+    (grid.transform(sym), sym)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::RuleSet;
+    use crate::rng::Rng;
+    use Symmetry::*;
+
+    // A game of `plies` random turns under `rules`, or until it ends
+    fn random_game(rules: RuleSet, plies: usize, rng: &mut Rng) -> Grid {
+        let mut grid = Grid::default().with_rules(rules);
+        for _ in 0..plies {
+            match rng.choose(grid.position().legal_moves()) {
+                Some(mv) => grid.apply_turn(mv.coords()).unwrap(),
+                None => break,
+            }
+        }
+        grid
+    }
+
+    #[test]
+    fn symmetries_commute_with_relative_addition() {
+        for sym in Symmetry::ALL {
+            for a in Direction::ALL {
+                for b in Direction::ALL {
+                    assert_eq!(sym.apply(a + b), sym.apply(a) + sym.apply(b), "{sym:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn inverse_and_then() {
+        for sym in Symmetry::ALL {
+            assert_eq!(sym.then(sym.inverse()), Identity);
+            assert_eq!(sym.inverse().then(sym), Identity);
+            for dir in Direction::ALL {
+                assert_eq!(sym.inverse().apply(sym.apply(dir)), dir, "{sym:?}");
+            }
+            for other in Symmetry::ALL {
+                for dir in Direction::ALL {
+                    assert_eq!(sym.then(other).apply(dir), other.apply(sym.apply(dir)));
+                }
+            }
+        }
+        assert_eq!(Rotate90.then(Rotate90), Rotate180);
+        assert_eq!(Rotate90.then(Rotate180), Rotate270);
+        assert_eq!(FlipHorizontal.then(FlipVertical), Rotate180);
+        assert_eq!(FlipDiagonal.then(FlipHorizontal), Rotate90);
+        // They are 8 different symmetries: NW and N end up somewhere different under each
+        let images = Symmetry::ALL.map(|sym| (sym.apply(Direction::NW), sym.apply(Direction::N)));
+        assert!((0..8).all(|a| (a + 1..8).all(|b| images[a] != images[b])));
+    }
+
+    #[test]
+    fn canonical_is_the_same_for_every_image() {
+        let mut rng = Rng::new(21);
+        for (_, rules) in RuleSet::PRESETS {
+            for plies in [0, 1, 7, 30] {
+                let position = *random_game(rules, plies, &mut rng).position();
+                let (expected, _) = canonical(&position);
+                for sym in Symmetry::ALL {
+                    let image = position.transform(sym);
+                    let (found, to_canonical) = canonical(&image);
+                    assert_eq!(found, expected, "{sym:?} of {position}");
+                    assert_eq!(image.transform(to_canonical), found);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn transformed_games_replay_legally() {
+        let mut rng = Rng::new(22);
+        for (_, rules) in RuleSet::PRESETS {
+            for _ in 0..4 {
+                let grid = random_game(rules, 81, &mut rng);
+                for sym in Symmetry::ALL {
+                    let transformed = grid.transform(sym);
+                    assert_eq!(transformed.turns.len(), grid.turns.len());
+                    assert_eq!(transformed.status(), grid.status(), "{sym:?}");
+                    assert_eq!(*transformed.position(), grid.position().transform(sym));
+                }
+                let (canonical_game, sym) = canonical_grid(&grid);
+                assert_eq!(*canonical_game.position(), canonical(grid.position()).0);
+                assert_eq!(
+                    canonical_game.transform(sym.inverse()).position(),
+                    grid.position()
+                );
+            }
+        }
+    }
+}
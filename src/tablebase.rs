@@ -0,0 +1,277 @@
+//! Endgame tablebases: the exact value of every position reachable from a set of late positions.
+//! Tables are sampled rather than complete, since there are far too many positions with even a
+//! few empty tiles to solve them all, so a position missing from a table may still be reachable
+//! from other roots.
+//!
+//! A tablebase file is little endian, and laid out as:
+//!
+//! | bytes    | contents                                                    |
+//! |----------|-------------------------------------------------------------|
+//! | 4        | `UT3T`                                                      |
+//! | 1        | format version, currently 1                                 |
+//! | 1        | length of the rules string                                  |
+//! | n        | the rules, as written by `RuleSet`'s `Display`              |
+//! | 1        | the most empty tiles of any position in the table           |
+//! | 8        | number of entries                                           |
+//! | 9 each   | entries: the canonical key (`u64`) then the value (`i8`)    |
+//!
+//! Entries are sorted by key, which is the hash of the position's canonical form (see
+//! `symmetry::canonical`), so one entry covers all 8 symmetric versions of a position. The value
+//! is for the side to move: `n > 0` wins in `n` plies, `n < 0` loses in `-n` plies and `0` is a
+//! draw. Finished games aren't stored.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use crate::bitboard::Position;
+use crate::error::UT3Error;
+use crate::game::{GameStatus, RuleSet};
+use crate::search::WIN;
+use crate::solver::{parent_score, Outcome};
+use crate::symmetry::canonical;
+
+const MAGIC: &[u8; 4] = b"UT3T";
+const VERSION: u8 = 1;
+
+pub struct Tablebase {
+    rules: RuleSet,
+    max_empty: u32,
+    entries: Vec<(u64, i8)>,
+}
+
+fn key(position: &Position) -> u64 {
+    canonical(position).0.hash()
+}
+
+fn encode(outcome: Outcome) -> i8 {
+    match outcome {
+        Outcome::Win(plies) => plies as i8,
+        Outcome::Draw => 0,
+        Outcome::Loss(plies) => -(plies as i8),
+    }
+}
+
+fn decode(value: i8) -> Outcome {
+    match value {
+        0 => Outcome::Draw,
+        plies if plies > 0 => Outcome::Win(plies as u32),
+        plies => Outcome::Loss(plies.unsigned_abs() as u32),
+    }
+}
+
+impl Tablebase {
+    /// Solves every position that can be reached from `roots`, which must all have at most
+    /// `max_empty` empty tiles and the same rules. Positions are solved from the fewest empty
+    /// tiles up, so everything a position leads to is already known when it is reached.
+    pub fn generate(roots: &[Position], max_empty: u32) -> Result<Self, UT3Error> {
+        let rules = match roots.first() {
+            Some(root) => root.rules(),
+            None => return Err(UT3Error::MissingField("root positions")),
+        };
+        if let Some(root) = roots
+            .iter()
+            .find(|root| root.rules() != rules || root.empty_tiles().count_ones() > max_empty)
+        {
+            return Err(UT3Error::InvalidPosition(format!(
+                "root `{root}` has other rules or too many empty tiles"
+            )));
+        }
+
+        // Every unfinished position, by key, bucketed by how many empty tiles it has
+        let mut positions: Vec<HashMap<u64, Position>> = vec![HashMap::new(); 82];
+        let mut stack = roots.to_vec();
+        while let Some(position) = stack.pop() {
+            if position.status().is_over() {
+                continue;
+            }
+            let bucket = &mut positions[position.empty_tiles().count_ones() as usize];
+            if bucket.insert(key(&position), position).is_some() {
+                continue;
+            }
+            for mv in position.legal_moves() {
+                let mut child = position;
+                child.play(mv);
+                stack.push(child);
+            }
+        }
+
+        let mut values: HashMap<u64, i8> = HashMap::new();
+        for bucket in &positions {
+            for (&position_key, position) in bucket {
+                let mut position = *position;
+                let side = position.side_to_move();
+                let mut best = -WIN - 1;
+                for mv in position.legal_moves() {
+                    let undo = position.play(mv);
+                    let child = match position.status() {
+                        GameStatus::Won(player) if player == side => -WIN,
+                        GameStatus::Won(_) => WIN,
+                        GameStatus::Drawn => 0,
+                        GameStatus::Ongoing => decode(values[&key(&position)]).score(0),
+                    };
+                    position.undo(mv, undo);
+                    best = best.max(parent_score(child));
+                }
+                values.insert(position_key, encode(Outcome::from_score(best)));
+            }
+        }
+
+        let mut entries = values.into_iter().collect::<Vec<_>>();
+        entries.sort_unstable();
+        Ok(Self {
+            rules,
+            max_empty,
+            entries,
+        })
+    }
+
+    pub fn rules(&self) -> RuleSet {
+        self.rules
+    }
+
+    pub fn max_empty(&self) -> u32 {
+        self.max_empty
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The value of `position` for the side to move, if it is in the table
+    pub fn probe(&self, position: &Position) -> Option<Outcome> {
+        if position.rules() != self.rules
+            || position.empty_tiles().count_ones() > self.max_empty
+            || position.status().is_over()
+        {
+            return None;
+        }
+        let key = key(position);
+        self.entries
+            .binary_search_by_key(&key, |&(key, _)| key)
+            .ok()
+            .map(|idx| decode(self.entries[idx].1))
+    }
+
+    pub fn write(&self, writer: &mut impl Write) -> io::Result<()> {
+        let rules = self.rules.to_string();
+        writer.write_all(MAGIC)?;
+        writer.write_all(&[VERSION, rules.len() as u8])?;
+        writer.write_all(rules.as_bytes())?;
+        writer.write_all(&[self.max_empty as u8])?;
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for &(key, value) in &self.entries {
+            writer.write_all(&key.to_le_bytes())?;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub fn read(reader: &mut impl Read) -> Result<Self, UT3Error> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(UT3Error::InvalidFile("not a tablebase".to_string()));
+        }
+        let mut header = [0; 2];
+        reader.read_exact(&mut header)?;
+        if header[0] != VERSION {
+            return Err(UT3Error::InvalidFile(format!(
+                "unsupported tablebase version {}",
+                header[0]
+            )));
+        }
+
+        let mut rules = vec![0; header[1] as usize];
+        reader.read_exact(&mut rules)?;
+        let rules = String::from_utf8(rules)
+            .map_err(|_| UT3Error::InvalidFile("rules aren't UTF-8".to_string()))?;
+        let rules = RuleSet::try_from(rules.as_str())?;
+
+        let mut max_empty = [0; 1];
+        reader.read_exact(&mut max_empty)?;
+        let mut count = [0; 8];
+        reader.read_exact(&mut count)?;
+
+        let mut entries = Vec::new();
+        let mut entry = [0; 9];
+        for _ in 0..u64::from_le_bytes(count) {
+            reader.read_exact(&mut entry)?;
+            let key = u64::from_le_bytes(entry[..8].try_into().unwrap());
+            entries.push((key, entry[8] as i8));
+        }
+        if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0) {
+            return Err(UT3Error::InvalidFile(
+                "tablebase entries aren't sorted".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            rules,
+            max_empty: max_empty[0] as u32,
+            entries,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+    use crate::solver::Solver;
+    use crate::symmetry::{Symmetry, Transform};
+
+    const MAX_EMPTY: u32 = 9;
+
+    // Random games played until at most `MAX_EMPTY` tiles are left
+    fn roots() -> Vec<Position> {
+        let mut rng = Rng::new(7);
+        let mut roots = Vec::new();
+        while roots.len() < 3 {
+            let mut position = Position::new(RuleSet::RELATIVE);
+            while !position.status().is_over() && position.empty_tiles().count_ones() > MAX_EMPTY {
+                position.play(rng.choose(position.legal_moves()).unwrap());
+            }
+            if !position.status().is_over() {
+                roots.push(position);
+            }
+        }
+        roots
+    }
+
+    #[test]
+    fn write_read_round_trip() {
+        let tablebase = Tablebase::generate(&roots(), MAX_EMPTY).unwrap();
+        assert!(!tablebase.is_empty());
+        let mut bytes = Vec::new();
+        tablebase.write(&mut bytes).unwrap();
+
+        let copy = Tablebase::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!(copy.rules(), RuleSet::RELATIVE);
+        assert_eq!(copy.max_empty(), MAX_EMPTY);
+        assert_eq!(copy.entries, tablebase.entries);
+
+        bytes[4] = VERSION + 1;
+        assert!(Tablebase::read(&mut bytes.as_slice()).is_err());
+        assert!(Tablebase::read(&mut &b"UT3N"[..]).is_err());
+    }
+
+    #[test]
+    fn probe_under_every_symmetry() {
+        let roots = roots();
+        let tablebase = Tablebase::generate(&roots, MAX_EMPTY).unwrap();
+        for root in &roots {
+            let outcome = tablebase.probe(root).expect("roots are in the table");
+            let solution = Solver::new(1 << 16).solve_position(root).unwrap();
+            assert_eq!(outcome, solution.outcome, "{root}");
+            for symmetry in Symmetry::ALL {
+                assert_eq!(tablebase.probe(&root.transform(symmetry)), Some(outcome));
+            }
+        }
+        assert_eq!(tablebase.probe(&Position::new(RuleSet::RELATIVE)), None);
+        assert_eq!(tablebase.probe(&Position::new(RuleSet::CLASSIC)), None);
+    }
+}
@@ -0,0 +1,231 @@
+//! Matches between engines, and the statistics to tell whether one is better than another.
+
+use crate::bitboard::Moves;
+use crate::engine::Engine;
+use crate::error::UT3Error;
+use crate::game::{GameStatus, Grid, Player, RuleSet};
+use crate::pgn::Game;
+use crate::rng::Rng;
+
+/// Plays up to `plies` random moves from the start, for games to start from. Moves that would
+/// end the game are never picked, so the opening stops early if every move would.
+pub fn random_opening(rules: RuleSet, plies: u32, rng: &mut Rng) -> Grid {
+    let mut grid = Grid::default().with_rules(rules);
+    for _ in 0..plies {
+        let mut position = *grid.position();
+        let safe = position
+            .legal_moves()
+            .filter(|&mv| {
+                let undo = position.play(mv);
+                let over = position.status().is_over();
+                position.undo(mv, undo);
+                !over
+            })
+            .fold(0, |mask, mv| mask | 1 << mv.index());
+        let Some(mv) = rng.choose(Moves::from_mask(safe)) else {
+            break;
+        };
+        grid.apply_turn(mv.coords())
+            .expect("legal moves can be played");
+    }
+    grid
+}
+
+/// Plays the engines against each other from `opening` until the game ends, the first as X and
+/// the second as O. An engine that plays an illegal move (or none at all) loses, with a comment
+/// after the last move saying so.
+pub fn play_game(mut engines: [&mut dyn Engine; 2], opening: &Grid) -> Result<Game, UT3Error> {
+    for engine in &mut engines {
+        engine.new_game()?;
+    }
+
+    let mut grid = opening.clone();
+    let mut forfeit = None;
+    while !grid.status().is_over() {
+        let player = grid.current_player();
+        let engine = &mut engines[player.index()];
+        let played = engine
+            .best_move(&grid)?
+            .map(|mv| mv.coords())
+            .ok_or(UT3Error::MissingField("move"))
+            .and_then(|coords| grid.apply_turn(coords));
+        if let Err(err) = played {
+            forfeit = Some((player, format!("{} forfeits: {err}", engine.name())));
+            break;
+        }
+    }
+
+    let mut game = Game::from_grid(&grid);
+    game.set_header("X", engines[0].name());
+    game.set_header("O", engines[1].name());
+    if let Some((player, comment)) = forfeit {
+        game.result = GameStatus::Won(player.opponent());
+        match game.moves.last_mut() {
+            Some(node) => node.comment = Some(comment),
+            None => game.comment = Some(comment),
+        }
+    }
+    Ok(game)
+}
+
+/// The results of one side of a match
+#[derive(Copy, Clone, Default, PartialEq, Eq, Debug)]
+pub struct Score {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+/// The chance of beating an opponent `elo` points weaker, counting a draw as half a win
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+fn elo_for(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+impl Score {
+    pub fn add(&mut self, result: GameStatus, player: Player) {
+        match result {
+            GameStatus::Won(winner) if winner == player => self.wins += 1,
+            GameStatus::Won(_) => self.losses += 1,
+            _ => self.draws += 1,
+        }
+    }
+
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    /// The average result per game, counting a draw as half a win
+    pub fn mean(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    // The variance of the result of a single game
+    fn variance(&self) -> f64 {
+        let mean = self.mean();
+        let games = self.games() as f64;
+        (self.wins as f64 * (1.0 - mean).powi(2)
+            + self.draws as f64 * (0.5 - mean).powi(2)
+            + self.losses as f64 * mean.powi(2))
+            / games
+    }
+
+    /// The Elo difference to the opponent, and how far off it could be with 95% confidence.
+    /// Infinite if one side won or lost every game.
+    pub fn elo(&self) -> (f64, f64) {
+        if self.games() == 0 {
+            return (0.0, f64::INFINITY);
+        }
+        let mean = self.mean();
+        let margin = 1.96 * (self.variance() / self.games() as f64).sqrt();
+        let low = elo_for((mean - margin).max(0.0));
+        let high = elo_for((mean + margin).min(1.0));
+        (elo_for(mean), (high - low) / 2.0)
+    }
+
+    /// A sequential probability ratio test of whether the Elo difference is `elo1` rather than
+    /// `elo0`, falsely deciding either way with probabilities `alpha` and `beta` respectively.
+    /// It uses the normal approximation of the results, so it needs a fair number of games.
+    pub fn sprt(&self, elo0: f64, elo1: f64, alpha: f64, beta: f64) -> Sprt {
+        let lower = (beta / (1.0 - alpha)).ln();
+        let upper = ((1.0 - beta) / alpha).ln();
+        let variance = self.variance();
+        let llr = if self.games() == 0 || variance == 0.0 {
+            0.0
+        } else {
+            let (score0, score1) = (expected_score(elo0), expected_score(elo1));
+            self.games() as f64 * (score1 - score0) * (2.0 * self.mean() - score0 - score1)
+                / (2.0 * variance)
+        };
+        Sprt {
+            llr,
+            lower,
+            upper,
+            result: if llr >= upper {
+                Some(true)
+            } else if llr <= lower {
+                Some(false)
+            } else {
+                None
+            },
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Sprt {
+    /// The log likelihood ratio of `elo1` over `elo0` so far
+    pub llr: f64,
+    /// Where the test accepts `elo0`
+    pub lower: f64,
+    /// Where the test accepts `elo1`
+    pub upper: f64,
+    /// `Some(true)` once `elo1` is accepted, `Some(false)` once `elo0` is, `None` until then
+    pub result: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(wins: u32, draws: u32, losses: u32) -> Score {
+        Score {
+            wins,
+            draws,
+            losses,
+        }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-3, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn elo() {
+        // Scoring 75% is about 190.8 Elo, and 64% about 100
+        assert_close(score(3, 0, 1).elo().0, 190.849);
+        assert_close(score(64, 0, 36).elo().0, 99.951);
+        assert_close(score(25, 50, 25).elo().0, 0.0);
+        assert_close(score(25, 50, 25).elo().1, 48.464);
+        let (elo, margin) = score(60, 20, 20).elo();
+        assert_close(elo, 147.191);
+        assert_close(margin, 66.015);
+        assert_eq!(score(5, 0, 0).elo().0, f64::INFINITY);
+        assert_eq!(Score::default().elo(), (0.0, f64::INFINITY));
+    }
+
+    #[test]
+    fn sprt() {
+        let sprt = score(60, 20, 20).sprt(0.0, 10.0, 0.05, 0.05);
+        assert_close(sprt.lower, -2.944);
+        assert_close(sprt.upper, 2.944);
+        assert_close(sprt.llr, 1.734);
+        assert_eq!(sprt.result, None);
+
+        let sprt = score(400, 400, 200).sprt(0.0, 10.0, 0.05, 0.05);
+        assert_close(sprt.llr, 9.537);
+        assert_eq!(sprt.result, Some(true));
+        assert_close(score(300, 400, 300).sprt(0.0, 10.0, 0.05, 0.05).llr, -0.690);
+        assert_eq!(
+            score(200, 400, 400).sprt(0.0, 10.0, 0.05, 0.05).result,
+            Some(false)
+        );
+        assert_eq!(Score::default().sprt(0.0, 10.0, 0.05, 0.05).llr, 0.0);
+    }
+
+    #[test]
+    fn random_openings_never_end_the_game() {
+        let mut rng = Rng::new(41);
+        for (_, rules) in RuleSet::PRESETS {
+            assert_eq!(random_opening(rules, 4, &mut rng).turns.len(), 4);
+            for _ in 0..5 {
+                let opening = random_opening(rules, 81, &mut rng);
+                assert!(!opening.status().is_over(), "{opening}");
+                assert!(opening.turns.len() > 30);
+            }
+        }
+    }
+}
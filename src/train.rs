@@ -0,0 +1,372 @@
+//! Training `nn::Network`s on self-play records, with Adam on the CPU.
+//!
+//! The value head learns the game result by mean squared error, and the policy head learns the
+//! search's policy by cross entropy.
+
+use crate::bitboard::Position;
+use crate::nn::{features, masked_softmax, Layer, Network};
+use crate::rng::Rng;
+use crate::selfplay::Record;
+
+/// A position to learn from
+#[derive(Clone, Debug)]
+pub struct Sample {
+    pub position: Position,
+    /// What the policy head should give each move, by `Move::index`
+    pub policy: [f32; 81],
+    /// The result for the side to move, from -1 to 1
+    pub value: f32,
+}
+
+impl From<&Record> for Sample {
+    fn from(record: &Record) -> Self {
+        Self {
+            position: record.position,
+            policy: record.policy,
+            value: record.result as f32,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TrainConfig {
+    pub learning_rate: f32,
+    pub batch_size: usize,
+    /// How much weights are pulled towards 0 on each step, as a fraction of the learning rate
+    pub weight_decay: f32,
+    /// How much the policy loss counts next to the value loss
+    pub policy_weight: f32,
+}
+
+impl Default for TrainConfig {
+    fn default() -> Self {
+        Self {
+            learning_rate: 0.001,
+            batch_size: 256,
+            weight_decay: 0.0001,
+            policy_weight: 1.0,
+        }
+    }
+}
+
+/// The average losses over some samples
+#[derive(Copy, Clone, Default, Debug)]
+pub struct Losses {
+    pub value: f64,
+    /// 0 for networks without a policy head
+    pub policy: f64,
+}
+
+const BETA1: f32 = 0.9;
+const BETA2: f32 = 0.999;
+const EPSILON: f32 = 1e-8;
+
+/// Trains one network, keeping Adam's moment estimates between batches
+pub struct Trainer {
+    pub config: TrainConfig,
+    // The gradients of the current batch, and Adam's moments, shaped like the network's layers
+    gradients: Vec<Layer>,
+    first_moments: Vec<Layer>,
+    second_moments: Vec<Layer>,
+    steps: i32,
+}
+
+impl Trainer {
+    pub fn new(network: &Network, config: TrainConfig) -> Self {
+        let zeros = || {
+            network
+                .layers()
+                .map(|layer| Layer::zeros(layer.inputs, layer.outputs))
+                .collect::<Vec<_>>()
+        };
+        Self {
+            config,
+            gradients: zeros(),
+            first_moments: zeros(),
+            second_moments: zeros(),
+            steps: 0,
+        }
+    }
+
+    /// One pass over `samples` in a random order, returning the average losses before each step
+    pub fn epoch(&mut self, network: &mut Network, samples: &[Sample], rng: &mut Rng) -> Losses {
+        let mut order = (0..samples.len()).collect::<Vec<_>>();
+        rng.shuffle(&mut order);
+
+        let mut total = Losses::default();
+        for batch in order.chunks(self.config.batch_size.max(1)) {
+            for &idx in batch {
+                let losses = self.backward(network, &samples[idx]);
+                total.value += losses.value;
+                total.policy += losses.policy;
+            }
+            self.step(network, batch.len());
+        }
+        let count = samples.len().max(1) as f64;
+        Losses {
+            value: total.value / count,
+            policy: total.policy / count,
+        }
+    }
+
+    // Adds the gradients of the losses for `sample` to `self.gradients`
+    fn backward(&mut self, network: &Network, sample: &Sample) -> Losses {
+        // The input to each hidden layer, and then the input to the heads
+        let mut activations = vec![features(&sample.position).to_vec()];
+        for layer in &network.hidden {
+            let mut output = Vec::new();
+            layer.forward(activations.last().unwrap(), &mut output);
+            output.iter_mut().for_each(|x| *x = x.max(0.0));
+            activations.push(output);
+        }
+        let body = activations.last().unwrap();
+        let heads = network.hidden.len();
+        let mut losses = Losses::default();
+        let mut output = Vec::new();
+
+        // The gradient of the losses with respect to the heads' input
+        let mut gradient = vec![0.0; body.len()];
+        network.value.forward(body, &mut output);
+        let value = output[0].tanh();
+        let error = value - sample.value;
+        losses.value = (error * error) as f64;
+        let delta = [2.0 * error * (1.0 - value * value)];
+        add_gradients(
+            &mut self.gradients[heads],
+            &network.value,
+            body,
+            &delta,
+            &mut gradient,
+        );
+
+        if let Some(head) = &network.policy {
+            head.forward(body, &mut output);
+            let policy = masked_softmax(&output, &sample.position);
+            let mut delta = [0.0; 81];
+            for mv in sample.position.legal_moves() {
+                let idx = mv.index();
+                losses.policy -= (sample.policy[idx] * policy[idx].max(1e-12).ln()) as f64;
+                delta[idx] = self.config.policy_weight * (policy[idx] - sample.policy[idx]);
+            }
+            add_gradients(
+                &mut self.gradients[heads + 1],
+                head,
+                body,
+                &delta,
+                &mut gradient,
+            );
+        }
+
+        for idx in (0..heads).rev() {
+            // Through the ReLU, which passes gradients only where it was active
+            for (delta, &activation) in gradient.iter_mut().zip(&activations[idx + 1]) {
+                if activation <= 0.0 {
+                    *delta = 0.0;
+                }
+            }
+            let mut input_gradient = vec![0.0; activations[idx].len()];
+            let layer = &network.hidden[idx];
+            add_gradients(
+                &mut self.gradients[idx],
+                layer,
+                &activations[idx],
+                &gradient,
+                &mut input_gradient,
+            );
+            gradient = input_gradient;
+        }
+        losses
+    }
+
+    // Applies the gradients summed over a batch of `count` samples, and clears them
+    fn step(&mut self, network: &mut Network, count: usize) {
+        self.steps += 1;
+        let config = self.config;
+        let correction1 = 1.0 - BETA1.powi(self.steps);
+        let correction2 = 1.0 - BETA2.powi(self.steps);
+        let layers = network
+            .layers_mut()
+            .zip(&mut self.gradients)
+            .zip(self.first_moments.iter_mut().zip(&mut self.second_moments));
+        for ((layer, gradients), (first, second)) in layers {
+            let params = [
+                (
+                    &mut layer.weights,
+                    &mut gradients.weights,
+                    &mut first.weights,
+                    &mut second.weights,
+                    true,
+                ),
+                (
+                    &mut layer.biases,
+                    &mut gradients.biases,
+                    &mut first.biases,
+                    &mut second.biases,
+                    false,
+                ),
+            ];
+            for (params, gradients, first, second, decay) in params {
+                for idx in 0..params.len() {
+                    let gradient = gradients[idx] / count as f32;
+                    gradients[idx] = 0.0;
+                    first[idx] = BETA1 * first[idx] + (1.0 - BETA1) * gradient;
+                    second[idx] = BETA2 * second[idx] + (1.0 - BETA2) * gradient * gradient;
+                    let update =
+                        (first[idx] / correction1) / ((second[idx] / correction2).sqrt() + EPSILON);
+                    if decay {
+                        params[idx] -= config.learning_rate * config.weight_decay * params[idx];
+                    }
+                    params[idx] -= config.learning_rate * update;
+                }
+            }
+        }
+    }
+}
+
+/// The average losses of `network` on `samples`, without training it
+pub fn losses(network: &Network, samples: &[Sample]) -> Losses {
+    let mut total = Losses::default();
+    for sample in samples {
+        let output = network.run(&sample.position);
+        total.value += ((output.value - sample.value) as f64).powi(2);
+        if let Some(policy) = output.policy {
+            for mv in sample.position.legal_moves() {
+                let idx = mv.index();
+                total.policy -= (sample.policy[idx] * policy[idx].max(1e-12).ln()) as f64;
+            }
+        }
+    }
+    let count = samples.len().max(1) as f64;
+    Losses {
+        value: total.value / count,
+        policy: total.policy / count,
+    }
+}
+
+// Adds the gradients of `layer`'s weights and biases to `gradients` given the gradient `delta`
+// of its outputs, and the gradient of its inputs to `input_gradient`
+fn add_gradients(
+    gradients: &mut Layer,
+    layer: &Layer,
+    input: &[f32],
+    delta: &[f32],
+    input_gradient: &mut [f32],
+) {
+    for (out, &delta) in delta.iter().enumerate() {
+        if delta == 0.0 {
+            continue;
+        }
+        gradients.biases[out] += delta;
+        let row = out * layer.inputs..(out + 1) * layer.inputs;
+        let weights = &layer.weights[row.clone()];
+        for ((gradient, &weight), (&x, input_gradient)) in gradients.weights[row]
+            .iter_mut()
+            .zip(weights)
+            .zip(input.iter().zip(input_gradient.iter_mut()))
+        {
+            *gradient += delta * x;
+            *input_gradient += delta * weight;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::RuleSet;
+
+    // Positions six random moves into a game, alternately won and lost, with most of the policy
+    // on the first legal move
+    fn samples(count: usize, rng: &mut Rng) -> Vec<Sample> {
+        (0..count)
+            .map(|idx| {
+                let mut position = Position::new(RuleSet::CLASSIC);
+                for _ in 0..6 {
+                    position.play(rng.choose(position.legal_moves()).unwrap());
+                }
+                let mut policy = [0.0; 81];
+                policy[position.legal_moves().next().unwrap().index()] = 0.75;
+                policy[position.legal_moves().last().unwrap().index()] += 0.25;
+                Sample {
+                    position,
+                    policy,
+                    value: if idx % 2 == 0 { 1.0 } else { -1.0 },
+                }
+            })
+            .collect()
+    }
+
+    fn total_loss(network: &Network, sample: &Sample, config: &TrainConfig) -> f64 {
+        let losses = losses(network, std::slice::from_ref(sample));
+        losses.value + config.policy_weight as f64 * losses.policy
+    }
+
+    #[test]
+    fn gradients_match_finite_differences() {
+        let mut rng = Rng::new(3);
+        let mut network = Network::new(&[8, 6], true, &mut rng);
+        let config = TrainConfig {
+            policy_weight: 0.5,
+            ..TrainConfig::default()
+        };
+        let mut trainer = Trainer::new(&network, config);
+        let sample = samples(1, &mut rng).pop().unwrap();
+        trainer.backward(&network, &sample);
+
+        // Small enough that no hidden unit crosses from active to inactive
+        const STEP: f32 = 1e-3;
+        for (layer_idx, gradients) in trainer.gradients.iter().enumerate() {
+            let mut checked = 0;
+            let params = gradients.weights.len() + gradients.biases.len();
+            for param in 0..params {
+                let nudge = |network: &mut Network, by: f32| {
+                    let layer = network.layers_mut().nth(layer_idx).unwrap();
+                    match param.checked_sub(layer.weights.len()) {
+                        None => layer.weights[param] += by,
+                        Some(bias) => layer.biases[bias] += by,
+                    }
+                };
+                nudge(&mut network, STEP);
+                let up = total_loss(&network, &sample, &config);
+                nudge(&mut network, -2.0 * STEP);
+                let down = total_loss(&network, &sample, &config);
+                nudge(&mut network, STEP);
+
+                let numeric = (up - down) / (2.0 * STEP as f64);
+                let analytic = match param.checked_sub(gradients.weights.len()) {
+                    None => gradients.weights[param],
+                    Some(bias) => gradients.biases[bias],
+                } as f64;
+                assert!(
+                    (numeric - analytic).abs() <= 2e-3 + 2e-2 * analytic.abs(),
+                    "layer {layer_idx}, parameter {param}: {numeric} vs {analytic}"
+                );
+                checked += (analytic != 0.0) as usize;
+            }
+            assert!(checked > 0, "layer {layer_idx} has no gradients to check");
+        }
+    }
+
+    #[test]
+    fn training_lowers_the_loss() {
+        let mut rng = Rng::new(4);
+        let mut network = Network::new(&[16], true, &mut rng);
+        let samples = samples(8, &mut rng);
+        let mut trainer = Trainer::new(
+            &network,
+            TrainConfig {
+                learning_rate: 0.01,
+                batch_size: samples.len(),
+                ..TrainConfig::default()
+            },
+        );
+
+        let before = losses(&network, &samples);
+        for _ in 0..20 {
+            trainer.epoch(&mut network, &samples, &mut rng);
+        }
+        let after = losses(&network, &samples);
+        assert!(after.value < before.value / 2.0, "{before:?} to {after:?}");
+        assert!(after.policy < before.policy, "{before:?} to {after:?}");
+    }
+}
@@ -0,0 +1,443 @@
+use crate::bitboard::Position;
+use crate::error::UT3Error;
+use crate::game::{Direction, Grid};
+use crate::pgn::{Annotation, Game, MoveNode};
+
+/// Index of a node in a `GameTree`. Ids stay the same for as long as the node exists.
+pub type NodeId = usize;
+
+#[derive(Clone, Debug)]
+pub struct Node {
+    // `None` only for the root, which stands for the starting position
+    coords: Option<(Direction, Direction)>,
+    turn_number: u32,
+    parent: Option<NodeId>,
+    // The first child is the main continuation, the rest are variations
+    children: Vec<NodeId>,
+    pub annotation: Option<Annotation>,
+    pub comment: Option<String>,
+}
+
+impl Node {
+    pub fn coords(&self) -> Option<(Direction, Direction)> {
+        self.coords
+    }
+
+    pub fn turn_number(&self) -> u32 {
+        self.turn_number
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+/// Every line explored from a starting position, with a cursor at one of the positions in it.
+/// Playing a move at the cursor that isn't already there starts a new variation instead of
+/// overwriting what was played before.
+#[derive(Clone, Debug)]
+pub struct GameTree {
+    // Deleted nodes leave a hole so that the other ids don't change
+    nodes: Vec<Option<Node>>,
+    cursor: NodeId,
+    // The game at the cursor
+    grid: Grid,
+}
+
+impl GameTree {
+    pub const ROOT: NodeId = 0;
+
+    pub fn new(start: Position) -> Self {
+        let grid = Grid::from_position(start);
+        Self {
+            nodes: vec![Some(Node {
+                coords: None,
+                turn_number: grid.current_turn_number - 1,
+                parent: None,
+                children: Vec::new(),
+                annotation: None,
+                comment: None,
+            })],
+            cursor: Self::ROOT,
+            grid,
+        }
+    }
+
+    /// A tree with just the turns of `grid` as its main line, with the cursor at the end
+    pub fn from_grid(grid: &Grid) -> Self {
+        let mut tree = GameTree::new(*grid.start());
+        for turn in &grid.turns {
+            tree.play(turn.coords).expect("turns in a grid are legal");
+        }
+        tree
+    }
+
+    /// Every line of `game`, with the cursor at the start
+    pub fn from_pgn(game: &Game) -> Result<Self, UT3Error> {
+        let mut tree = GameTree::new(game.start()?);
+        tree.node_mut(Self::ROOT).comment = game.comment.clone();
+        tree.add_line(Self::ROOT, &game.moves)?;
+        tree.jump(Self::ROOT);
+        Ok(tree)
+    }
+
+    fn add_line(&mut self, mut parent: NodeId, moves: &[MoveNode]) -> Result<(), UT3Error> {
+        for mv in moves {
+            self.jump(parent);
+            let id = self.play(mv.coords)?;
+            let node = self.node_mut(id);
+            node.annotation = mv.annotation;
+            node.comment = mv.comment.clone();
+            for variation in &mv.variations {
+                self.add_line(parent, variation)?;
+            }
+            parent = id;
+        }
+        Ok(())
+    }
+
+    /// The whole tree as a game with variations, with the headers needed to replay it
+    pub fn to_pgn(&self) -> Game {
+        let mut game = Game::from_grid(&Grid::from_position(*self.grid.start()));
+        game.comment = self.get(Self::ROOT).comment.clone();
+        if let Some(&first) = self.get(Self::ROOT).children.first() {
+            game.moves = self.line(first, true);
+        }
+        game.result = self.grid_at(*self.main_line().last().unwrap()).status();
+        game
+    }
+
+    // The moves from `id` following the main continuations. The other children of each parent
+    // become variations, except for `id`'s own siblings unless `siblings` is set.
+    fn line(&self, mut id: NodeId, mut siblings: bool) -> Vec<MoveNode> {
+        let mut moves = Vec::new();
+        loop {
+            let node = self.get(id);
+            let mut mv = MoveNode::new(node.coords.unwrap());
+            mv.annotation = node.annotation;
+            mv.comment = node.comment.clone();
+            if siblings {
+                let parent = self.get(node.parent.unwrap());
+                for &sibling in &parent.children[1..] {
+                    mv.variations.push(self.line(sibling, false));
+                }
+            }
+            moves.push(mv);
+
+            match node.children.first() {
+                Some(&child) => {
+                    id = child;
+                    siblings = true;
+                }
+                None => return moves,
+            }
+        }
+    }
+
+    fn get(&self, id: NodeId) -> &Node {
+        self.nodes[id].as_ref().expect("node has been deleted")
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.nodes[id].as_mut().expect("node has been deleted")
+    }
+
+    /// `None` if there is no node `id` (any more)
+    pub fn node(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id).and_then(Option::as_ref)
+    }
+
+    pub fn cursor(&self) -> NodeId {
+        self.cursor
+    }
+
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    /// The nodes from the root to `id`, both included
+    pub fn path(&self, id: NodeId) -> Vec<NodeId> {
+        let mut path = vec![id];
+        while let Some(parent) = self.get(*path.last().unwrap()).parent {
+            path.push(parent);
+        }
+        path.reverse();
+        path
+    }
+
+    /// The nodes from the root following the main continuations
+    pub fn main_line(&self) -> Vec<NodeId> {
+        let mut line = vec![Self::ROOT];
+        while let Some(&child) = self.get(*line.last().unwrap()).children.first() {
+            line.push(child);
+        }
+        line
+    }
+
+    /// The game at node `id`
+    pub fn grid_at(&self, id: NodeId) -> Grid {
+        let mut grid = Grid::from_position(*self.grid.start());
+        for node in &self.path(id)[1..] {
+            grid.apply_turn(self.get(*node).coords.unwrap())
+                .expect("moves in the tree are legal");
+        }
+        grid
+    }
+
+    /// Plays `coords` at the cursor and moves the cursor to it, following the existing node if
+    /// that move has been played here before or adding a new variation otherwise
+    pub fn play(&mut self, coords: (Direction, Direction)) -> Result<NodeId, UT3Error> {
+        let existing = self
+            .get(self.cursor)
+            .children
+            .iter()
+            .find(|&&child| self.get(child).coords == Some(coords))
+            .copied();
+
+        self.grid.apply_turn(coords)?;
+        let id = match existing {
+            Some(id) => id,
+            None => {
+                let id = self.nodes.len();
+                self.nodes.push(Some(Node {
+                    coords: Some(coords),
+                    turn_number: self.get(self.cursor).turn_number + 1,
+                    parent: Some(self.cursor),
+                    children: Vec::new(),
+                    annotation: None,
+                    comment: None,
+                }));
+                let cursor = self.cursor;
+                self.node_mut(cursor).children.push(id);
+                id
+            }
+        };
+        self.cursor = id;
+        Ok(id)
+    }
+
+    /// Moves the cursor along the main continuation. Returns whether there was one.
+    pub fn forward(&mut self) -> bool {
+        match self.get(self.cursor).children.first() {
+            Some(&child) => {
+                self.grid
+                    .apply_turn(self.get(child).coords.unwrap())
+                    .expect("moves in the tree are legal");
+                self.cursor = child;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the cursor to the previous move. Returns whether there was one.
+    pub fn back(&mut self) -> bool {
+        match self.get(self.cursor).parent {
+            Some(parent) => {
+                self.grid.undo_turn();
+                self.cursor = parent;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Moves the cursor to `id`. Returns whether there is such a node.
+    pub fn jump(&mut self, id: NodeId) -> bool {
+        if self.node(id).is_none() {
+            return false;
+        }
+        if id != self.cursor {
+            self.grid = self.grid_at(id);
+            self.cursor = id;
+        }
+        true
+    }
+
+    /// Annotates the move at `id`, or clears its annotation with `None`. Returns whether there is
+    /// such a node.
+    pub fn set_annotation(&mut self, id: NodeId, annotation: Option<Annotation>) -> bool {
+        match self.nodes.get_mut(id).and_then(Option::as_mut) {
+            Some(node) => {
+                node.annotation = annotation;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Comments on the move at `id`, or on the starting position for the root. Returns whether
+    /// there is such a node.
+    pub fn set_comment(&mut self, id: NodeId, comment: Option<String>) -> bool {
+        match self.nodes.get_mut(id).and_then(Option::as_mut) {
+            Some(node) => {
+                node.comment = comment;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Makes the variation `id` is in the main continuation where it branches off, moving the
+    /// previous main continuation to be the first variation. Returns whether anything changed.
+    pub fn promote(&mut self, id: NodeId) -> bool {
+        let path = self.path(id);
+        for pair in path.windows(2).rev() {
+            let (parent, child) = (pair[0], pair[1]);
+            let children = &mut self.node_mut(parent).children;
+            let idx = children.iter().position(|&c| c == child).unwrap();
+            if idx != 0 {
+                let child = children.remove(idx);
+                children.insert(0, child);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Removes `id` and everything played after it. The cursor moves back to `id`'s parent if it
+    /// was on one of them. Returns whether anything was deleted, which it can't be for the root.
+    pub fn delete(&mut self, id: NodeId) -> bool {
+        let parent = match self.node(id).and_then(Node::parent) {
+            Some(parent) => parent,
+            None => return false,
+        };
+        if self.path(self.cursor).contains(&id) {
+            self.jump(parent);
+        }
+
+        self.node_mut(parent).children.retain(|&child| child != id);
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            stack.extend(self.get(id).children.iter().copied());
+            self.nodes[id] = None;
+        }
+        true
+    }
+}
+
+impl Default for GameTree {
+    fn default() -> Self {
+        GameTree::new(Position::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use Direction::*;
+
+    // 1. C/C 2. C/N 3. N/C, with 2. C/S 3. S/C as a variation. Returns the ids of the main line
+    // and of the variation.
+    fn tree() -> (GameTree, Vec<NodeId>, Vec<NodeId>) {
+        let mut tree = GameTree::default();
+        let main = [(C, C), (C, N), (N, C)]
+            .map(|coords| tree.play(coords).unwrap())
+            .to_vec();
+        tree.jump(main[0]);
+        let variation = [(C, S), (S, C)]
+            .map(|coords| tree.play(coords).unwrap())
+            .to_vec();
+        (tree, main, variation)
+    }
+
+    #[test]
+    fn play_adds_variations_and_follows_existing_moves() {
+        let (mut tree, main, variation) = tree();
+        assert_eq!(
+            tree.main_line(),
+            [GameTree::ROOT, main[0], main[1], main[2]]
+        );
+        assert_eq!(
+            tree.node(main[0]).unwrap().children(),
+            [main[1], variation[0]]
+        );
+        assert_eq!(tree.node(variation[1]).unwrap().turn_number(), 3);
+
+        tree.jump(main[0]);
+        assert_eq!(tree.play((C, N)).unwrap(), main[1]);
+        assert_eq!(tree.node(main[0]).unwrap().children().len(), 2);
+
+        assert!(tree.play((C, C)).is_err());
+        assert_eq!(tree.cursor(), main[1]);
+        assert_eq!(tree.grid().current_turn_number, 3);
+    }
+
+    #[test]
+    fn jump_sets_the_grid() {
+        let (mut tree, main, variation) = tree();
+        assert_eq!(tree.cursor(), variation[1]);
+        assert!(tree.jump(main[2]));
+        assert_eq!(tree.cursor(), main[2]);
+        assert_eq!(tree.grid().position(), tree.grid_at(main[2]).position());
+        assert_eq!(tree.grid().turns.len(), 3);
+
+        assert!(!tree.jump(100));
+        assert_eq!(tree.cursor(), main[2]);
+
+        assert!(tree.back());
+        assert!(tree.back());
+        assert_eq!(tree.cursor(), main[0]);
+        assert!(tree.forward());
+        assert_eq!(tree.cursor(), main[1]);
+        tree.jump(GameTree::ROOT);
+        assert!(!tree.back());
+        assert_eq!(*tree.grid().position(), Position::default());
+    }
+
+    #[test]
+    fn promote_a_variation() {
+        let (mut tree, main, variation) = tree();
+        assert!(tree.promote(variation[1]));
+        assert_eq!(
+            tree.main_line(),
+            [GameTree::ROOT, main[0], variation[0], variation[1]]
+        );
+        assert_eq!(
+            tree.node(main[0]).unwrap().children(),
+            [variation[0], main[1]]
+        );
+        assert!(!tree.promote(variation[1]));
+    }
+
+    #[test]
+    fn delete_the_node_under_the_cursor() {
+        let (mut tree, main, variation) = tree();
+        assert!(tree.delete(variation[0]));
+        assert_eq!(tree.cursor(), main[0]);
+        assert_eq!(tree.grid().turns.len(), 1);
+        assert!(tree.node(variation[0]).is_none());
+        assert!(tree.node(variation[1]).is_none());
+        assert_eq!(tree.node(main[0]).unwrap().children(), [main[1]]);
+
+        // The cursor stays put if it isn't in what is deleted
+        assert!(tree.delete(main[2]));
+        assert_eq!(tree.cursor(), main[0]);
+        assert!(!tree.delete(GameTree::ROOT));
+        assert!(!tree.delete(variation[0]));
+        assert!(!tree.set_comment(variation[0], Some("Gone".to_string())));
+        assert!(!tree.set_annotation(variation[1], None));
+    }
+
+    #[test]
+    fn pgn_round_trip() {
+        let (mut tree, main, variation) = tree();
+        assert!(tree.set_annotation(main[1], Some(Annotation::Dubious)));
+        assert!(tree.set_comment(variation[0], Some("Better".to_string())));
+        assert!(tree.set_comment(GameTree::ROOT, Some("Before the game".to_string())));
+
+        let game = tree.to_pgn();
+        let copy = GameTree::from_pgn(&game).unwrap();
+        assert_eq!(copy.to_pgn(), game);
+        assert_eq!(copy.cursor(), GameTree::ROOT);
+        assert_eq!(copy.main_line().len(), 4);
+        let first = copy.node(copy.main_line()[1]).unwrap();
+        let variation = copy.node(first.children()[1]).unwrap();
+        assert_eq!(variation.coords(), Some((C, S)));
+        assert_eq!(variation.comment.as_deref(), Some("Better"));
+    }
+}
@@ -0,0 +1,252 @@
+//! Texel tuning: adjusting `Heuristic`'s weights so that its scores, as chances of winning (see
+//! `eval::win_probability`), predict the results of finished games as well as possible.
+//!
+//! The error is the mean squared difference between the predicted and actual result over every
+//! position of the games. The search is the usual one: nudge each weight up and down by a step,
+//! keep whatever lowers the error, and halve the step once nothing does.
+
+use std::thread;
+
+use crate::bitboard::Position;
+use crate::error::UT3Error;
+use crate::eval::{win_probability, Evaluator, Heuristic, Weights, MAX_SCORE};
+use crate::game::{GameStatus, Grid, Player, RuleSet};
+use crate::pgn::Game;
+use crate::selfplay::Record;
+
+/// A position and how the game it came from ended
+#[derive(Copy, Clone, Debug)]
+pub struct TuningPosition {
+    pub position: Position,
+    /// The result for the side to move: 1 for a win, 0.5 for a draw and 0 for a loss
+    pub result: f64,
+}
+
+fn result_for(status: GameStatus, player: Player) -> f64 {
+    match status {
+        GameStatus::Won(winner) if winner == player => 1.0,
+        GameStatus::Won(_) => 0.0,
+        _ => 0.5,
+    }
+}
+
+/// The positions of `game`'s main line after the first `skip_plies`, leaving out where the game
+/// is already over. Games without a result give none.
+pub fn game_positions(game: &Game, skip_plies: usize) -> Result<Vec<TuningPosition>, UT3Error> {
+    if !game.result.is_over() {
+        return Ok(Vec::new());
+    }
+    let mut grid = Grid::from_position(game.start()?);
+    let mut positions = Vec::new();
+    for (ply, node) in game.moves.iter().enumerate() {
+        let position = *grid.position();
+        if ply >= skip_plies && !position.status().is_over() {
+            positions.push(TuningPosition {
+                position,
+                result: result_for(game.result, position.side_to_move()),
+            });
+        }
+        grid.apply_turn(node.coords)?;
+    }
+    Ok(positions)
+}
+
+impl From<&Record> for TuningPosition {
+    fn from(record: &Record) -> Self {
+        Self {
+            position: record.position,
+            result: (record.result as f64 + 1.0) / 2.0,
+        }
+    }
+}
+
+/// Keeps only the positions played under `rules`, since the best weights differ between them
+pub fn with_rules(positions: Vec<TuningPosition>, rules: RuleSet) -> Vec<TuningPosition> {
+    positions
+        .into_iter()
+        .filter(|tuning| tuning.position.rules() == rules)
+        .collect()
+}
+
+/// The mean squared error of `weights`' predictions over `positions`, split over `threads`
+pub fn error(weights: Weights, positions: &[TuningPosition], threads: usize) -> f64 {
+    if positions.is_empty() {
+        return 0.0;
+    }
+    let heuristic = Heuristic::new(weights);
+    let chunk_size = positions.len().div_ceil(threads.max(1));
+    let total = thread::scope(|scope| {
+        let handles = positions
+            .chunks(chunk_size)
+            .map(|chunk| {
+                let heuristic = &heuristic;
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|tuning| {
+                            let predicted = win_probability(heuristic.evaluate(&tuning.position));
+                            (predicted - tuning.result).powi(2)
+                        })
+                        .sum::<f64>()
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("the error calculation panicked"))
+            .sum::<f64>()
+    });
+    total / positions.len() as f64
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct TuneConfig {
+    /// How far each weight is nudged at first
+    pub step: i32,
+    /// At most this many passes over the weights
+    pub passes: u32,
+    pub threads: usize,
+}
+
+impl Default for TuneConfig {
+    fn default() -> Self {
+        Self {
+            step: 16,
+            passes: 200,
+            threads: 1,
+        }
+    }
+}
+
+/// Where the tuning is after a pass over the weights
+#[derive(Copy, Clone, Debug)]
+pub struct Progress {
+    pub pass: u32,
+    pub step: i32,
+    pub error: f64,
+    pub weights: Weights,
+}
+
+/// Tunes `weights` on `positions`, calling `progress` after every pass. Stops once a step of 1
+/// doesn't help, or after `config.passes`. The weights stay within `MAX_SCORE` either way, so they
+/// can be written to a weights file.
+pub fn tune(
+    weights: Weights,
+    positions: &[TuningPosition],
+    config: &TuneConfig,
+    mut progress: impl FnMut(&Progress),
+) -> Weights {
+    let mut best = weights.to_array();
+    let mut best_error = error(weights, positions, config.threads);
+    let mut step = config.step.max(1);
+
+    for pass in 1..=config.passes {
+        let mut improved = false;
+        for idx in 0..best.len() {
+            for delta in [step, -step] {
+                let mut candidate = best;
+                candidate[idx] = best[idx].saturating_add(delta).clamp(-MAX_SCORE, MAX_SCORE);
+                if candidate[idx] == best[idx] {
+                    continue;
+                }
+                let candidate_error =
+                    error(Weights::from_array(candidate), positions, config.threads);
+                if candidate_error < best_error {
+                    best = candidate;
+                    best_error = candidate_error;
+                    improved = true;
+                    break;
+                }
+            }
+        }
+
+        progress(&Progress {
+            pass,
+            step,
+            error: best_error,
+            weights: Weights::from_array(best),
+        });
+        if !improved {
+            if step == 1 {
+                break;
+            }
+            step /= 2;
+        }
+    }
+    Weights::from_array(best)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    // Every position of `count` random games that were finished
+    fn random_games(count: usize, rng: &mut Rng) -> Vec<TuningPosition> {
+        let mut positions = Vec::new();
+        for _ in 0..count {
+            let mut position = Position::new(RuleSet::CLASSIC);
+            let mut played = Vec::new();
+            while !position.status().is_over() {
+                played.push(position);
+                position.play(rng.choose(position.legal_moves()).unwrap());
+            }
+            positions.extend(played.into_iter().map(|played| TuningPosition {
+                position: played,
+                result: result_for(position.status(), played.side_to_move()),
+            }));
+        }
+        positions
+    }
+
+    #[test]
+    fn tuning_lowers_the_error() {
+        let positions = random_games(20, &mut Rng::new(5));
+        let weights = Weights::from_array(Weights::default().to_array().map(|weight| -weight));
+        let config = TuneConfig {
+            passes: 10,
+            threads: 2,
+            ..TuneConfig::default()
+        };
+
+        let mut errors = vec![error(weights, &positions, config.threads)];
+        let tuned = tune(weights, &positions, &config, |progress| {
+            assert_eq!(
+                progress.error,
+                error(progress.weights, &positions, config.threads)
+            );
+            errors.push(progress.error);
+        });
+        assert!(
+            errors.windows(2).all(|pair| pair[1] <= pair[0]),
+            "{errors:?}"
+        );
+        assert!(errors.last().unwrap() < &errors[0], "{errors:?}");
+        assert_eq!(
+            &error(tuned, &positions, config.threads),
+            errors.last().unwrap()
+        );
+    }
+
+    #[test]
+    fn weights_stay_within_max_score() {
+        let positions = random_games(5, &mut Rng::new(6));
+        let weights = Weights::from_array([MAX_SCORE - 3, -MAX_SCORE, 0, 0, 0, 0, 0, 0, 0]);
+        let config = TuneConfig {
+            step: 64,
+            passes: 4,
+            threads: 1,
+        };
+        let tuned = tune(weights, &positions, &config, |progress| {
+            assert!(progress
+                .weights
+                .to_array()
+                .iter()
+                .all(|w| w.abs() <= MAX_SCORE));
+        });
+        assert_eq!(
+            Weights::try_from(tuned.to_string().as_str()).unwrap(),
+            tuned
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use crate::bitboard::Position;
+use crate::game::{Direction, Player, RuleSet, Variant};
+
+// The keys come from a fixed seed, so hashes are the same across runs, platforms and builds and can
+// be stored in files.
+const SEED: u64 = 0x5554_3345_2d7a_6f62;
+
+pub(crate) const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    (state, z ^ (z >> 31))
+}
+
+pub struct Keys {
+    tiles: [[u64; 81]; 2],
+    box_winners: [[u64; 9]; 2],
+    side_to_move: u64,
+    // One key per box that can be forced, plus one for being able to play anywhere
+    track: [u64; 10],
+    variant: [u64; 2],
+    play_in_won_boxes: u64,
+    drawn_boxes_count_for_both: u64,
+    decide_draws_by_box_count: u64,
+}
+
+const fn make_keys() -> Keys {
+    let mut keys = Keys {
+        tiles: [[0; 81]; 2],
+        box_winners: [[0; 9]; 2],
+        side_to_move: 0,
+        track: [0; 10],
+        variant: [0; 2],
+        play_in_won_boxes: 0,
+        drawn_boxes_count_for_both: 0,
+        decide_draws_by_box_count: 0,
+    };
+    let mut state = SEED;
+    let mut key;
+
+    let mut player = 0;
+    while player < 2 {
+        let mut i = 0;
+        while i < 81 {
+            (state, key) = splitmix64(state);
+            keys.tiles[player][i] = key;
+            i += 1;
+        }
+        let mut i = 0;
+        while i < 9 {
+            (state, key) = splitmix64(state);
+            keys.box_winners[player][i] = key;
+            i += 1;
+        }
+        player += 1;
+    }
+
+    let mut i = 0;
+    while i < 10 {
+        (state, key) = splitmix64(state);
+        keys.track[i] = key;
+        i += 1;
+    }
+
+    (state, keys.side_to_move) = splitmix64(state);
+    (state, keys.variant[0]) = splitmix64(state);
+    (state, keys.variant[1]) = splitmix64(state);
+    (state, keys.play_in_won_boxes) = splitmix64(state);
+    (state, keys.drawn_boxes_count_for_both) = splitmix64(state);
+    (_, keys.decide_draws_by_box_count) = splitmix64(state);
+
+    keys
+}
+
+pub const KEYS: Keys = make_keys();
+
+impl Keys {
+    pub fn tile(&self, player: Player, idx: usize) -> u64 {
+        self.tiles[player.index()][idx]
+    }
+
+    pub fn box_winner(&self, player: Player, idx: Direction) -> u64 {
+        self.box_winners[player.index()][idx.index()]
+    }
+
+    /// Only O to move is hashed in, so the hash changes every turn
+    pub fn side_to_move(&self, player: Player) -> u64 {
+        match player {
+            Player::X => 0,
+            Player::O => self.side_to_move,
+        }
+    }
+
+    pub fn track(&self, track: Option<Direction>) -> u64 {
+        match track {
+            Some(dir) => self.track[dir.index()],
+            None => self.track[9],
+        }
+    }
+
+    /// Everything about the rules that affects how play continues. Who moved first doesn't,
+    /// given whose turn it is.
+    pub fn rules(&self, rules: RuleSet) -> u64 {
+        let mut key = match rules.variant {
+            Variant::Relative => self.variant[0],
+            Variant::Absolute => self.variant[1],
+        };
+        if rules.play_in_won_boxes {
+            key ^= self.play_in_won_boxes;
+        }
+        if rules.drawn_boxes_count_for_both {
+            key ^= self.drawn_boxes_count_for_both;
+        }
+        if rules.decide_draws_by_box_count {
+            key ^= self.decide_draws_by_box_count;
+        }
+        key
+    }
+}
+
+/// Hashes `position` from scratch. `Position::hash` is kept up to date incrementally and should
+/// always be equal to this.
+pub fn hash(position: &Position) -> u64 {
+    let mut hash = KEYS.rules(position.rules())
+        ^ KEYS.side_to_move(position.side_to_move())
+        ^ KEYS.track(position.track());
+
+    for player in [Player::X, Player::O] {
+        let mut tiles = position.tiles(player);
+        while tiles != 0 {
+            hash ^= KEYS.tile(player, tiles.trailing_zeros() as usize);
+            tiles &= tiles - 1;
+        }
+        for dir in Direction::ALL {
+            if position.box_winner(dir) == Some(player) {
+                hash ^= KEYS.box_winner(player, dir);
+            }
+        }
+    }
+
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    #[test]
+    fn incremental_hash_matches_recompute() {
+        let mut rng = Rng::new(31);
+        for (name, rules) in RuleSet::PRESETS {
+            for _ in 0..10 {
+                let mut position = Position::new(rules);
+                let mut played = Vec::new();
+                while let Some(mv) = rng.choose(position.legal_moves()) {
+                    let undo = position.play(mv);
+                    assert_eq!(position.hash(), hash(&position), "{name}: {position}");
+                    played.push((mv, undo));
+                }
+                while let Some((mv, undo)) = played.pop() {
+                    position.undo(mv, undo);
+                    assert_eq!(position.hash(), hash(&position), "{name}: {position}");
+                }
+                assert_eq!(position, Position::new(rules));
+            }
+        }
+    }
+
+    #[test]
+    fn hash_covers_side_track_and_rules() {
+        let hash_of = |string: &str| Position::try_from(string).unwrap().hash();
+        let tiles = "X......../........./........./O......../........./........./\
+                     ........./........./.........";
+
+        // Who went first isn't hashed, so this only differs in whose turn it is
+        assert_ne!(
+            hash_of(&format!("{tiles} ......... X NW absolute")),
+            hash_of(&format!("{tiles} ......... O NW absolute+o-first"))
+        );
+
+        let tracks = ["NW", "W", "C", "-"]
+            .map(|track| hash_of(&format!("{tiles} ......... X {track} absolute")));
+        let rules = [
+            "absolute",
+            "relative",
+            "absolute+open-boxes",
+            "absolute+wild-draws",
+            "absolute+box-count",
+            "relative+open-boxes+wild-draws+box-count",
+        ]
+        .map(|rules| hash_of(&format!("{tiles} ......... X NW {rules}")));
+        for hashes in [&tracks[..], &rules[..]] {
+            for (idx, hash) in hashes.iter().enumerate() {
+                assert!(!hashes[idx + 1..].contains(hash), "{hashes:?}");
+            }
+        }
+    }
+}